@@ -2,8 +2,8 @@
 tempfile=$(mktemp)
 echo "Using ${tempfile}"
 playground=~/Projects/FYP/src/playground/
-echo "Removing .autoparallelise"
-rm .autoparallelise
+echo "Removing saved analysis"
+rm -f .autoparallelise/*/state.json
 echo "Running Stage 1"
 RUST_BACKTRACE=full cargo build
 for i in {1..5}
@@ -2,6 +2,9 @@
 extern crate serde;
 #[macro_use] extern crate serde_derive;
 extern crate serde_json;
+extern crate toml;
+#[cfg(test)]
+extern crate sequential_program_generator;
 
 #[macro_use] extern crate rustc;
 extern crate syntax;
@@ -9,13 +12,14 @@ extern crate syntax_pos;
 extern crate serialize;
 extern crate rustc_plugin;
 
+use rustc::session::Session;
 use rustc_plugin::Registry;
 use syntax::ext::base::SyntaxExtension::{MultiModifier};
 use syntax::symbol::Symbol;
 
 use std::fs::{self, File};
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[macro_use] mod utils;
 mod parallel_stages;
@@ -23,15 +27,29 @@ mod plugin;
 mod rendering;
 mod tests;
 
+use plugin::config;
 use plugin::shared_state::*;
+use utils::logging;
 
-static SAVE_FILE: &'static str = ".autoparallelise";
+// Found in the manifest directory of the crate
+static TOML_CONFIG_FILE: &'static str = "autoparallelise.toml";
 static CONFIG_FILE: &'static str = "autoparallelise.config";
+static PROFILE_FILE: &'static str = "autoparallelise.profile";
+// Every crate and target has its own directory in STATE_DIR, see AutoParallelise::state_path
+static STATE_DIR: &'static str = ".autoparallelise";
+static SAVE_FILE: &'static str = "state.json";
+static SAVE_FILE_VERSION: u32 = 2;
+static STALE_FILE: &'static str = "stale";
+static CACHE_FILE: &'static str = "cache.json";
+static REPORT_FILE: &'static str = "report.json";
+static HTML_SECTIONS_FILE: &'static str = "html-sections.json";
+static HTML_REPORT_FILE: &'static str = "report.html";
 
 #[plugin_registrar]
 pub fn plugin_registrar(reg: &mut Registry) {
     // Try to load AutoParallelise
-    let mut obj = AutoParallelise::load();
+    let mut obj = AutoParallelise::load(reg.sess);
+    logging::init(&obj.config.log);
     let stage = match obj.compiler_stage {
         CompilerStage::Analysis => 1,
         CompilerStage::Modification => 2,
@@ -40,9 +58,9 @@ pub fn plugin_registrar(reg: &mut Registry) {
     if obj.config.plugin_enabled {
         obj.config.plugin_enabled = reg.args().len() == 0;
     }
-    eprintln!("[auto_parallelise] Stage {} of 2 - {:?}", stage, obj.compiler_stage);
+    log_info!(logging::PLUGIN, "Stage {} of 2 - {:?}", stage, obj.compiler_stage);
     if !obj.config.plugin_enabled {
-        eprintln!("[auto_parallelise] Plugin Disabled")
+        log_info!(logging::PLUGIN, "Plugin Disabled")
     }
     // Second pass uses the syntax extension
     reg.register_syntax_extension(Symbol::intern("autoparallelise"), MultiModifier(Box::new(obj.clone())));
@@ -51,49 +69,93 @@ pub fn plugin_registrar(reg: &mut Registry) {
     reg.register_early_lint_pass(Box::new(obj));
 }
 
+// The crates of a workspace, and the targets of a crate, are compiled separately so each needs its own state
+fn crate_key(sess: &Session) -> String {
+    let crate_name = match sess.opts.crate_name {
+        Some(ref crate_name) => crate_name.clone(),
+        None => "main".to_owned(),
+    };
+    let crate_types: Vec<String> = sess.opts.crate_types.iter().map(|crate_type| crate_type.to_string()).collect();
+    let mut key = format!("{}-{}", crate_name, crate_types.join("-"));
+    if sess.opts.test {
+        key.push_str("-test");
+    }
+    key
+}
+
 impl AutoParallelise {
-    fn new() -> Self {
+    fn new(crate_key: &str) -> Self {
         AutoParallelise {
+            version: SAVE_FILE_VERSION,
+            crate_name: crate_key.to_owned(),
+            state_dir: utils::manifest_dir().join(STATE_DIR).join(crate_key).to_string_lossy().into_owned(),
             compiler_stage: CompilerStage::Analysis,
             linter_level: 0,
+            module_path: vec![],
             functions: vec![],
             config: Config::default(),
         }
     }
 
-    pub fn load() -> Self {
-        let mconfig = utils::read_file(CONFIG_FILE);
-        let mobj = utils::read_file(SAVE_FILE);
-
-        // Extract config if it exists otherwise use default
-        let config = match mconfig {
-            Some(ref json) => match serde_json::from_str(json) {
-                Ok(config) => config,
-                Err(why) => panic!("Unable to parse {} as json: {}", CONFIG_FILE, why),
+    pub fn load(sess: &Session) -> Self {
+        let crate_key: &str = &crate_key(sess);
+        // Older versions saved the state in a single file with the name of the state directory
+        let legacy_path = utils::manifest_dir().join(STATE_DIR);
+        if legacy_path.is_file() {
+            let _ = fs::remove_file(&legacy_path);
+        }
+        let new_obj = AutoParallelise::new(crate_key);
+        let save_path = new_obj.state_path(SAVE_FILE);
+        let mobj = utils::read_file(&save_path.to_string_lossy());
+
+        // Extract config if it exists otherwise use default. An invalid config fails the build, but
+        // the default is used until then so the rest of the crate is still checked.
+        let config = match config::load_config(&utils::manifest_dir()) {
+            Ok(config) => config,
+            Err(why) => {
+                sess.err(&why);
+                Config::default()
             },
-            None => Config::default(),
         };
 
         // Try to convert it the string to an AutoParallelise object
+        // A save file which cannot be trusted is ignored, so every function is analysed again
         let mut obj : AutoParallelise = match mobj {
-            Some(ref json) => match serde_json::from_str(json) {
+            Some(ref json) => match serde_json::from_str::<AutoParallelise>(json) {
+                Ok(ref obj) if obj.version != SAVE_FILE_VERSION => {
+                    log_warn!(logging::PLUGIN, "{} has format version {} instead of {}, analysing again", save_path.display(), obj.version, SAVE_FILE_VERSION);
+                    new_obj.clone()
+                },
+                Ok(ref obj) if obj.crate_name != crate_key => {
+                    log_warn!(logging::PLUGIN, "{} was left by crate {:?}, analysing again", save_path.display(), obj.crate_name);
+                    new_obj.clone()
+                },
                 Ok(obj) => obj,
-                Err(why) => panic!("Unable to parse {} as json: {}", SAVE_FILE, why),
+                Err(why) => {
+                    log_warn!(logging::PLUGIN, "Unable to parse {} as json, analysing again: {}", save_path.display(), why);
+                    new_obj.clone()
+                },
             },
-            None => AutoParallelise::new(),
+            None => new_obj.clone(),
         };
 
         obj.config = config;
+        obj.state_dir = new_obj.state_dir;
         obj.linter_level = 0;
+        obj.module_path = vec![];
         obj
     }
 
     pub fn save(&mut self) {
         // Save it so that modification happens next
+        self.save_for_stage(CompilerStage::Modification);
+    }
+
+    pub fn save_for_stage(&mut self, next_stage: CompilerStage) {
         let stage = self.compiler_stage;
-        self.compiler_stage = CompilerStage::Modification;
+        self.compiler_stage = next_stage;
 
-        let path = Path::new(SAVE_FILE);
+        let path = self.state_path(SAVE_FILE);
 
         // Try to convert the object to json
         let obj_json = match serde_json::to_string_pretty(&self) {
@@ -108,9 +170,49 @@ impl AutoParallelise {
     }
 
     pub fn delete(&self) {
-        match fs::remove_file(SAVE_FILE) {
+        let path = self.state_path(SAVE_FILE);
+        match fs::remove_file(&path) {
             Ok(_) => {},
-            Err(why) => panic!("Failed to delete {}: {}", SAVE_FILE, why),
+            Err(why) => panic!("Failed to delete {}: {}", path.display(), why),
+        }
+    }
+
+    // Files kept between the stages are in the directory for this crate and target
+    pub fn state_path(&self, file: &str) -> PathBuf {
+        let state_dir = Path::new(&self.state_dir);
+        if let Err(why) = fs::create_dir_all(state_dir) {
+            panic!("Unable to create {}: {}", state_dir.display(), why);
+        }
+        state_dir.join(file)
+    }
+
+    // Graphs and reports for this crate and target, if the config asks for them
+    pub fn output_dir(&self) -> Option<PathBuf> {
+        match self.config.output_dir {
+            Some(ref output_dir) => {
+                let output_dir = utils::manifest_dir().join(output_dir).join(&self.crate_name);
+                if let Err(why) = fs::create_dir_all(&output_dir) {
+                    panic!("Unable to create {}: {}", output_dir.display(), why);
+                }
+                Some(output_dir)
+            },
+            None => None,
+        }
+    }
+
+    // Functions that changed after they were analysed are left unchanged, and analysed again on the next build
+    pub fn mark_stale(&self, func_name: &str) {
+        let mut stale = self.load_stale();
+        if !stale.contains(&func_name.to_owned()) {
+            stale.push(func_name.to_owned());
+        }
+        utils::write_file(&self.state_path(STALE_FILE), &stale.join("\n"));
+    }
+
+    pub fn load_stale(&self) -> Vec<String> {
+        match utils::read_file(&self.state_path(STALE_FILE).to_string_lossy()) {
+            Some(contents) => contents.lines().map(|line| line.to_owned()).collect(),
+            None => vec![],
         }
     }
 }
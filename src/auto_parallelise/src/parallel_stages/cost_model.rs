@@ -0,0 +1,150 @@
+use syntax::ast::{Expr, ExprKind, LitKind, Mac, RangeLimits, Stmt, StmtKind};
+use syntax::visit::{self, Visitor};
+use std::collections::BTreeMap;
+
+use parallel_stages::dependency_analysis::DependencyNode;
+use parallel_stages::profiler::Profile;
+
+// Rough estimates of how much work is done, in units of a simple statement
+pub const STMT_COST: u32 = 1;
+pub const CALL_COST: u32 = 5;
+pub const THREAD_COST: u32 = 100; // Spawning and joining a thread
+pub const CHANNEL_COST: u32 = 10; // Sending a variable along a syncline
+pub const NANOS_PER_COST: u64 = 50; // Converts profiled times into cost units
+const UNKNOWN_TRIPS: u32 = 10; // Iterations of a loop whose trip count cannot be found
+const MAX_TRIPS: u32 = 1_000_000;
+
+// Uses the measured cost of the statement when it has been profiled
+pub fn node_cost(node: &DependencyNode, profile: &Profile) -> u32 {
+    match profile.stmt_costs.get(&node.get_stmtid()) {
+        Some(&cost) => cost,
+        None => estimate_cost(node, &profile.call_costs),
+    }
+}
+
+// Calls are CALL_COST unless call_costs has a cost for the name of the function or method
+pub fn estimate_cost(node: &DependencyNode, call_costs: &BTreeMap<String, u32>) -> u32 {
+    match node {
+        &DependencyNode::Expr(ref stmt, _, _) => STMT_COST.saturating_add(stmt_call_cost(stmt, call_costs)),
+        // Most macros print, so count them as a call
+        &DependencyNode::Mac(_, _, _) => STMT_COST + CALL_COST,
+        &DependencyNode::Block(_, ref nodes, _, _) => STMT_COST.saturating_add(nodes_cost(nodes, call_costs)),
+        &DependencyNode::ExprBlock(ref stmt, ref nodes, _, _) => {
+            // The statement has had its blocks removed, so this is only the header
+            let header_cost = STMT_COST.saturating_add(stmt_call_cost(stmt, call_costs));
+            header_cost.saturating_add(loop_trips(stmt).saturating_mul(nodes_cost(nodes, call_costs)))
+        },
+    }
+}
+
+fn nodes_cost(nodes: &Vec<DependencyNode>, call_costs: &BTreeMap<String, u32>) -> u32 {
+    nodes.iter().fold(0, |total, node| total.saturating_add(estimate_cost(node, call_costs)))
+}
+
+// Work done by a loop statement whose body does body_work each iteration
+pub fn loop_work(stmt: &Stmt, body_work: u32) -> u32 {
+    loop_trips(stmt).saturating_mul(body_work)
+}
+
+pub fn is_loop(stmt: &Stmt) -> bool {
+    match stmt_expr(stmt) {
+        Some(expr) => match expr.node {
+            ExprKind::ForLoop(_, _, _, _) |
+            ExprKind::While(_, _, _) |
+            ExprKind::WhileLet(_, _, _, _) |
+            ExprKind::Loop(_, _) => true,
+            _ => false,
+        },
+        None => false,
+    }
+}
+
+fn stmt_expr(stmt: &Stmt) -> Option<&Expr> {
+    match stmt.node {
+        StmtKind::Local(ref local) => local.init.as_ref().map(|expr| &**expr),
+        StmtKind::Expr(ref expr) |
+        StmtKind::Semi(ref expr) => Some(&**expr),
+        _ => None,
+    }
+}
+
+// How many times the blocks of the statement are run
+fn loop_trips(stmt: &Stmt) -> u32 {
+    let expr = match stmt_expr(stmt) {
+        Some(expr) => expr,
+        None => return 1,
+    };
+    match expr.node {
+        ExprKind::ForLoop(_, ref iter_expr, _, _) => range_trips(iter_expr).unwrap_or(UNKNOWN_TRIPS),
+        ExprKind::While(_, _, _) |
+        ExprKind::WhileLet(_, _, _, _) |
+        ExprKind::Loop(_, _) => UNKNOWN_TRIPS,
+        _ => 1,
+    }
+}
+
+// Trip count of a range between two integer literals
+fn range_trips(expr: &Expr) -> Option<u32> {
+    match expr.node {
+        ExprKind::Paren(ref expr1) => range_trips(expr1),
+        ExprKind::Range(Some(ref start), Some(ref end), ref limits) => {
+            let (start, end) = (int_lit(start)?, int_lit(end)?);
+            let end = if let &RangeLimits::Closed = limits { end + 1 } else { end };
+            let trips = end.saturating_sub(start);
+            Some(::std::cmp::min(trips, MAX_TRIPS as u128) as u32)
+        },
+        _ => None,
+    }
+}
+
+fn int_lit(expr: &Expr) -> Option<u128> {
+    if let ExprKind::Lit(ref lit) = expr.node {
+        if let LitKind::Int(value, _) = lit.node {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn stmt_call_cost(stmt: &Stmt, call_costs: &BTreeMap<String, u32>) -> u32 {
+    let mut visitor = CallVisitor { cost: 0, call_costs: call_costs };
+    visitor.visit_stmt(stmt);
+    visitor.cost
+}
+
+struct CallVisitor<'b> {
+    cost: u32,
+    call_costs: &'b BTreeMap<String, u32>,
+}
+
+impl<'b> CallVisitor<'b> {
+    fn call_cost(&self, name: &str) -> u32 {
+        self.call_costs.get(name).cloned().unwrap_or(CALL_COST)
+    }
+}
+
+impl<'a, 'b> Visitor<'a> for CallVisitor<'b> {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        match expr.node {
+            ExprKind::Call(ref func, _) => {
+                let cost = match func.node {
+                    ExprKind::Path(_, ref path) => match path.segments.last() {
+                        Some(segment) => self.call_cost(&*segment.identifier.name.as_str()),
+                        None => CALL_COST,
+                    },
+                    _ => CALL_COST,
+                };
+                self.cost = self.cost.saturating_add(cost);
+            },
+            ExprKind::MethodCall(ref segment, _) => {
+                self.cost = self.cost.saturating_add(self.call_cost(&*segment.identifier.name.as_str()));
+            },
+            _ => {},
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_mac(&mut self, _mac: &'a Mac) {
+        self.cost = self.cost.saturating_add(CALL_COST);
+    }
+}
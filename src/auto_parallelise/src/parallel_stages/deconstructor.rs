@@ -1,11 +1,14 @@
-use syntax::ast::{Block, Expr, ExprKind, Stmt, StmtKind, Path, PatKind, PathSegment};
+use syntax::ast::{Block, Expr, ExprKind, Mac, Mutability, Stmt, StmtKind, Path, PatKind, PathSegment};
 use syntax::ptr::P;
+use syntax::visit::{self, Visitor};
 use std::ops::Deref;
 use std::vec;
 use syntax::print::pprust;
 use syntax_pos::Span;
 
+use parallel_stages::fallback;
 use parallel_stages::dependency_analysis::{analyse_block_with_env, DependencyNode, DependencyTree, Environment, InOutEnvironment, PathName};
+use utils::logging;
 
 fn empty_block(block: &Block) -> P<Block> {
     P(Block {
@@ -283,19 +286,19 @@ pub fn check_stmt(deptree: &mut DependencyTree, stmt: &Stmt) -> InOutEnvironment
     match stmt.node {
         // A local let ?
         StmtKind::Local(ref local) => {
+            let ident = match local.pat.deref().node {
+                PatKind::Ident(_, ref ident, _) => ident.node,
+                _ => fallback::give_up("it has a let statement which does not bind a single variable".to_owned(), vec![
+                    (stmtID!(stmt), format!("binds `{}`", pprust::pat_to_string(&local.pat))),
+                ]),
+            };
             if let Some(ref expr) = local.init {
                 // Check expression
                 let mut subtree = vec![];
                 let (mut inenv, mut outenv) = check_expr(&mut subtree, &expr.deref());
 
                 // Add current variable name as part of the environment
-                if let PatKind::Ident(ref mode, ref ident, ref mpat) = local.pat.deref().node {
-                    // TODO: Use other two unused variables
-                    outenv.push(vec![ident.node]);
-                } else {
-                    // Managed to get something other than Ident
-                    panic!("local.pat: {:?}", local.pat);
-                }
+                outenv.push(vec![ident]);
 
                 // Add Expr or ExprBlock into dependency tree
                 if subtree.len() == 0 {
@@ -312,13 +315,10 @@ pub fn check_stmt(deptree: &mut DependencyTree, stmt: &Stmt) -> InOutEnvironment
                 }
                 (inenv, outenv)
             } else {
-                // Add current variable name as part of the environment
-                if let PatKind::Ident(ref mode, ref ident, ref mpat) = local.pat.deref().node {
-                    (Environment::empty(), Environment::new(vec![vec![ident.node]]))
-                } else {
-                    // Managed to get something other than Ident
-                    panic!("local.pat: {:?}", local.pat);
-                }
+                // Declares the variable for the statements which assign to it, without depending on anything
+                let outenv = Environment::new(vec![vec![ident]]);
+                deptree.push(DependencyNode::Expr(P(stmt.clone()), vec![], (Environment::empty(), outenv.clone())));
+                (Environment::empty(), outenv)
             }
         },
 
@@ -344,8 +344,10 @@ pub fn check_stmt(deptree: &mut DependencyTree, stmt: &Stmt) -> InOutEnvironment
             (inenv, outenv)
         },
 
+        // Items do not use the variables of the function, see spawn_from_schedule_helper for where they are placed
         StmtKind::Item(ref item) => {
-            eprintln!("ITEM: {:?}", item);
+            log_trace!(logging::ANALYSIS, "ITEM: {:?}", item);
+            deptree.push(DependencyNode::Expr(P(stmt.clone()), vec![], (Environment::empty(), Environment::empty())));
             (Environment::empty(), Environment::empty())
         },
 
@@ -672,3 +674,136 @@ pub fn check_pattern(sub_blocks: &mut DependencyTree, patkind: &PatKind) -> Envi
 
     env
 }
+
+// Only the variables a pattern binds, unlike check_pattern which also has the paths it matches against,
+// such as Some in Some(x)
+pub fn pattern_bindings(patkind: &PatKind) -> Environment {
+    let mut env = Environment::empty();
+    match patkind {
+        &PatKind::Ident(_, ref spanident, ref mpat) => {
+            env.push(vec![spanident.node]);
+            if let &Some(ref pat) = mpat {
+                env.merge(pattern_bindings(&pat.node));
+            }
+        },
+        &PatKind::Struct(_, ref fieldpats, _) => {
+            for fieldpat in fieldpats {
+                env.merge(pattern_bindings(&fieldpat.node.pat.node));
+            }
+        },
+        &PatKind::TupleStruct(_, ref pats, _) |
+        &PatKind::Tuple(ref pats, _) => {
+            for pat in pats {
+                env.merge(pattern_bindings(&pat.node));
+            }
+        },
+        &PatKind::Box(ref pat) |
+        &PatKind::Ref(ref pat, _) => env.merge(pattern_bindings(&pat.node)),
+        &PatKind::Slice(ref pats1, ref mpat, ref pats2) => {
+            for pat in pats1.iter().chain(mpat.iter()).chain(pats2.iter()) {
+                env.merge(pattern_bindings(&pat.node));
+            }
+        },
+        _ => {},
+    }
+    env
+}
+
+// Methods known to only read their receiver. Any other method may take it by &mut self, such as the
+// methods of the crate's own types, so is treated as a write.
+static READ_ONLY_METHODS: &'static [&'static str] = &[
+    "as_bytes", "as_slice", "as_str", "binary_search", "bytes", "chars", "chunks", "clone", "cmp",
+    "contains", "contains_key", "ends_with", "eq", "find", "first", "get", "is_empty", "iter", "keys",
+    "last", "len", "lines", "ne", "partial_cmp", "split", "split_whitespace", "starts_with", "to_lowercase",
+    "to_owned", "to_string", "to_uppercase", "to_vec", "trim", "values", "windows",
+];
+
+pub fn method_may_mutate(method: &str) -> bool {
+    !READ_ONLY_METHODS.contains(&method)
+}
+
+// Variables which the nodes assign to, mutably borrow or call a method that may mutate on. The outenv
+// of a node is no use for this, as it also holds every variable the node reads.
+pub fn written_variables(nodes: &Vec<&DependencyNode>) -> Environment {
+    let mut written = Environment::empty();
+    for node in nodes {
+        collect_written(node, &mut written);
+    }
+    written
+}
+
+fn collect_written(node: &DependencyNode, written: &mut Environment) {
+    match node {
+        &DependencyNode::Expr(ref stmt, _, _) |
+        &DependencyNode::ExprBlock(ref stmt, _, _, _) => {
+            let mut visitor = WriteVisitor { written: Environment::empty(), saw_mac: false };
+            visitor.visit_stmt(stmt.deref());
+            written.merge(visitor.written);
+            if visitor.saw_mac {
+                // Cannot see inside the macro, so anything it uses may be written
+                let &(ref inenv, ref outenv) = node.get_env();
+                written.merge(inenv.clone());
+                written.merge(outenv.clone());
+            }
+        },
+        &DependencyNode::Mac(_, _, (ref inenv, ref outenv)) => {
+            written.merge(inenv.clone());
+            written.merge(outenv.clone());
+        },
+        &DependencyNode::Block(_, _, _, _) => {},
+    }
+    match node {
+        &DependencyNode::ExprBlock(_, ref subtree, _, _) |
+        &DependencyNode::Block(_, ref subtree, _, _) => {
+            for subnode in subtree {
+                collect_written(subnode, written);
+            }
+        },
+        _ => {},
+    }
+}
+
+struct WriteVisitor {
+    written: Environment,
+    saw_mac: bool,
+}
+
+impl WriteVisitor {
+    // Writing to part of a variable, as in x[i] = e, x.field = e or *x = e, writes to x
+    fn write_place(&mut self, expr: &Expr) {
+        match expr.node {
+            ExprKind::Paren(ref expr1) |
+            ExprKind::Unary(_, ref expr1) |
+            ExprKind::Field(ref expr1, _) |
+            ExprKind::TupField(ref expr1, _) |
+            ExprKind::Index(ref expr1, _) => self.write_place(expr1),
+            ExprKind::Path(None, ref path) => {
+                if let Some(pathname) = read_path(path) {
+                    self.written.push(pathname);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for WriteVisitor {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        match expr.node {
+            ExprKind::Assign(ref lhs, _) |
+            ExprKind::AssignOp(_, ref lhs, _) |
+            ExprKind::AddrOf(Mutability::Mutable, ref lhs) => self.write_place(lhs),
+            ExprKind::MethodCall(ref segment, ref exprl) => {
+                if method_may_mutate(&*segment.identifier.name.as_str()) {
+                    self.write_place(&exprl[0]);
+                }
+            },
+            _ => {},
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_mac(&mut self, _mac: &'a Mac) {
+        self.saw_mac = true;
+    }
+}
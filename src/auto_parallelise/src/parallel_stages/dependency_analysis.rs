@@ -1,5 +1,7 @@
 use syntax::ast::{Block, Stmt, Ident};
+use syntax::codemap::CodeMap;
 use syntax::ptr::P;
+use syntax_pos::{BytePos, Span, NO_EXPANSION};
 use std::ops::Deref;
 use serde::ser::{Serialize, Serializer, SerializeStruct, SerializeSeq};
 use std::{vec, iter};
@@ -7,8 +9,9 @@ use syntax::print::pprust;
 use syntax_pos::symbol::Symbol;
 use syntax_pos::hygiene::Mark;
 
-use parallel_stages::deconstructor;
-use plugin::shared_state::{EncodedDependencyNode, EncodedDependencyTree, EncodedEnvironment};
+use utils;
+use parallel_stages::{deconstructor, fallback};
+use plugin::shared_state::{EncodedDependencyNode, EncodedDependencyTree, EncodedEnvironment, StableID};
 
 pub type PathName = Vec<Ident>;
 pub type StmtID = (u32, u32);
@@ -254,121 +257,138 @@ impl DependencyNode {
         }
     }
 
-    pub fn encode(&self) -> EncodedDependencyNode {
+    pub fn encode(&self, path: Vec<usize>, codemap: &CodeMap) -> EncodedDependencyNode {
         let &(ref inenv, ref outenv) = self.get_env();
         let encoded_inoutenv = (inenv.into_depstr(), outenv.into_depstr());
+        let stable_id = StableID {
+            hash: content_hash(codemap, &self.get_stmtid()),
+            path: path.clone(),
+        };
         match self {
-            &DependencyNode::Expr(_, ref deps, ref env) => {
-                EncodedDependencyNode::Expr(self.get_stmtid(), deps.clone(), encoded_inoutenv)
+            &DependencyNode::Expr(_, ref deps, _) => {
+                EncodedDependencyNode::Expr(stable_id, deps.clone(), encoded_inoutenv)
             },
-            &DependencyNode::Block(_, ref subdeptree, ref deps, ref env) => {
-                let encoded_subdeptree = encode_deptree(subdeptree);
-                EncodedDependencyNode::Block(self.get_stmtid(), encoded_subdeptree, deps.clone(), encoded_inoutenv)
+            &DependencyNode::Block(_, ref subdeptree, ref deps, _) => {
+                let encoded_subdeptree = encode_deptree_helper(subdeptree, &path, codemap);
+                EncodedDependencyNode::Block(stable_id, encoded_subdeptree, deps.clone(), encoded_inoutenv)
             },
-            &DependencyNode::ExprBlock(_, ref subdeptree, ref deps, ref env) => {
-                let encoded_subdeptree = encode_deptree(subdeptree);
-                EncodedDependencyNode::ExprBlock(self.get_stmtid(), encoded_subdeptree, deps.clone(), encoded_inoutenv)
+            &DependencyNode::ExprBlock(_, ref subdeptree, ref deps, _) => {
+                let encoded_subdeptree = encode_deptree_helper(subdeptree, &path, codemap);
+                EncodedDependencyNode::ExprBlock(stable_id, encoded_subdeptree, deps.clone(), encoded_inoutenv)
             },
-            &DependencyNode::Mac(_, ref deps, ref env) => EncodedDependencyNode::Mac(self.get_stmtid(), deps.clone(), encoded_inoutenv)
+            &DependencyNode::Mac(_, ref deps, _) => EncodedDependencyNode::Mac(stable_id, deps.clone(), encoded_inoutenv)
         }
     }
 }
 
-pub fn encode_deptree(deptree: &DependencyTree) -> EncodedDependencyTree {
+// Hash of the source of a statement, so edits between the stages are noticed even when no spans move
+fn content_hash(codemap: &CodeMap, stmtid: &StmtID) -> u64 {
+    let &(lo, hi) = stmtid;
+    utils::source_hash(codemap, Span::new(BytePos(lo), BytePos(hi), NO_EXPANSION))
+}
+
+fn path_to_string(path: &Vec<usize>) -> String {
+    path.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(".")
+}
+
+pub fn encode_deptree(deptree: &DependencyTree, codemap: &CodeMap) -> EncodedDependencyTree {
+    encode_deptree_helper(deptree, &vec![], codemap)
+}
+
+fn encode_deptree_helper(deptree: &DependencyTree, parent_path: &Vec<usize>, codemap: &CodeMap) -> EncodedDependencyTree {
     let mut encoded_deptree = vec![];
-    for node in deptree {
-        encoded_deptree.push(node.encode());
+    for (i, node) in deptree.iter().enumerate() {
+        let mut path = parent_path.clone();
+        path.push(i);
+        encoded_deptree.push(node.encode(path, codemap));
     }
     encoded_deptree
 }
 
+// A statement that does not match its analysis, or the block containing it when it cannot be found
+pub type StmtMismatch = (Option<StmtID>, String);
 
-pub fn replace_dependencies(base: &mut DependencyTree, patch: &EncodedDependencyTree) {
+// Statements are matched with their analysis by their position in the tree and the hash of their source, not their spans
+pub fn replace_dependencies(base: &mut DependencyTree, patch: &EncodedDependencyTree, codemap: &CodeMap) -> Result<(), Vec<StmtMismatch>> {
+    let mut errors = vec![];
+    replace_subtree_dependencies(base, patch, None, &vec![], codemap, &mut errors);
+    if errors.len() == 0 {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn replace_subtree_dependencies(base: &mut DependencyTree, patch: &EncodedDependencyTree, parent: Option<StmtID>, parent_path: &Vec<usize>, codemap: &CodeMap, errors: &mut Vec<StmtMismatch>) {
     if base.len() != patch.len() {
-        panic!("Lengths not equal: {:?} != {:?}", base, patch);
+        errors.push((parent, format!("block has {} statements but {} were analysed", base.len(), patch.len())));
+        return;
     }
     for i in 0..base.len() {
-        replace_dependencies_helper(&mut base[i], &patch[i]);
+        let mut path = parent_path.clone();
+        path.push(i);
+        replace_dependencies_helper(&mut base[i], &patch[i], &path, codemap, errors);
     }
 }
 
-fn replace_dependencies_helper(base: &mut DependencyNode, patch: &EncodedDependencyNode) {
+fn replace_dependencies_helper(base: &mut DependencyNode, patch: &EncodedDependencyNode, path: &Vec<usize>, codemap: &CodeMap, errors: &mut Vec<StmtMismatch>) {
+    let stmtid = base.get_stmtid();
     // Get list of dependencies from the patch
-    let (patch_deps, &(ref patch_inenv, ref patch_outenv)) = match patch {
-        &EncodedDependencyNode::ExprBlock(_, _, ref deps, ref depstr) |
-        &EncodedDependencyNode::Block(_, _, ref deps, ref depstr) |
-        &EncodedDependencyNode::Expr(_, ref deps, ref depstr)  |
-        &EncodedDependencyNode::Mac(_, ref deps, ref depstr) => (deps, depstr),
+    let (patch_id, patch_deps, &(ref patch_inenv, ref patch_outenv)) = match patch {
+        &EncodedDependencyNode::ExprBlock(ref id, _, ref deps, ref depstr) |
+        &EncodedDependencyNode::Block(ref id, _, ref deps, ref depstr) |
+        &EncodedDependencyNode::Expr(ref id, ref deps, ref depstr)  |
+        &EncodedDependencyNode::Mac(ref id, ref deps, ref depstr) => (id, deps, depstr),
     };
 
-    // Add the dependencies to the current node
-    match base {
-        &mut DependencyNode::ExprBlock(_, ref mut base_subtree, ref mut deps, ref mut env) => {
-            // Replacement instead of merge
-            deps.clear();
-            env.0.clear();
-            env.1.clear();
-            deps.extend(patch_deps);
-            deps.sort_unstable();
-            deps.dedup();
-            env.0.append(patch_inenv);
-            env.1.append(patch_outenv);
-            // Recurse down the tree
-            if let &EncodedDependencyNode::ExprBlock(_, ref patch_subtree, _, _) = patch {
-                replace_dependencies(base_subtree, patch_subtree);
-            } else {
-                panic!("patch was not a exprblock: {:?}", patch);
-            }
-        },
-        &mut DependencyNode::Block(_, ref mut base_subtree, ref mut deps, ref mut env) => {
-            // Replacement insdeps.clear();tead of merge
-            deps.clear();
-            env.0.clear();
-            env.1.clear();
-            deps.extend(patch_deps);
-            deps.sort_unstable();
-            deps.dedup();
-            env.0.append(patch_inenv);
-            env.1.append(patch_outenv);
-            // Recurse down the tree
-            if let &EncodedDependencyNode::Block(_, ref patch_subtree, _, _) = patch {
-                replace_dependencies(base_subtree, patch_subtree);
-            } else {
-                panic!("patch was not a block: {:?}", patch);
-            }
-        },
-        &mut DependencyNode::Expr(ref stmt, ref mut deps, ref mut env) => {
-            // Replacement instead of merge
-            deps.clear();
-            env.0.clear();
-            env.1.clear();
-            deps.extend(patch_deps);
-            deps.sort_unstable();
-            deps.dedup();
-            env.0.append(patch_inenv);
-            env.1.append(patch_outenv);
-            // Check that span ids line up
-            if let &EncodedDependencyNode::Expr((patch_lo, patch_hi), _, _) = patch {
-                let base_lo = stmt.span.lo().0;
-                let base_hi = stmt.span.hi().0;
-                if base_lo != patch_lo || base_hi != patch_hi {
-                    panic!("span ids do not line up: base:{} != patch:{} or base:{} != patch:{}", base_lo, patch_lo, base_hi, patch_hi);
-                }
-            } else {
-                panic!("patch was not a expr: {:?}", patch);
-            }
+    // Check the statement is the one that was analysed
+    if patch_id.path != *path {
+        errors.push((Some(stmtid), format!("statement {} was analysed as statement {}", path_to_string(path), path_to_string(&patch_id.path))));
+        return;
+    }
+    let (kinds_match, is_mac) = match (&*base, patch) {
+        (&DependencyNode::Expr(_, _, _), &EncodedDependencyNode::Expr(_, _, _)) |
+        (&DependencyNode::Block(_, _, _, _), &EncodedDependencyNode::Block(_, _, _, _)) |
+        (&DependencyNode::ExprBlock(_, _, _, _), &EncodedDependencyNode::ExprBlock(_, _, _, _)) => (true, false),
+        // Macros are expanded before the analysis stage, so may have been analysed as any kind of statement
+        (&DependencyNode::Mac(_, _, _), _) => (true, true),
+        _ => (false, false),
+    };
+    if !kinds_match {
+        errors.push((Some(stmtid), format!("statement {} is not the same kind of statement as when it was analysed", path_to_string(path))));
+        return;
+    }
+    // The source of an expanded macro is not the macro call, so only its position is checked
+    if !is_mac && patch_id.hash != content_hash(codemap, &stmtid) {
+        errors.push((Some(stmtid), format!("statement {} has changed since it was analysed", path_to_string(path))));
+        return;
+    }
+
+    // Replacement instead of merge
+    {
+        let (deps, env) = match *base {
+            DependencyNode::ExprBlock(_, _, ref mut deps, ref mut env) |
+            DependencyNode::Block(_, _, ref mut deps, ref mut env) |
+            DependencyNode::Expr(_, ref mut deps, ref mut env) |
+            DependencyNode::Mac(_, ref mut deps, ref mut env) => (deps, env),
+        };
+        deps.clear();
+        env.0.clear();
+        env.1.clear();
+        deps.extend(patch_deps);
+        deps.sort_unstable();
+        deps.dedup();
+        env.0.append(patch_inenv);
+        env.1.append(patch_outenv);
+    }
+
+    // Recurse down the tree
+    match (base, patch) {
+        (&mut DependencyNode::ExprBlock(_, ref mut base_subtree, _, _), &EncodedDependencyNode::ExprBlock(_, ref patch_subtree, _, _)) |
+        (&mut DependencyNode::Block(_, ref mut base_subtree, _, _), &EncodedDependencyNode::Block(_, ref patch_subtree, _, _)) => {
+            replace_subtree_dependencies(base_subtree, patch_subtree, Some(stmtid), path, codemap, errors);
         },
-        &mut DependencyNode::Mac(_, ref mut deps, ref mut env) => {
-            // Replacement instead of merge
-            deps.clear();
-            env.0.clear();
-            env.1.clear();
-            deps.extend(patch_deps);
-            deps.sort_unstable();
-            deps.dedup();
-            env.0.append(patch_inenv);
-            env.1.append(patch_outenv);
-        }
+        _ => {},
     }
 }
 
@@ -402,7 +422,11 @@ pub fn analyse_block_with_env(block: &Block) -> (DependencyTree, InOutEnvironmen
                     deps.push(backid);
                     return false
                 } else if backin.contains(elem) {
-                    panic!("{:?} consumes {:?} without releasing. Unable to satisfy {:?}", deptree[backid], elem, deptree[id]);
+                    let var = fallback::path_name(elem);
+                    fallback::give_up(format!("`{}` is consumed without being released", var), vec![
+                        (deptree[backid].get_stmtid(), format!("consumes `{}`", var)),
+                        (deptree[id].get_stmtid(), format!("uses `{}` after it was consumed", var)),
+                    ]);
                 }
                 true
             }).collect();
@@ -0,0 +1,36 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use parallel_stages::dependency_analysis::{StmtID, PathName};
+use utils::logging;
+
+// Why a function cannot be parallelised, with each statement involved and what it did
+#[derive(Debug)]
+pub struct Unparallelisable {
+    pub reason: String,
+    pub stmts: Vec<(StmtID, String)>,
+}
+
+// Unwinds to catch_unparallelisable, so only the current function is left sequential instead of the
+// whole crate failing to compile. Unlike panic! this does not print a message or backtrace.
+pub fn give_up(reason: String, stmts: Vec<(StmtID, String)>) -> ! {
+    log_debug!(logging::PLUGIN, "Giving up on function: {} {:?}", reason, stmts);
+    panic::resume_unwind(Box::new(Unparallelisable {
+        reason: reason,
+        stmts: stmts,
+    }))
+}
+
+// Any other panic is a bug in the plugin, so it is not caught
+pub fn catch_unparallelisable<F, R>(f: F) -> Result<R, Unparallelisable> where F: FnOnce() -> R {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => Ok(result),
+        Err(payload) => match payload.downcast::<Unparallelisable>() {
+            Ok(unparallelisable) => Err(*unparallelisable),
+            Err(payload) => panic::resume_unwind(payload),
+        },
+    }
+}
+
+pub fn path_name(path: &PathName) -> String {
+    path.iter().map(|ident| ident.name.to_string()).collect::<Vec<String>>().join("::")
+}
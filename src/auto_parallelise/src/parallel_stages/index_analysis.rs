@@ -0,0 +1,279 @@
+use syntax::ast::{BinOpKind, Expr, ExprKind, Ident, Mac, LitKind, Mutability, Pat, PatKind};
+use syntax::visit::{self, Visitor};
+use syntax::print::pprust;
+use syntax::ptr::P;
+use std::ops::Deref;
+
+use parallel_stages::deconstructor;
+use parallel_stages::dependency_analysis::{DependencyNode, Environment, PathName};
+use utils::logging;
+
+// How a variable that is only ever indexed by the loop variable is used in a loop body
+#[derive(Clone, Debug)]
+pub enum IndexAccess {
+    ReadOnly,                    // Only read, so every chunk can share it
+    DisjointWrites(Vec<P<Expr>>), // Each iteration writes to different elements, given by these subscripts
+}
+
+// Subscript of the form coef * i + offset, where i is the loop variable
+type Affine = (i64, i64);
+
+#[derive(Default)]
+struct Accesses {
+    reads: Vec<(Affine, P<Expr>)>,
+    writes: Vec<(Affine, P<Expr>)>,
+    unanalysable: bool, // Used without an affine subscript, or inside a macro
+}
+
+// Finds the candidate variables whose element accesses cannot overlap between iterations
+pub fn find_independent_indexing(nodes: &Vec<&DependencyNode>, loopvar: Ident, candidates: &Environment) -> Vec<(PathName, IndexAccess)> {
+    let mut independent = vec![];
+    for var in candidates.clone().into_iter() {
+        let mut accesses = Accesses::default();
+        for node in nodes {
+            collect_node_accesses(node, &var, loopvar, &mut accesses);
+        }
+        if accesses.unanalysable || (accesses.reads.len() == 0 && accesses.writes.len() == 0) {
+            continue;
+        }
+
+        // Every write must only conflict with accesses made by the same iteration
+        let mut carried = false;
+        for &(write, _) in &accesses.writes {
+            for &(other, _) in accesses.reads.iter().chain(accesses.writes.iter()) {
+                if let Some(distance) = dependence_distance(write, other) {
+                    if distance != 0 {
+                        log_debug!(logging::RECONSTRUCTOR, "{:?} has a loop-carried dependence with distance {}", var, distance);
+                        carried = true;
+                    }
+                }
+            }
+        }
+        if carried {
+            continue;
+        }
+
+        let access = if accesses.writes.len() == 0 {
+            IndexAccess::ReadOnly
+        } else {
+            let mut subscripts: Vec<P<Expr>> = vec![];
+            for (_, subscript) in accesses.writes {
+                let subscript_str = pprust::expr_to_string(&subscript);
+                if !subscripts.iter().any(|s| pprust::expr_to_string(s) == subscript_str) {
+                    subscripts.push(subscript);
+                }
+            }
+            IndexAccess::DisjointWrites(subscripts)
+        };
+        log_debug!(logging::RECONSTRUCTOR, "{:?} is indexed independently by each iteration: {:?}", var, access);
+        independent.push((var, access));
+    }
+    independent
+}
+
+// Distance in iterations between two accesses to the same element, or None if they never overlap.
+// Some(0) means only the same iteration touches the element. Unknown distances are treated as carried.
+fn dependence_distance(a: Affine, b: Affine) -> Option<i64> {
+    let ((coef_a, offset_a), (coef_b, offset_b)) = (a, b);
+    if coef_a == coef_b {
+        if coef_a == 0 {
+            // Same constant element every iteration
+            return if offset_a == offset_b { Some(1) } else { None };
+        }
+        let diff = offset_b - offset_a;
+        if diff % coef_a == 0 { Some(diff / coef_a) } else { None }
+    } else {
+        // GCD test: coef_a * i - coef_b * j = offset_b - offset_a only has solutions when gcd divides it
+        let divisor = gcd(coef_a.abs(), coef_b.abs());
+        if divisor != 0 && (offset_b - offset_a) % divisor != 0 {
+            None
+        } else {
+            Some(1)
+        }
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn collect_node_accesses(node: &DependencyNode, var: &PathName, loopvar: Ident, accesses: &mut Accesses) {
+    let &(ref inenv, ref outenv) = node.get_env();
+    if !inenv.contains(var) && !outenv.contains(var) {
+        return;
+    }
+    match node {
+        &DependencyNode::Expr(ref stmt, _, _) |
+        &DependencyNode::ExprBlock(ref stmt, _, _, _) => {
+            let mut visitor = IndexVisitor { var: var, loopvar: loopvar, accesses: accesses, saw_mac: false };
+            visitor.visit_stmt(stmt.deref());
+            if visitor.saw_mac {
+                // Cannot see inside the macro, so do not know how it uses var
+                visitor.accesses.unanalysable = true;
+            }
+        },
+        &DependencyNode::Mac(_, _, _) => accesses.unanalysable = true,
+        &DependencyNode::Block(_, _, _, _) => {},
+    }
+    match node {
+        &DependencyNode::ExprBlock(_, ref subtree, _, _) |
+        &DependencyNode::Block(_, ref subtree, _, _) => {
+            for subnode in subtree {
+                collect_node_accesses(subnode, var, loopvar, accesses);
+            }
+        },
+        _ => {},
+    }
+}
+
+struct IndexVisitor<'v> {
+    var: &'v PathName,
+    loopvar: Ident,
+    accesses: &'v mut Accesses,
+    saw_mac: bool,
+}
+
+impl<'v> IndexVisitor<'v> {
+    fn is_var(&self, expr: &Expr) -> bool {
+        if let ExprKind::Path(None, ref path) = expr.node {
+            path.segments.len() == 1 && self.var.len() == 1 &&
+            path.segments[0].identifier.name.as_str() == self.var[0].name.as_str()
+        } else {
+            false
+        }
+    }
+
+    // Records var[subscript], returning false if expr is not an index into var
+    fn record_index<'a>(&mut self, expr: &'a Expr, is_write: bool, is_read: bool) -> bool {
+        if let ExprKind::Index(ref base, ref subscript) = expr.node {
+            if self.is_var(base) {
+                match affine_subscript(subscript, self.loopvar) {
+                    Some(affine) => {
+                        if is_read {
+                            self.accesses.reads.push((affine, subscript.clone()));
+                        }
+                        if is_write {
+                            self.accesses.writes.push((affine, subscript.clone()));
+                        }
+                    },
+                    None => self.accesses.unanalysable = true,
+                }
+                self.visit_expr(subscript);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Records a place which is written to or mutably borrowed. Writing to part of an element, as in
+    // var[i][j] = x or var[i].field = x, writes to the element var[i] too.
+    fn record_place<'a>(&mut self, expr: &'a Expr, is_write: bool, is_read: bool) -> bool {
+        match expr.node {
+            ExprKind::Paren(ref expr1) => self.record_place(expr1, is_write, is_read),
+            ExprKind::Field(ref base, _) |
+            ExprKind::TupField(ref base, _) => self.record_place(base, is_write, true),
+            ExprKind::Index(ref base, ref subscript) if !self.is_var(base) => {
+                if self.record_place(base, is_write, true) {
+                    self.visit_expr(subscript);
+                    true
+                } else {
+                    false
+                }
+            },
+            _ => self.record_index(expr, is_write, is_read),
+        }
+    }
+}
+
+impl<'a, 'v> Visitor<'a> for IndexVisitor<'v> {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        match expr.node {
+            ExprKind::Assign(ref lhs, ref rhs) => {
+                if !self.record_place(lhs, true, false) {
+                    self.visit_expr(lhs);
+                }
+                self.visit_expr(rhs);
+            },
+            ExprKind::AssignOp(_, ref lhs, ref rhs) => {
+                if !self.record_place(lhs, true, true) {
+                    self.visit_expr(lhs);
+                }
+                self.visit_expr(rhs);
+            },
+            ExprKind::AddrOf(Mutability::Mutable, ref expr1) => {
+                if !self.record_place(expr1, true, true) {
+                    self.visit_expr(expr1);
+                }
+            },
+            ExprKind::MethodCall(ref segment, ref exprl) => {
+                // The receiver is only written if the method may take it by &mut self
+                let is_write = deconstructor::method_may_mutate(&*segment.identifier.name.as_str());
+                if !self.record_place(&exprl[0], is_write, true) {
+                    self.visit_expr(&exprl[0]);
+                }
+                for expr1 in exprl.iter().skip(1) {
+                    self.visit_expr(expr1);
+                }
+            },
+            ExprKind::Index(_, _) => {
+                if !self.record_index(expr, false, true) {
+                    visit::walk_expr(self, expr);
+                }
+            },
+            ExprKind::Path(_, _) => {
+                // Any use of var other than through an index
+                if self.is_var(expr) {
+                    self.accesses.unanalysable = true;
+                }
+            },
+            _ => visit::walk_expr(self, expr),
+        }
+    }
+
+    fn visit_mac(&mut self, _mac: &'a Mac) {
+        self.saw_mac = true;
+    }
+}
+
+fn affine_subscript(expr: &Expr, loopvar: Ident) -> Option<Affine> {
+    match expr.node {
+        ExprKind::Paren(ref expr1) |
+        ExprKind::Cast(ref expr1, _) => affine_subscript(expr1, loopvar),
+        ExprKind::Path(None, ref path) => {
+            if path.segments.len() == 1 && path.segments[0].identifier.name.as_str() == loopvar.name.as_str() {
+                Some((1, 0))
+            } else {
+                None
+            }
+        },
+        ExprKind::Lit(ref lit) => {
+            if let LitKind::Int(value, _) = lit.node {
+                Some((0, value as i64))
+            } else {
+                None
+            }
+        },
+        ExprKind::Binary(ref op, ref expr1, ref expr2) => {
+            let (coef1, offset1) = affine_subscript(expr1, loopvar)?;
+            let (coef2, offset2) = affine_subscript(expr2, loopvar)?;
+            match op.node {
+                BinOpKind::Add => Some((coef1 + coef2, offset1 + offset2)),
+                BinOpKind::Sub => Some((coef1 - coef2, offset1 - offset2)),
+                // Only affine if one side is a constant
+                BinOpKind::Mul if coef1 == 0 => Some((offset1 * coef2, offset1 * offset2)),
+                BinOpKind::Mul if coef2 == 0 => Some((coef1 * offset2, offset1 * offset2)),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+// The loop variable, if the pattern binds a single name
+pub fn loop_variable(pat: &Pat) -> Option<Ident> {
+    if let PatKind::Ident(_, ref spanident, None) = pat.node {
+        Some(spanident.node)
+    } else {
+        None
+    }
+}
@@ -1,4 +1,11 @@
 pub mod deconstructor;
 pub mod dependency_analysis;
 pub mod scheduler;
+pub mod cost_model;
+pub mod profiler;
+pub mod parallelism;
 pub mod reconstructor;
+pub mod reductions;
+pub mod index_analysis;
+pub mod verifier;
+pub mod fallback;
@@ -0,0 +1,110 @@
+use serde_json;
+use std::cmp;
+use std::path::Path;
+
+use utils;
+
+use parallel_stages::cost_model;
+use parallel_stages::dependency_analysis::{DependencyTree, DependencyNode};
+use parallel_stages::profiler::Profile;
+use parallel_stages::scheduler::{Schedule, ScheduleTree};
+
+// How much parallelism is available in a function, in cost model units
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ParallelismReport {
+    pub function: String,
+    pub total_work: u32,
+    pub critical_path: u32,
+    pub max_width: usize, // Most branches of the schedule that can run at once
+    pub speedup_bound: f64,
+}
+
+// Reports are saved as each function is modified, replacing any older report for the same function
+pub fn save_report(path: &Path, report: ParallelismReport) {
+    let mut reports = load_reports(path);
+    reports.retain(|old_report| old_report.function != report.function);
+    reports.push(report);
+    let reports_json = match serde_json::to_string_pretty(&reports) {
+        Ok(json) => json,
+        Err(why) => panic!("Unable to convert parallelism reports to JSON: {}", why),
+    };
+    utils::write_file(path, &reports_json);
+}
+
+pub fn load_reports(path: &Path) -> Vec<ParallelismReport> {
+    match utils::read_file(&path.to_string_lossy()) {
+        Some(json) => match serde_json::from_str(&json) {
+            Ok(reports) => reports,
+            Err(why) => panic!("Unable to parse {} as json: {}", path.display(), why),
+        },
+        None => vec![],
+    }
+}
+
+pub fn analyse_parallelism(function: &str, deptree: &DependencyTree, schedule: &Schedule, profile: &Profile) -> ParallelismReport {
+    let total_work = deptree.iter().fold(0u32, |total, node| total.saturating_add(cost_model::node_cost(node, profile)));
+    let critical_path = critical_path(deptree, profile);
+    let max_width = schedule_width(schedule.list());
+
+    // Work over span, but never more than the number of branches which can run at once
+    let speedup_bound = if critical_path > 0 {
+        f64::min(total_work as f64 / critical_path as f64, max_width as f64)
+    } else {
+        1.0
+    };
+
+    ParallelismReport {
+        function: function.to_owned(),
+        total_work: total_work,
+        critical_path: critical_path,
+        max_width: max_width,
+        speedup_bound: speedup_bound,
+    }
+}
+
+// Longest chain of dependent statements in a block
+fn critical_path(deptree: &DependencyTree, profile: &Profile) -> u32 {
+    let mut finish_times: Vec<u32> = vec![];
+    for node in deptree {
+        // Dependencies are always earlier in the block
+        let start = node.get_deps().iter().filter(|&&dep| dep < finish_times.len()).map(|&dep| finish_times[dep]).max().unwrap_or(0);
+        finish_times.push(start.saturating_add(node_span(node, profile)));
+    }
+    finish_times.into_iter().max().unwrap_or(0)
+}
+
+// Time taken by a single node when its inner blocks are run in parallel
+fn node_span(node: &DependencyNode, profile: &Profile) -> u32 {
+    if profile.stmt_costs.contains_key(&node.get_stmtid()) {
+        return cost_model::node_cost(node, profile);
+    }
+    match node {
+        &DependencyNode::Expr(_, _, _) |
+        &DependencyNode::Mac(_, _, _) => cost_model::estimate_cost(node, &profile.call_costs),
+        &DependencyNode::Block(_, ref subtree, _, _) => cost_model::STMT_COST.saturating_add(critical_path(subtree, profile)),
+        &DependencyNode::ExprBlock(ref stmt, ref subtree, _, _) => {
+            if cost_model::is_loop(stmt) {
+                // Iterations are assumed to be run one after another
+                cost_model::estimate_cost(node, &profile.call_costs)
+            } else {
+                let inner_path = subtree.iter().map(|subnode| node_span(subnode, profile)).max().unwrap_or(0);
+                cost_model::STMT_COST.saturating_add(inner_path)
+            }
+        },
+    }
+}
+
+// Number of branches that are running at the same time, if every sibling is given a thread
+fn schedule_width(trees: &Vec<ScheduleTree>) -> usize {
+    trees.iter().map(tree_width).sum()
+}
+
+fn tree_width(tree: &ScheduleTree) -> usize {
+    match tree {
+        &ScheduleTree::Node(_, ref spanning_tree) => cmp::max(1, schedule_width(&spanning_tree.children)),
+        &ScheduleTree::Block(_, ref spanning_tree, ref schedule) => {
+            cmp::max(1, cmp::max(schedule_width(schedule.list()), schedule_width(&spanning_tree.children)))
+        },
+        &ScheduleTree::SyncTo(_, _, _) => 0,
+    }
+}
@@ -0,0 +1,164 @@
+use syntax::ast::{Block, Ident, Mac, Stmt, StmtKind};
+use syntax::ext::base::ExtCtxt;
+use syntax::fold::{self, Folder};
+use syntax::ptr::P;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use utils;
+use utils::logging;
+use parallel_stages::cost_model;
+use parallel_stages::dependency_analysis::StmtID;
+
+// What the cost model knows about a function beyond its code, in the same units as the cost model
+pub struct Profile {
+    pub stmt_costs: HashMap<StmtID, u32>, // Measured cost of each statement that has been profiled
+    pub call_costs: BTreeMap<String, u32>, // Cost of calls by name, see Config::call_costs
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile {
+            stmt_costs: HashMap::new(),
+            call_costs: BTreeMap::new(),
+        }
+    }
+}
+
+// Each line of the profile file is the total time of a statement during one run of the
+// function: function lo hi nanoseconds. The cost of a statement is its average over the runs.
+pub fn load_profile(path: &Path, func_name: &str) -> Profile {
+    let mut nanos: HashMap<StmtID, (u64, u64)> = HashMap::new();
+    if let Some(contents) = utils::read_file(&path.to_string_lossy()) {
+        for line in contents.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() != 4 || parts[0] != func_name {
+                continue;
+            }
+            match (parts[1].parse::<u32>(), parts[2].parse::<u32>(), parts[3].parse::<u64>()) {
+                (Ok(lo), Ok(hi), Ok(time)) => {
+                    let &mut (ref mut total, ref mut runs) = nanos.entry((lo, hi)).or_insert((0, 0));
+                    *total = total.saturating_add(time);
+                    *runs += 1;
+                },
+                _ => log_warn!(logging::PROFILER, "Skipping malformed line in {}: {}", path.display(), line),
+            }
+        }
+    } else {
+        log_warn!(logging::PROFILER, "No profile found at {}, using estimated costs", path.display());
+    }
+
+    // Convert the average time of each statement into cost units
+    let mut profile = Profile::new();
+    for (stmtid, (time, runs)) in nanos {
+        let cost = ::std::cmp::max(1, time / runs / cost_model::NANOS_PER_COST);
+        profile.stmt_costs.insert(stmtid, ::std::cmp::min(cost, u32::max_value() as u64) as u32);
+    }
+    profile
+}
+
+// Times every statement in the block. The timings are kept in memory and appended to the profile file
+// once the outermost call of the function returns, so the time taken to write them is not measured.
+pub fn instrument_block(cx: &mut ExtCtxt, func_name: &str, profile_path: &Path, block: P<Block>) -> P<Block> {
+    let block = {
+        let mut instrumenter = Instrumenter {
+            cx: cx,
+        };
+        instrumenter.fold_block(block)
+    };
+
+    // Statements run on other threads are written out straight away, as nothing else would write them
+    let profile_path = &*profile_path.to_string_lossy();
+    let mut stmts = vec![
+        quote_stmt!(cx, thread_local! {
+            static PROFILE_TIMINGS: ::std::cell::RefCell<(usize, ::std::collections::HashMap<(u32, u32), u64>)> =
+                ::std::cell::RefCell::new((0, ::std::collections::HashMap::new()));
+        }).unwrap(),
+        quote_stmt!(cx, fn profile_flush(timings: &mut ::std::collections::HashMap<(u32, u32), u64>) {
+            use std::io::Write;
+            let mut profile_file = ::std::fs::OpenOptions::new().create(true).append(true).open($profile_path).unwrap();
+            for (&(lo, hi), nanos) in timings.iter() {
+                writeln!(profile_file, "{} {} {} {}", $func_name, lo, hi, nanos).unwrap();
+            }
+            timings.clear();
+        }).unwrap(),
+        quote_stmt!(cx, fn profile_record(lo: u32, hi: u32, elapsed: ::std::time::Duration) {
+            let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+            PROFILE_TIMINGS.with(|timings| {
+                let mut timings = timings.borrow_mut();
+                *timings.1.entry((lo, hi)).or_insert(0) += nanos;
+                if timings.0 == 0 {
+                    profile_flush(&mut timings.1);
+                }
+            });
+        }).unwrap(),
+        // Returning early or panicking still writes the timings
+        quote_stmt!(cx, struct ProfileCall;).unwrap(),
+        quote_stmt!(cx, impl Drop for ProfileCall {
+            fn drop(&mut self) {
+                PROFILE_TIMINGS.with(|timings| {
+                    let mut timings = timings.borrow_mut();
+                    timings.0 -= 1;
+                    if timings.0 == 0 {
+                        profile_flush(&mut timings.1);
+                    }
+                });
+            }
+        }).unwrap(),
+        quote_stmt!(cx, PROFILE_TIMINGS.with(|timings| timings.borrow_mut().0 += 1);).unwrap(),
+        quote_stmt!(cx, let _profile_call = ProfileCall;).unwrap(),
+    ];
+    block.map(|mut block| {
+        stmts.append(&mut block.stmts);
+        block.stmts = stmts;
+        block
+    })
+}
+
+struct Instrumenter<'a, 'b: 'a> {
+    cx: &'a mut ExtCtxt<'b>,
+}
+
+impl<'a, 'b> Instrumenter<'a, 'b> {
+    fn instrument_stmt(&mut self, stmt: Stmt, is_last: bool) -> Vec<Stmt> {
+        let (lo, hi) = stmtID!(stmt);
+        let start = Ident::from_str(&format!("profile_{}_{}_start", lo, hi));
+        let value = Ident::from_str(&format!("profile_{}_{}_value", lo, hi));
+        let cx = &mut *self.cx;
+
+        let start_stmt = quote_stmt!(cx, let $start = ::std::time::Instant::now();).unwrap();
+        let record_stmt = quote_stmt!(cx, profile_record($lo, $hi, $start.elapsed());).unwrap();
+
+        match stmt.node {
+            // The value of the block has to be kept until after it is recorded
+            StmtKind::Expr(ref expr) if is_last => vec![
+                start_stmt,
+                quote_stmt!(cx, let $value = $expr;).unwrap(),
+                record_stmt,
+                quote_stmt!(cx, $value).unwrap(),
+            ],
+            StmtKind::Item(_) => vec![stmt.clone()],
+            _ => vec![start_stmt, stmt.clone(), record_stmt],
+        }
+    }
+}
+
+impl<'a, 'b> Folder for Instrumenter<'a, 'b> {
+    fn fold_block(&mut self, block: P<Block>) -> P<Block> {
+        // Instrument inner blocks first
+        let block = fold::noop_fold_block(block, self);
+        block.map(|mut block| {
+            let stmts = ::std::mem::replace(&mut block.stmts, vec![]);
+            let num_stmts = stmts.len();
+            for (i, stmt) in stmts.into_iter().enumerate() {
+                let mut instrumented = self.instrument_stmt(stmt, i + 1 == num_stmts);
+                block.stmts.append(&mut instrumented);
+            }
+            block
+        })
+    }
+
+    fn fold_mac(&mut self, mac: Mac) -> Mac {
+        mac
+    }
+}
@@ -1,16 +1,21 @@
 use syntax::ptr::P;
-use syntax::ast::{self, Local, Stmt, StmtKind, Expr, ExprKind, Block, Ident, Item, ItemKind, Path, PathSegment, Pat, PatKind};
+use syntax::ast::{self, Local, Stmt, StmtKind, Expr, ExprKind, Block, Ident, Item, ItemKind, Mac, Path, PathSegment, Pat, PatKind};
 use syntax::codemap::dummy_spanned;
 use syntax::ext::base::{ExtCtxt};
+use syntax::visit::{self, Visitor};
+use syntax::fold::Folder;
+use std::collections::HashMap;
 use syntax_pos::{BytePos, Span};
 use std::ops::Deref;
 
-use parallel_stages::{dependency_analysis, scheduler, deconstructor};
-use self::dependency_analysis::{Environment, PathName, StmtID, DependencyNode};
+use parallel_stages::{dependency_analysis, scheduler, deconstructor, reductions, index_analysis, cost_model, verifier};
+use self::dependency_analysis::{Environment, PathName, StmtID, DependencyNode, DependencyTree};
 use self::scheduler::{Schedule, ScheduleTree};
+use self::reductions::{Accumulators, ReductionKind};
+use self::index_analysis::IndexAccess;
+use self::verifier::LoopRewrite;
 use plugin::shared_state::Config;
-
-use serde_json;
+use utils::logging;
 
 pub fn create_block(cx: &mut ExtCtxt, stmts: Vec<Stmt>, stmtid: Option<StmtID>) -> Block {
     let block = quote_block!(cx, {});
@@ -43,7 +48,7 @@ pub fn create_path(pathname: PathName) -> Path {
 pub fn create_function(cx: &mut ExtCtxt, item: &Item, func_name: &str, join_handle: bool, body: Block) -> (Ident, Item) {
     if let ItemKind::Fn(ref fndecl, ref unsafety, ref constness, ref abi, ref generics, _) = item.node {
         let mut new_fndecl = fndecl.deref().clone();
-        eprintln!("new_fndecl: {:?}", new_fndecl);
+        log_trace!(logging::RECONSTRUCTOR, "new_fndecl: {:?}", new_fndecl);
         if join_handle {
             let new_ty = match new_fndecl.output {
                 ast::FunctionRetTy::Default(_) => quote_ty!(cx, ::std::thread::JoinHandle<()>),
@@ -73,7 +78,7 @@ fn create_thread(cx: &mut ExtCtxt, lo: u32, hi: u32, thread_contents: Vec<Stmt>)
     let thread_sname = format!("thread_{}_{}", lo, hi);
     let thread_name = Ident::from_str(&thread_sname);
     let thread_block = create_block(cx, thread_contents, None);
-    let thread_stmt = quote_stmt!(cx, let $thread_name = std::thread::spawn(move || $thread_block);).unwrap();
+    let thread_stmt = quote_stmt!(cx, let $thread_name = ::std::thread::spawn(move || $thread_block);).unwrap();
     (thread_name, thread_stmt)
 }
 
@@ -87,7 +92,7 @@ fn envtuple_expr(cx: &mut ExtCtxt, env: &Environment) -> P<Expr> {
     } else {
         panic!("was not tup")
     }
-    eprintln!("ENV: {:?}, TUPLE: {:?}", env, tuple);
+    log_trace!(logging::RECONSTRUCTOR, "ENV: {:?}, TUPLE: {:?}", env, tuple);
     P(tuple)
 }
 
@@ -102,7 +107,33 @@ fn envtuple_pat(cx: &mut ExtCtxt, env: &Environment) -> P<Pat> {
     } else {
         panic!("was not tup")
     }
-    eprintln!("ENV: {:?}, TUPLE: {:?}", env, tuple);
+    log_trace!(logging::RECONSTRUCTOR, "ENV: {:?}, TUPLE: {:?}", env, tuple);
+    P(tuple)
+}
+
+fn identtuple_expr(cx: &mut ExtCtxt, idents: &Vec<Ident>) -> P<Expr> {
+    let mut tuple = quote_expr!(cx, ()).deref().clone();
+    if let ExprKind::Tup(ref mut exprl) = tuple.node {
+        for ident in idents {
+            let path = create_path(vec![*ident]);
+            exprl.push(quote_expr!(cx, $path));
+        }
+    } else {
+        panic!("was not tup")
+    }
+    P(tuple)
+}
+
+fn identtuple_pat(cx: &mut ExtCtxt, idents: &Vec<Ident>) -> P<Pat> {
+    let mut tuple = quote_pat!(cx, ()).deref().clone();
+    if let PatKind::Tuple(ref mut pats, _) = tuple.node {
+        for ident in idents {
+            let spanned_ident = dummy_spanned(*ident);
+            pats.push(quote_pat!(cx, mut $spanned_ident));
+        }
+    } else {
+        panic!("was not tup")
+    }
     P(tuple)
 }
 
@@ -110,34 +141,128 @@ fn syncline_name(stmtid1: &StmtID, stmtid2: &StmtID, env: &Environment) -> Strin
     let &(to_a, to_b) = stmtid1;
     let &(from_a, from_b) = stmtid2;
     let mut line_name = format!("syncline_{}_{}_{}_{}", to_a, to_b, from_a, from_b);
-    for path in env.clone().into_depstr() {
-        for (var, marks) in path {
+    let depstr = env.clone().into_depstr();
+    for path in &depstr {
+        for &(ref var, ref marks) in path {
             line_name.push_str(&format!("_{}", var));
-            for mark in marks {
-                line_name.push_str(&format!("{}", mark));
+            // Hygiene marks change between builds, so are only used to tell apart variables with the same name
+            let same_name = depstr.iter().filter(|other| other.iter().any(|&(ref other_var, _)| other_var == var)).count();
+            if same_name > 1 {
+                for mark in marks {
+                    line_name.push_str(&format!("{}", mark));
+                }
             }
         }
     }
     line_name
 }
 
+// Generated names contain StmtIDs, which are byte offsets and change with any edit before the statement.
+// These are replaced by the position of the statement in the function, so names only change when statements do.
+pub fn canonical_names(block: Block, deptree: &DependencyTree) -> Block {
+    let mut stmtids = vec![];
+    collect_stmtids(deptree, &mut stmtids);
+    stmtids.sort();
+    stmtids.dedup();
+    let mut positions = HashMap::new();
+    for (position, stmtid) in stmtids.into_iter().enumerate() {
+        positions.insert(stmtid, position);
+    }
+    let mut renamer = CanonicalNames { positions: positions };
+    renamer.fold_block(P(block)).into_inner()
+}
+
+fn collect_stmtids(deptree: &DependencyTree, stmtids: &mut Vec<StmtID>) {
+    for node in deptree {
+        stmtids.push(node.get_stmtid());
+        match node {
+            &DependencyNode::Block(_, ref subtree, _, _) |
+            &DependencyNode::ExprBlock(_, ref subtree, _, _) => collect_stmtids(subtree, stmtids),
+            _ => {},
+        }
+    }
+}
+
+struct CanonicalNames {
+    positions: HashMap<StmtID, usize>,
+}
+
+impl CanonicalNames {
+    // Replaces the StmtIDs after the prefix, e.g. syncline_417_446_512_530_x -> syncline_3_5_x
+    fn rename(&self, name: &str) -> Option<String> {
+        let parts: Vec<&str> = name.split('_').collect();
+        let num_stmtids = match parts[0] {
+            "thread" | "forloop" | "whileloop" => 1,
+            "syncline" => 2,
+            _ => return None,
+        };
+        if parts.len() < 1 + 2 * num_stmtids {
+            return None;
+        }
+        let mut renamed = vec![parts[0].to_owned()];
+        for i in 0..num_stmtids {
+            let lo = parts[1 + 2 * i].parse::<u32>().ok()?;
+            let hi = parts[2 + 2 * i].parse::<u32>().ok()?;
+            let position = self.positions.get(&(lo, hi))?;
+            renamed.push(format!("{}", position));
+        }
+        renamed.extend(parts[1 + 2 * num_stmtids..].iter().map(|part| (*part).to_owned()));
+        Some(renamed.join("_"))
+    }
+}
+
+impl Folder for CanonicalNames {
+    fn fold_ident(&mut self, ident: Ident) -> Ident {
+        match self.rename(&*ident.name.as_str()) {
+            Some(name) => Ident::from_str(&name),
+            None => ident,
+        }
+    }
+
+    fn fold_mac(&mut self, mac: Mac) -> Mac {
+        mac
+    }
+}
+
 pub fn spawn_from_schedule<'a>(config: &Config, cx: &mut ExtCtxt, schedule: Schedule) -> Vec<Stmt> {
     // Gather all the synclines and create them as variables
     let synclines = schedule.get_all_synclines();
-    eprintln!("Synclines:\n{:?}\n", synclines);
+    log_trace!(logging::RECONSTRUCTOR, "Synclines:\n{:?}\n", synclines);
     let mut stmts = vec![];
     for (ref stmtid1, ref stmtid2, ref env) in synclines.clone() {
         let line_name = syncline_name(stmtid1, stmtid2, env);
         let sx = Ident::from_str(&format!("{}_send", line_name));
         let rx = Ident::from_str(&format!("{}_receive", line_name));
-        let stmt = quote_stmt!(cx, let ($sx, $rx) = std::sync::mpsc::channel()).unwrap();
+        let stmt = quote_stmt!(cx, let ($sx, $rx) = ::std::sync::mpsc::channel()).unwrap();
         stmts.push(stmt);
     }
-    let mut body: Vec<Stmt> = spawn_from_schedule_helper(config, cx, schedule.list(), &synclines);
+    // Reductions depend on what their variables were declared as
+    let mut nodes = vec![];
+    schedule_nodes(schedule.list(), &mut nodes);
+    let accumulators = reductions::declared_accumulators(&nodes);
+
+    // Every spawned branch may be running while a loop is, so loops only spawn the threads left over
+    let mut spare_threads = if config.max_threads > 0 {
+        Some((config.max_threads - 1).saturating_sub(schedule.spawned_branches()))
+    } else {
+        None
+    };
+
+    let mut body: Vec<Stmt> = spawn_from_schedule_helper(config, cx, schedule.list(), &synclines, &accumulators, &mut spare_threads, true);
     stmts.append(&mut body);
     stmts
 }
 
+// Every node of the schedule, in the order of the schedule
+fn schedule_nodes<'a>(trees: &Vec<ScheduleTree<'a>>, nodes: &mut Vec<&'a DependencyNode>) {
+    for tree in trees {
+        if let Some(spanning_tree) = tree.get_spanning_tree() {
+            nodes.push(spanning_tree.node);
+            schedule_nodes(&spanning_tree.children, nodes);
+        }
+    }
+}
+
 pub fn create_seq_fn(cx: &mut ExtCtxt, seq_fn_name: &String, parident: &Ident, item: &Item) -> (Ident, Item) {
     let mut seqcall =  {
         if let ItemKind::Fn(ref fndecl, _, _, _, _, _) = item.node {
@@ -189,7 +314,7 @@ pub fn create_seq_fn(cx: &mut ExtCtxt, seq_fn_name: &String, parident: &Ident, i
 }
 
 fn unwrap_stmts_to_blocks(stmts: &Vec<Stmt>) -> Vec<Block> {
-    eprintln!("unwrap_stmts_to_blocks({:?})", stmts);
+    log_trace!(logging::RECONSTRUCTOR, "unwrap_stmts_to_blocks({:?})", stmts);
     let mut output = vec![];
     for id in 0..stmts.len() {
         // Work in pairs as stmt is in style of:
@@ -236,8 +361,19 @@ fn unwrap_stmts_to_blocks(stmts: &Vec<Stmt>) -> Vec<Block> {
     output
 }
 
-fn spawn_from_schedule_helper<'a>(config: &Config, cx: &mut ExtCtxt, sch: &Vec<ScheduleTree<'a>>, all_synclines: &Vec<(StmtID, StmtID, &Environment)>) -> Vec<Stmt> {
+// When flatten is set and no threads are spawned, the statements are output directly instead of
+// in a return_value block. unwrap_stmts_to_blocks needs the return_value blocks.
+fn spawn_from_schedule_helper<'a>(config: &Config, cx: &mut ExtCtxt, sch: &Vec<ScheduleTree<'a>>, all_synclines: &Vec<(StmtID, StmtID, &Environment)>, accumulators: &Accumulators, spare_threads: &mut Option<usize>, flatten: bool) -> Vec<Stmt> {
+    // Items can be used anywhere in the block they are declared in, so are placed before any thread is spawned
     let mut output = vec![];
+    let mut trees = vec![];
+    for tree in sch {
+        match item_stmt(tree) {
+            Some(stmt) => output.push(stmt),
+            None => trees.push(tree.clone()),
+        }
+    }
+    let sch = &trees;
     let mut threads = vec![];
     let mut add_return_value = false;
 
@@ -254,7 +390,7 @@ fn spawn_from_schedule_helper<'a>(config: &Config, cx: &mut ExtCtxt, sch: &Vec<S
             let ((lo, hi), mut children) = match sch[i] {
                 ScheduleTree::Block(ref prereqs, ref spanning_tree, _) |
                 ScheduleTree::Node(ref prereqs, ref spanning_tree) => {
-                    eprintln!("{:?} paths: {:?}", spanning_tree.node.get_stmtid(), spanning_tree.node.get_env());
+                    log_trace!(logging::RECONSTRUCTOR, "{:?} paths: {:?}", spanning_tree.node.get_stmtid(), spanning_tree.node.get_env());
 
                     // Add prereqs
                     let ref stmtid2 = spanning_tree.node.get_stmtid();
@@ -271,7 +407,7 @@ fn spawn_from_schedule_helper<'a>(config: &Config, cx: &mut ExtCtxt, sch: &Vec<S
                     }
 
                     // Spawn children after node
-                    let children = spawn_from_schedule_helper(config, cx, &spanning_tree.children, all_synclines);
+                    let children = spawn_from_schedule_helper(config, cx, &spanning_tree.children, all_synclines, accumulators, spare_threads, true);
 
                     // Return node id
                     (spanning_tree.node.get_stmtid(), children)
@@ -290,7 +426,7 @@ fn spawn_from_schedule_helper<'a>(config: &Config, cx: &mut ExtCtxt, sch: &Vec<S
                     match spanning_tree.node {
                         &DependencyNode::Block(ref stmtid, _, _, _) => {
                             // Add block to the schedule
-                            let mut inner_block = spawn_from_schedule_helper(config, cx, schedule.list(), all_synclines);
+                            let mut inner_block = spawn_from_schedule_helper(config, cx, schedule.list(), all_synclines, accumulators, spare_threads, true);
                             let exprblock = create_block(cx, inner_block, Some(*stmtid));
                             let mut mnode_stmt = spanning_tree.node.get_stmt();
                             let stmt = quote_stmt!(cx, $exprblock).unwrap();
@@ -298,7 +434,7 @@ fn spawn_from_schedule_helper<'a>(config: &Config, cx: &mut ExtCtxt, sch: &Vec<S
                         },
                         &DependencyNode::ExprBlock(ref exprblockstmt, _, _, _) => {
                             // Add block to the schedule
-                            eprintln!("ScheduleTree Block ExprBlock: {:?}", exprblockstmt);
+                            log_trace!(logging::RECONSTRUCTOR, "ScheduleTree Block ExprBlock: {:?}", exprblockstmt);
 
                             let mut mnode_stmt = spanning_tree.node.get_stmt();
                             let stmt =
@@ -306,13 +442,13 @@ fn spawn_from_schedule_helper<'a>(config: &Config, cx: &mut ExtCtxt, sch: &Vec<S
                                     // If the block has no external dependencies, then it can be run in parallel
                                     let (ref inenv, _) = schedule.get_env();
                                     // TODO: Send inenv so that for loops can be parallelised.
-                                    exprblock_into_statement(config, cx, node_stmt.deref().clone(), &schedule, inenv, all_synclines)
+                                    exprblock_into_statement(config, cx, node_stmt.deref().clone(), &schedule, inenv, all_synclines, accumulators, spare_threads)
                                 } else {
                                     // Convert inner schedules into blocks
                                     let mut inner_blocks_stmts = vec![];
                                     for inner_schedule_tree in schedule.list() {
                                         let inner_schedule: Vec<ScheduleTree<'a>> = vec![inner_schedule_tree.clone()];
-                                        let mut inner_block_stmt = spawn_from_schedule_helper(config, cx, &inner_schedule, all_synclines);
+                                        let mut inner_block_stmt = spawn_from_schedule_helper(config, cx, &inner_schedule, all_synclines, accumulators, spare_threads, false);
                                         inner_blocks_stmts.append(&mut inner_block_stmt);
                                     }
                                     let mut inner_blocks = unwrap_stmts_to_blocks(&inner_blocks_stmts);
@@ -332,7 +468,7 @@ fn spawn_from_schedule_helper<'a>(config: &Config, cx: &mut ExtCtxt, sch: &Vec<S
 
             if i == sch.len() - 1 {
                 // Last uses the current thread
-                if true { // TODO: threads.len() > 0 {
+                if threads.len() > 0 || !flatten {
                     // Place in a block so that we can get the correct return_value
                     let return_block = create_block(cx, thread_contents, None);
                     let let_stmt = quote_stmt!(cx, let return_value = $return_block;).unwrap();
@@ -340,14 +476,17 @@ fn spawn_from_schedule_helper<'a>(config: &Config, cx: &mut ExtCtxt, sch: &Vec<S
                     add_return_value = true;
                 } else {
                     // No threads to join so don't need messy return_value
-                    // TODO: This breaks unwrap_stmts_to_blocks
                     output.append(&mut thread_contents);
                 }
-            } else {
+            } else if !sch[i].is_sequential() {
                 // All execpt the last is put into a concurrent thread
                 let (thread_name, thread_stmt) = create_thread(cx, lo, hi, thread_contents);
                 output.push(thread_stmt);
                 threads.push(thread_name);
+            } else {
+                // Not worth a thread, and does not wait for anything, so run it on the current thread
+                let inline_block = create_block(cx, thread_contents, None);
+                output.push(quote_stmt!(cx, $inline_block;).unwrap());
             }
         }
     }
@@ -365,13 +504,762 @@ fn spawn_from_schedule_helper<'a>(config: &Config, cx: &mut ExtCtxt, sch: &Vec<S
     output
 }
 
-fn exprblock_into_statement<'a>(config: &Config, cx: &mut ExtCtxt, exprstmt: Stmt, inner_schedule: &Schedule<'a>, inenv: &Environment, all_synclines: &Vec<(StmtID, StmtID, &Environment)>) -> Stmt {
+// Items have no dependencies, so are never the child of another node
+fn item_stmt(tree: &ScheduleTree) -> Option<Stmt> {
+    if let &ScheduleTree::Node(_, ref spanning_tree) = tree {
+        if let Some(stmt) = spanning_tree.node.get_stmt() {
+            if let StmtKind::Item(_) = stmt.node {
+                if spanning_tree.children.len() == 0 {
+                    return Some(stmt.deref().clone());
+                }
+            }
+        }
+    }
+    None
+}
+
+fn forloop_ident(stmtid: &StmtID, suffix: &str) -> Ident {
+    let &(lo, hi) = stmtid;
+    Ident::from_str(&format!("forloop_{}_{}_{}", lo, hi, suffix))
+}
+
+// How the iterations of a parallel for loop are handed out to its threads. The items are moved
+// into spawned threads, so only iterators known to own their items or to borrow a slice are split.
+enum LoopSplit {
+    // Owned iterator (e.g. 0..10, v.clone()): collected into a Vec and split into chunks
+    Chunks,
+    // Borrowed slice (e.g. &v[..], v[1..].iter()): split by index, each chunk gets a copy of its part
+    Slice(P<Expr>),
+}
+
+fn loop_split(iter_expr: &Expr) -> Option<LoopSplit> {
+    match iter_expr.node {
+        ExprKind::Range(_, _, _) |
+        ExprKind::Mac(_) if is_owned_iter(iter_expr) => Some(LoopSplit::Chunks),
+        ExprKind::Paren(ref expr1) => loop_split(expr1),
+        ExprKind::AddrOf(ast::Mutability::Immutable, ref expr1) if is_slice(expr1) => Some(LoopSplit::Slice(expr1.clone())),
+        ExprKind::MethodCall(ref segment, ref exprl) => {
+            match &*segment.identifier.name.as_str() {
+                "iter" if exprl.len() == 1 && is_slice(&exprl[0]) => Some(LoopSplit::Slice(exprl[0].clone())),
+                _ if is_owned_iter(iter_expr) => Some(LoopSplit::Chunks),
+                // Anything else may borrow from a variable that cannot be moved into a thread
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+// Iterators which own their items whatever the type of the variables they use
+fn is_owned_iter(expr: &Expr) -> bool {
+    match expr.node {
+        ExprKind::Range(_, _, _) => true,
+        ExprKind::Paren(ref expr1) => is_owned_iter(expr1),
+        ExprKind::Mac(ref mac) => mac.node.path == "vec",
+        ExprKind::MethodCall(ref segment, ref exprl) => {
+            match &*segment.identifier.name.as_str() {
+                "clone" | "to_vec" => true,
+                // Adaptors keep their items owned
+                "rev" | "skip" | "take" | "step_by" => is_owned_iter(&exprl[0]),
+                _ => false,
+            }
+        },
+        _ => false,
+    }
+}
+
+// Indexing by a range always gives a slice, which can be split by index
+fn is_slice(expr: &Expr) -> bool {
+    match expr.node {
+        ExprKind::Paren(ref expr1) => is_slice(expr1),
+        ExprKind::Index(_, ref index) => match index.node {
+            ExprKind::Range(_, _, _) => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// A for loop which carries variables from one iteration to the next. Each chunk runs the prefix of all
+// its iterations as soon as it starts, then waits for the previous chunk to hand over the carried variables
+// before running the rest of each iteration, so they are only sent between chunks rather than iterations.
+struct CarriedIterations {
+    prefix_stmts: Vec<Stmt>,
+    tail_stmts: Vec<Stmt>,
+    locals: Environment, // Loop bindings and locals the prefix of an iteration leaves for the rest of it
+    carried: Environment,
+}
+
+// Splits the iterations of a for loop between threads, see LoopSplit for how they are split.
+// Reduction variables are accumulated privately by each thread and combined once the loop is over,
+// independently indexed variables are shared or copied, see IndexAccess, and variables which are only
+// read are copied to each chunk. Anything else is carried between chunks, see CarriedIterations.
+fn parallel_for_loop(cx: &mut ExtCtxt, stmtid: StmtID, split: LoopSplit, loop_expr: &Expr, body: Block, reductions: &Vec<(PathName, ReductionKind)>, indexed: &Vec<(PathName, IndexAccess)>, shared: &Environment, carried: Option<CarriedIterations>, num_chunks: usize) -> Stmt {
+    let (loop_pat, iter_expr, label) = match loop_expr.node {
+        ExprKind::ForLoop(ref a, ref b, _, ref c) => (a.clone(), b.clone(), c.clone()),
+        _ => panic!("Unexpected ExprKind in parallel for loop: {:?}", loop_expr.node),
+    };
+    let items = forloop_ident(&stmtid, "items");
+    let chunk_size = forloop_ident(&stmtid, "chunk_size");
+    let chunk = forloop_ident(&stmtid, "chunk");
+    let chunks = forloop_ident(&stmtid, "chunks");
+    let threads = forloop_ident(&stmtid, "threads");
+    let thread = forloop_ident(&stmtid, "thread");
+    let prefixes = forloop_ident(&stmtid, "prefixes");
+    let order_send = forloop_ident(&stmtid, "order_send");
+    let order_send_0 = forloop_ident(&stmtid, "order_send_0");
+    let order_receive = forloop_ident(&stmtid, "order_receive");
+    let order_receive_0 = forloop_ident(&stmtid, "order_receive_0");
+    let order_receive_i = forloop_ident(&stmtid, "order_receive_i");
+    let order_receive_new = forloop_ident(&stmtid, "order_receive_new");
+
+    let mut chunk_stmts = vec![];
+    let mut recv_stmts = vec![];
+
+    // Each chunk starts its reductions from the identity, the partial results are combined in chunk order
+    let mut reduction_vars = vec![];
+    let mut partial_vars = vec![];
+    let mut combine_stmts = vec![];
+    let (mut needs_zero, mut needs_one) = (false, false);
+    for &(ref var, kind) in reductions {
+        let ident = var[0];
+        let init = forloop_ident(&stmtid, &format!("{}_init", ident.name));
+        let partial = forloop_ident(&stmtid, &format!("{}_partial", ident.name));
+        let (init_stmt, combine_stmt) = match kind {
+            ReductionKind::Add => {
+                needs_zero = true;
+                (quote_stmt!(cx, let $init = reduction_zero(&$ident);), quote_stmt!(cx, $ident += $partial;))
+            },
+            ReductionKind::Mul => {
+                needs_one = true;
+                (quote_stmt!(cx, let $init = reduction_one(&$ident);), quote_stmt!(cx, $ident *= $partial;))
+            },
+            ReductionKind::Push |
+            ReductionKind::Insert => {
+                needs_zero = true;
+                (quote_stmt!(cx, let $init = reduction_zero(&$ident);), quote_stmt!(cx, $ident.extend($partial);))
+            },
+            // Starting from the current value is fine as max and min are idempotent
+            ReductionKind::Max => (quote_stmt!(cx, let $init = $ident.clone();), quote_stmt!(cx, $ident = $ident.max($partial);)),
+            ReductionKind::Min => (quote_stmt!(cx, let $init = $ident.clone();), quote_stmt!(cx, $ident = $ident.min($partial);)),
+        };
+        chunk_stmts.push(init_stmt.unwrap());
+        recv_stmts.push(quote_stmt!(cx, let mut $ident = $init;).unwrap());
+        combine_stmts.push(combine_stmt.unwrap());
+        reduction_vars.push(ident);
+        partial_vars.push(partial);
+    }
+
+    // Read only variables are shared between the chunks. Written variables are copied to each chunk,
+    // then the elements a chunk wrote are copied back, which is safe as no two iterations write the same element
+    let mut share_stmts = vec![];
+    let mut unshare_stmts = vec![];
+    let mut copyback_stmts = vec![];
+    let mut returned_env = Environment::empty();
+    for &(ref var, ref access) in indexed {
+        let ident = var[0];
+        match access {
+            &IndexAccess::ReadOnly => {
+                let shared = forloop_ident(&stmtid, &format!("{}_shared", ident.name));
+                share_stmts.push(quote_stmt!(cx, let $shared = ::std::sync::Arc::new($ident);).unwrap());
+                chunk_stmts.push(quote_stmt!(cx, let $ident = $shared.clone();).unwrap());
+                unshare_stmts.push(quote_stmt!(cx, let $ident = ::std::sync::Arc::try_unwrap($shared).ok().unwrap();).unwrap());
+                returned_env.merge(Environment::new(vec![var.clone()]));
+            },
+            &IndexAccess::DisjointWrites(ref subscripts) => {
+                let init = forloop_ident(&stmtid, &format!("{}_init", ident.name));
+                let partial = forloop_ident(&stmtid, &format!("{}_partial", ident.name));
+                chunk_stmts.push(quote_stmt!(cx, let $init = $ident.clone();).unwrap());
+                recv_stmts.push(quote_stmt!(cx, let mut $ident = $init;).unwrap());
+                for subscript in subscripts {
+                    copyback_stmts.push(quote_stmt!(cx, $ident[$subscript] = $partial[$subscript].clone();).unwrap());
+                }
+                reduction_vars.push(ident);
+                partial_vars.push(partial);
+            },
+        }
+    }
+    if copyback_stmts.len() > 0 {
+        // Remember which iterations each chunk ran
+        chunk_stmts.push(quote_stmt!(cx, $chunks.push($chunk.clone());).unwrap());
+    }
+
+    // Any other variable the body reads is copied to each chunk
+    for var in shared.clone().into_iter() {
+        let ident = var[0];
+        chunk_stmts.push(quote_stmt!(cx, let $ident = $ident.clone();).unwrap());
+    }
+
+    // Thread which runs the iterations of its chunk
+    let chunk_expr = match split {
+        LoopSplit::Slice(_) => quote_expr!(cx, $chunk.iter()),
+        LoopSplit::Chunks => quote_expr!(cx, $chunk),
+    };
+    let mut thread_stmts = recv_stmts;
+    match carried {
+        Some(ref iterations) => {
+            // Run the prefix of every iteration, keeping what the rest of the iteration needs
+            let localsexpr = envtuple_expr(cx, &iterations.locals);
+            let localspat = envtuple_pat(cx, &iterations.locals);
+            let mut prefix_stmts = iterations.prefix_stmts.clone();
+            prefix_stmts.push(quote_stmt!(cx, $prefixes.push($localsexpr);).unwrap());
+            let prefix_block = create_block(cx, prefix_stmts, None);
+            let tail_block = create_block(cx, iterations.tail_stmts.clone(), None);
+            thread_stmts.push(quote_stmt!(cx, let mut $prefixes = vec![];).unwrap());
+            thread_stmts.push(quote_stmt!(cx, for $loop_pat in $chunk_expr $prefix_block).unwrap());
+
+            // Then the rest of each iteration in order, once the previous chunk has finished with the carried variables
+            let carriedpat = envtuple_pat(cx, &iterations.carried);
+            let carriedexpr = envtuple_expr(cx, &iterations.carried);
+            thread_stmts.push(quote_stmt!(cx, let $carriedpat = $order_receive.recv().unwrap();).unwrap());
+            thread_stmts.push(quote_stmt!(cx, for $localspat in $prefixes $tail_block).unwrap());
+            thread_stmts.push(quote_stmt!(cx, $order_send.send($carriedexpr).unwrap();).unwrap());
+
+            chunk_stmts.push(quote_stmt!(cx, let ($order_send, $order_receive_new) = ::std::sync::mpsc::channel();).unwrap());
+            chunk_stmts.push(quote_stmt!(cx, let $order_receive = ::std::mem::replace(&mut $order_receive_i, $order_receive_new);).unwrap());
+        },
+        None => {
+            // Each chunk runs the original loop over its share of the iterations
+            let mut chunk_loop_expr = loop_expr.clone();
+            chunk_loop_expr.node = ExprKind::ForLoop(loop_pat.clone(), chunk_expr, P(body), label);
+            let chunk_loop_expr = P(chunk_loop_expr);
+            thread_stmts.push(quote_stmt!(cx, $chunk_loop_expr;).unwrap());
+        },
+    }
+    if reduction_vars.len() > 0 {
+        let reductionexpr = identtuple_expr(cx, &reduction_vars);
+        thread_stmts.push(quote_stmt!(cx, $reductionexpr).unwrap());
+    }
+    let thread_block = create_block(cx, thread_stmts, None);
+    chunk_stmts.push(quote_stmt!(cx, let $thread = ::std::thread::spawn(move || $thread_block);).unwrap());
+    chunk_stmts.push(quote_stmt!(cx, $threads.push($thread);).unwrap());
+
+    // Hand out the iterations to each chunk
+    let mut items_stmts = vec![];
+    let mut split_stmts = vec![];
+    let mut chunk_preamble = match split {
+        LoopSplit::Chunks => {
+            // Collected outside of the split block, before any variables are shared
+            items_stmts.push(quote_stmt!(cx, let mut $items = ($iter_expr).into_iter().collect::<Vec<_>>().into_iter();).unwrap());
+            items_stmts.push(quote_stmt!(cx, let $chunk_size = ::std::cmp::max(1, ($items.len() + $num_chunks - 1) / $num_chunks);).unwrap());
+            vec![quote_stmt!(cx, let $chunk: Vec<_> = $items.by_ref().take($chunk_size).collect();).unwrap()]
+        },
+        LoopSplit::Slice(ref slice_expr) => {
+            let start = forloop_ident(&stmtid, "start");
+            let end = forloop_ident(&stmtid, "end");
+            split_stmts.push(quote_stmt!(cx, let $items = &($slice_expr)[..];).unwrap());
+            split_stmts.push(quote_stmt!(cx, let $chunk_size = ::std::cmp::max(1, ($items.len() + $num_chunks - 1) / $num_chunks);).unwrap());
+            split_stmts.push(quote_stmt!(cx, let mut $start = 0;).unwrap());
+            vec![
+                quote_stmt!(cx, let $end = ::std::cmp::min($items.len(), $start + $chunk_size);).unwrap(),
+                quote_stmt!(cx, let $chunk = $items[$start..$end].to_vec();).unwrap(),
+                quote_stmt!(cx, $start = $end;).unwrap(),
+            ]
+        },
+    };
+    chunk_preamble.append(&mut chunk_stmts);
+    let chunk_block = create_block(cx, chunk_preamble, None);
+    match split {
+        LoopSplit::Chunks => {
+            split_stmts.push(quote_stmt!(cx, while $items.len() > 0 $chunk_block).unwrap());
+        },
+        LoopSplit::Slice(_) => {
+            let start = forloop_ident(&stmtid, "start");
+            split_stmts.push(quote_stmt!(cx, while $start < $items.len() $chunk_block).unwrap());
+        },
+    }
+    let split_block = create_block(cx, split_stmts, None);
+
+    // Construct a block containing start, split, end stmts
+    let mut start_end_stmts = vec![];
+    if needs_zero {
+        start_end_stmts.push(quote_stmt!(cx, fn reduction_zero<T: Default>(_: &T) -> T { T::default() }).unwrap());
+    }
+    if needs_one {
+        start_end_stmts.push(quote_stmt!(cx, fn reduction_one<T: ::std::iter::Product>(_: &T) -> T { ::std::iter::empty().product() }).unwrap());
+    }
+    start_end_stmts.push(quote_stmt!(cx, let mut $threads = vec![];).unwrap());
+    if copyback_stmts.len() > 0 {
+        start_end_stmts.push(quote_stmt!(cx, let mut $chunks = vec![];).unwrap());
+    }
+    start_end_stmts.append(&mut items_stmts);
+    start_end_stmts.append(&mut share_stmts);
+    if let Some(ref iterations) = carried {
+        // The first chunk is released by the carried variables, the last returns them
+        let carriedexpr = envtuple_expr(cx, &iterations.carried);
+        start_end_stmts.push(quote_stmt!(cx, let ($order_send_0, $order_receive_0) = ::std::sync::mpsc::channel();).unwrap());
+        start_end_stmts.push(quote_stmt!(cx, let mut $order_receive_i = $order_receive_0;).unwrap());
+        start_end_stmts.push(quote_stmt!(cx, $order_send_0.send($carriedexpr).unwrap();).unwrap());
+    }
+    start_end_stmts.push(quote_stmt!(cx, $split_block).unwrap());
+    if reduction_vars.len() > 0 {
+        let partialpat = identtuple_pat(cx, &partial_vars);
+        let mut join_stmts = vec![quote_stmt!(cx, let $partialpat = $thread.join().unwrap();).unwrap()];
+        join_stmts.append(&mut combine_stmts);
+        if copyback_stmts.len() > 0 {
+            let copyback_block = create_block(cx, copyback_stmts, None);
+            join_stmts.push(quote_stmt!(cx, for $loop_pat in $chunk $copyback_block).unwrap());
+            let join_block = create_block(cx, join_stmts, None);
+            start_end_stmts.push(quote_stmt!(cx, for ($thread, $chunk) in $threads.into_iter().zip($chunks) $join_block).unwrap());
+        } else {
+            let join_block = create_block(cx, join_stmts, None);
+            start_end_stmts.push(quote_stmt!(cx, for $thread in $threads $join_block).unwrap());
+        }
+    } else {
+        start_end_stmts.push(quote_stmt!(cx, for $thread in $threads { $thread.join().unwrap(); }).unwrap());
+    }
+    start_end_stmts.append(&mut unshare_stmts);
+    if let Some(ref iterations) = carried {
+        let carriedpat = envtuple_pat(cx, &iterations.carried);
+        start_end_stmts.push(quote_stmt!(cx, let $carriedpat = $order_receive_i.recv().unwrap();).unwrap());
+        returned_env.merge(iterations.carried.clone());
+    }
+    if returned_env.len() > 0 {
+        let envexpr = envtuple_expr(cx, &returned_env);
+        start_end_stmts.push(quote_stmt!(cx, $envexpr).unwrap());
+    }
+    let start_end_block = create_block(cx, start_end_stmts, None);
+
+    // Combine start_end_block with a let statement
+    if returned_env.len() > 0 {
+        let envexpr = envtuple_pat(cx, &returned_env);
+        quote_stmt!(cx, let $envexpr = $start_end_block;).unwrap()
+    } else {
+        quote_stmt!(cx, $start_end_block;).unwrap()
+    }
+}
+
+fn whileloop_ident(stmtid: &StmtID, suffix: &str) -> Ident {
+    let &(lo, hi) = stmtid;
+    Ident::from_str(&format!("whileloop_{}_{}_{}", lo, hi, suffix))
+}
+
+// Finds control flow which leaves the loop body early, which a pipelined loop cannot follow
+struct LoopExitVisitor {
+    exits: bool,
+    loop_depth: usize,
+}
+
+impl<'a> Visitor<'a> for LoopExitVisitor {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        match expr.node {
+            ExprKind::Ret(_) |
+            ExprKind::Try(_) => self.exits = true,
+            ExprKind::Break(ref label, _) |
+            ExprKind::Continue(ref label) => {
+                // Unlabelled breaks in inner loops stay inside them
+                if self.loop_depth == 0 || label.is_some() {
+                    self.exits = true;
+                }
+            },
+            ExprKind::While(_, _, _) |
+            ExprKind::WhileLet(_, _, _, _) |
+            ExprKind::ForLoop(_, _, _, _) |
+            ExprKind::Loop(_, _) => {
+                self.loop_depth += 1;
+                visit::walk_expr(self, expr);
+                self.loop_depth -= 1;
+                return;
+            },
+            // Returns inside a closure only leave the closure
+            ExprKind::Closure(_, _, _, _, _) => return,
+            _ => {},
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_mac(&mut self, _mac: &'a Mac) {}
+}
+
+// Splits the loop body into a prefix which only uses the loop bindings, its own locals and the shared
+// variables that no iteration writes, and the rest of the body. Also returns the bindings and locals the
+// prefix leaves for the rest of the body. Returns None if the rest of the body cannot be rebuilt from the
+// original statements.
+fn split_loop_body(body_node: &DependencyNode, bindings: &Environment, shared: &Environment) -> Option<(Vec<Stmt>, Vec<Stmt>, Environment)> {
+    let subtree = match body_node {
+        &DependencyNode::Block(_, ref subtree, _, _) => subtree,
+        _ => return None,
+    };
+    let mut local_env = bindings.clone();
+    let mut prefix_stmts = vec![];
+    let mut tail_stmts = vec![];
+    for node in subtree {
+        let is_prefix = tail_stmts.len() == 0 && match node {
+            &DependencyNode::Expr(ref stmt, _, (ref inenv, ref outenv)) => {
+                let mut defined = Environment::empty();
+                if let StmtKind::Local(ref local) = stmt.node {
+                    defined = deconstructor::check_pattern(&mut vec![], &local.pat.node);
+                }
+                inenv.clone().into_iter().all(|var| local_env.contains(&var) || shared.contains(&var)) &&
+                outenv.clone().into_iter().all(|var| local_env.contains(&var) || shared.contains(&var) || defined.contains(&var))
+            },
+            // Macros may have side effects so stay in order
+            _ => false,
+        };
+        match node {
+            &DependencyNode::Expr(ref stmt, _, (_, ref outenv)) if is_prefix => {
+                local_env.merge(outenv.clone());
+                local_env.remove_env(shared.clone());
+                prefix_stmts.push(stmt.deref().clone());
+            },
+            &DependencyNode::Expr(ref stmt, _, _) |
+            &DependencyNode::Mac(ref stmt, _, _) => tail_stmts.push(stmt.deref().clone()),
+            // Inner blocks have been emptied out
+            _ => return None,
+        }
+    }
+    Some((prefix_stmts, tail_stmts, local_env))
+}
+
+// Runs the loop header on the current thread, handing each iteration to a worker through a channel.
+// The prefix of each iteration runs as soon as a worker receives it, while the tail waits for the tail
+// of the previous iteration, so carried variables and side effects happen in the original order.
+fn pipelined_while_loop(cx: &mut ExtCtxt, stmtid: StmtID, loop_expr: &Expr, bindings: &Environment, prefix_stmts: Vec<Stmt>, tail_stmts: Vec<Stmt>, carried_inenv: &Environment, header_uses_carried: bool, num_workers: usize) -> Stmt {
+    let work = whileloop_ident(&stmtid, "work");
+    let work_send = whileloop_ident(&stmtid, "work_send");
+    let senders = whileloop_ident(&stmtid, "senders");
+    let threads = whileloop_ident(&stmtid, "threads");
+    let thread = whileloop_ident(&stmtid, "thread");
+    let iteration = whileloop_ident(&stmtid, "iteration");
+    let iteration_bindings = whileloop_ident(&stmtid, "bindings");
+    let order_send = whileloop_ident(&stmtid, "order_send");
+    let order_send_0 = whileloop_ident(&stmtid, "order_send_0");
+    let order_receive = whileloop_ident(&stmtid, "order_receive");
+    let order_receive_0 = whileloop_ident(&stmtid, "order_receive_0");
+    let order_receive_i = whileloop_ident(&stmtid, "order_receive_i");
+    let order_receive_new = whileloop_ident(&stmtid, "order_receive_new");
+    let bindexpr = envtuple_expr(cx, bindings);
+    let bindpat = envtuple_pat(cx, bindings);
+
+    // Each worker runs the iterations it is given
+    let mut iteration_stmts = vec![quote_stmt!(cx, let $bindpat = $iteration_bindings;).unwrap()];
+    iteration_stmts.extend(prefix_stmts);
+    if carried_inenv.len() > 0 {
+        let envpat = envtuple_pat(cx, carried_inenv);
+        iteration_stmts.push(quote_stmt!(cx, let $envpat = $order_receive.recv().unwrap();).unwrap());
+    } else {
+        iteration_stmts.push(quote_stmt!(cx, $order_receive.recv().unwrap();).unwrap());
+    }
+    iteration_stmts.extend(tail_stmts);
+    let envexpr = envtuple_expr(cx, carried_inenv);
+    iteration_stmts.push(quote_stmt!(cx, $order_send.send($envexpr).unwrap();).unwrap());
+    let iteration_block = create_block(cx, iteration_stmts, None);
+    let worker_stmts = vec![
+        quote_stmt!(cx, for ($iteration_bindings, $order_receive, $order_send) in $work $iteration_block).unwrap(),
+    ];
+    let worker_block = create_block(cx, worker_stmts, None);
+
+    // The header stays on the current thread, with a body that hands out the iteration
+    let producer_stmts = vec![
+        quote_stmt!(cx, let ($order_send, $order_receive_new) = ::std::sync::mpsc::channel();).unwrap(),
+        quote_stmt!(cx, let $order_receive = ::std::mem::replace(&mut $order_receive_i, $order_receive_new);).unwrap(),
+        quote_stmt!(cx, $senders[$iteration % $senders.len()].send(($bindexpr, $order_receive, $order_send)).unwrap();).unwrap(),
+        quote_stmt!(cx, $iteration += 1;).unwrap(),
+    ];
+    let producer_block = create_block(cx, producer_stmts, None);
+    let mut producer_expr = loop_expr.clone();
+    producer_expr.node = match loop_expr.node {
+        ExprKind::While(ref a, _, ref b) => ExprKind::While(a.clone(), P(producer_block), b.clone()),
+        ExprKind::WhileLet(ref a, ref b, _, ref c) => ExprKind::WhileLet(a.clone(), b.clone(), P(producer_block), c.clone()),
+        _ => panic!("Unexpected ExprKind in pipelined loop: {:?}", loop_expr.node),
+    };
+    let producer_expr = P(producer_expr);
+
+    // The first iteration is released by the carried variables, the last returns them
+    let envexpr = envtuple_expr(cx, carried_inenv);
+    let release_stmt = quote_stmt!(cx, $order_send_0.send($envexpr).unwrap();).unwrap();
+    let mut start_end_stmts = vec![
+        quote_stmt!(cx, let ($order_send_0, $order_receive_0) = ::std::sync::mpsc::channel();).unwrap(),
+        quote_stmt!(cx, let mut $order_receive_i = $order_receive_0;).unwrap(),
+        quote_stmt!(cx, let mut $senders = vec![];).unwrap(),
+        quote_stmt!(cx, let mut $threads = vec![];).unwrap(),
+        quote_stmt!(cx, for _ in 0..$num_workers {
+            let ($work_send, $work) = ::std::sync::mpsc::channel();
+            $senders.push($work_send);
+            let $thread = ::std::thread::spawn(move || $worker_block);
+            $threads.push($thread);
+        }).unwrap(),
+    ];
+    if !header_uses_carried {
+        start_end_stmts.push(release_stmt.clone());
+    }
+    start_end_stmts.push(quote_stmt!(cx, let mut $iteration = 0;).unwrap());
+    start_end_stmts.push(quote_stmt!(cx, $producer_expr;).unwrap());
+    if header_uses_carried {
+        start_end_stmts.push(release_stmt);
+    }
+    // Closing the work channels lets the workers finish
+    start_end_stmts.push(quote_stmt!(cx, ::std::mem::drop($senders);).unwrap());
+    start_end_stmts.push(quote_stmt!(cx, for $thread in $threads { $thread.join().unwrap(); }).unwrap());
+    if carried_inenv.len() > 0 {
+        let envpat = envtuple_pat(cx, carried_inenv);
+        let envexpr = envtuple_expr(cx, carried_inenv);
+        start_end_stmts.push(quote_stmt!(cx, let $envpat = $order_receive_i.recv().unwrap();).unwrap());
+        start_end_stmts.push(quote_stmt!(cx, $envexpr).unwrap());
+    }
+    let start_end_block = create_block(cx, start_end_stmts, None);
+
+    // Combine start_end_block with a let statement
+    if carried_inenv.len() > 0 {
+        let envexpr = envtuple_pat(cx, carried_inenv);
+        quote_stmt!(cx, let $envexpr = $start_end_block;).unwrap()
+    } else {
+        quote_stmt!(cx, $start_end_block;).unwrap()
+    }
+}
+
+// The threads a loop is split between, which are taken from spare_threads when max_threads is set.
+// Each copy of the body would spawn its own threads, so a body which spawns threads is not split.
+fn take_loop_threads(config: &Config, body: &Block, spare_threads: &mut Option<usize>) -> Option<usize> {
+    let spare = match *spare_threads {
+        Some(spare) => spare,
+        None => return Some(config.num_loop_chunks()),
+    };
+    let mut spawn_visitor = SpawnVisitor { spawns: false };
+    spawn_visitor.visit_block(body);
+    if spawn_visitor.spawns {
+        log_debug!(logging::RECONSTRUCTOR, "Unable to parallelise loop, body spawns threads and max_threads is set");
+        return None;
+    }
+    let threads = ::std::cmp::min(config.num_loop_chunks(), spare);
+    if threads == 0 {
+        log_debug!(logging::RECONSTRUCTOR, "Unable to parallelise loop, no threads are left under max_threads = {}", config.max_threads);
+        return None;
+    }
+    *spare_threads = Some(spare - threads);
+    Some(threads)
+}
+
+// Finds calls to thread::spawn, which the body of a loop has when its inner loops or blocks were parallelised
+struct SpawnVisitor {
+    spawns: bool,
+}
+
+impl<'a> Visitor<'a> for SpawnVisitor {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        if let ExprKind::Call(ref func, _) = expr.node {
+            if let ExprKind::Path(_, ref path) = func.node {
+                let names: Vec<String> = path.segments.iter().map(|segment| segment.identifier.name.to_string()).collect();
+                if names.ends_with(&["thread".to_owned(), "spawn".to_owned()]) {
+                    self.spawns = true;
+                }
+            }
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_mac(&mut self, _mac: &'a Mac) {}
+}
+
+// Whether a loop does enough work in total to be worth splitting between threads
+fn loop_worth_parallelising<'a>(config: &Config, exprstmt: &Stmt, inner_schedule: &Schedule<'a>) -> bool {
+    let body_work = inner_schedule.list().iter().fold(0u32, |total, tree| total.saturating_add(tree.get_work()));
+    let work = cost_model::loop_work(exprstmt, body_work);
+    if work <= config.min_thread_work {
+        log_debug!(logging::RECONSTRUCTOR, "Loop is not worth parallelising, estimated work {} <= {}", work, config.min_thread_work);
+        return false;
+    }
+    true
+}
+
+// A rewrite which fails verification is a mistake in the reconstructor, so the loop is left sequential
+fn loop_rewrite_verified(stmtid: StmtID, rewrite: &LoopRewrite) -> bool {
+    match verifier::verify_loop_rewrite(stmtid, rewrite) {
+        Ok(()) => true,
+        Err(errors) => {
+            for (_, error) in errors {
+                log_warn!(logging::RECONSTRUCTOR, "Leaving loop {:?} sequential: {}", stmtid, error);
+            }
+            false
+        },
+    }
+}
+
+// Checks whether a while loop can be pipelined, returning the pipelined statement if so
+fn try_pipeline_while_loop<'a>(config: &Config, cx: &mut ExtCtxt, exprstmt: &Stmt, loop_expr: &Expr, exprblock: &Block, inner_schedule: &Schedule<'a>, inenv: &Environment, spare_threads: &mut Option<usize>) -> Option<Stmt> {
+    if !config.parallel_while_loops || !loop_worth_parallelising(config, exprstmt, inner_schedule) {
+        return None;
+    }
+
+    // Everything the header needs and what the loop binds each iteration
+    let (header_env, bindings) = match loop_expr.node {
+        ExprKind::While(ref cond, _, None) => (deconstructor::check_expr(&mut vec![], cond), Environment::empty()),
+        ExprKind::WhileLet(ref pats, ref expr1, _, None) if pats.len() == 1 => {
+            (deconstructor::check_expr(&mut vec![], expr1), deconstructor::pattern_bindings(&pats[0].node))
+        },
+        _ => return None,
+    };
+    let (header_inenv, header_outenv) = header_env;
+
+    // The body cannot leave the loop, as the header would have already moved on
+    let mut exit_visitor = LoopExitVisitor { exits: false, loop_depth: 0 };
+    exit_visitor.visit_block(exprblock);
+    if exit_visitor.exits {
+        log_debug!(logging::RECONSTRUCTOR, "Unable to pipeline while loop, body leaves the loop early");
+        return None;
+    }
+
+    // The header runs ahead of the body, so they cannot share any variables it changes
+    let (_, ref body_outenv) = inner_schedule.get_env();
+    let conflicts = header_inenv.clone().into_iter().any(|var| body_outenv.contains(&var)) ||
+                    header_outenv.clone().into_iter().any(|var| inenv.contains(&var) || body_outenv.contains(&var));
+    if conflicts {
+        log_debug!(logging::RECONSTRUCTOR, "Unable to pipeline while loop, header depends on the body");
+        return None;
+    }
+
+    // Everything the body uses, except the loop bindings, is carried between iterations
+    let mut carried_inenv = inenv.clone();
+    carried_inenv.remove_env(bindings.clone());
+    let header_uses_carried = carried_inenv.clone().into_iter().any(|var| header_inenv.contains(&var));
+
+    // Without an unordered prefix the whole body runs in order
+    let body_node = inner_schedule.list().iter().filter_map(|tree| tree.get_spanning_tree()).map(|tree| tree.node).next()?;
+    let (prefix_stmts, tail_stmts) = match split_loop_body(body_node, &bindings, &Environment::empty()) {
+        Some((prefix_stmts, tail_stmts, _)) => (prefix_stmts, tail_stmts),
+        None => {
+            // The rebuilt body would move its synclines into every worker
+            if inner_schedule.get_all_synclines().len() > 0 {
+                log_debug!(logging::RECONSTRUCTOR, "Unable to pipeline while loop, body has synclines");
+                return None;
+            }
+            (vec![], exprblock.stmts.clone())
+        },
+    };
+    log_debug!(logging::RECONSTRUCTOR, "Possible WHILELOOP Pipelining: {} unordered statements, carried {:?}", prefix_stmts.len(), carried_inenv);
+    let body_nodes = vec![body_node];
+    let verified = loop_rewrite_verified(stmtID!(exprstmt), &LoopRewrite {
+        body: &body_nodes,
+        uses: &carried_inenv,
+        header: &header_inenv,
+        shared: &Environment::empty(),
+        split: &Environment::empty(),
+        carried: &carried_inenv,
+        unordered: &prefix_stmts,
+    });
+    if !verified {
+        return None;
+    }
+
+    let num_workers = take_loop_threads(config, exprblock, spare_threads)?;
+    Some(pipelined_while_loop(cx, stmtID!(exprstmt), loop_expr, &bindings, prefix_stmts, tail_stmts, &carried_inenv, header_uses_carried, num_workers))
+}
+
+// Checks whether the iterations of a for loop can be split between threads, returning the parallel statement if so
+fn try_parallel_for_loop<'a>(config: &Config, cx: &mut ExtCtxt, exprstmt: &Stmt, loop_expr: &Expr, exprblock: &Block, inner_schedule: &Schedule<'a>, inenv: &Environment, accumulators: &Accumulators, spare_threads: &mut Option<usize>) -> Option<Stmt> {
+    if !config.parallel_for_loops || !loop_worth_parallelising(config, exprstmt, inner_schedule) {
+        return None;
+    }
+    let (a, b) = match loop_expr.node {
+        ExprKind::ForLoop(ref a, ref b, _, _) => (a, b),
+        _ => return None,
+    };
+
+    // Only attempt iterators that can be split between threads
+    let split = loop_split(b.deref())?;
+
+    // Each chunk runs its iterations separately, so the body cannot leave the loop
+    let mut exit_visitor = LoopExitVisitor { exits: false, loop_depth: 0 };
+    exit_visitor.visit_block(exprblock);
+    if exit_visitor.exits {
+        log_debug!(logging::RECONSTRUCTOR, "Unable to parallelise for loop, body leaves the loop early");
+        return None;
+    }
+
+    // Every chunk gets its own copy of the body, which would move the synclines of the function into the first chunk
+    if inner_schedule.get_all_synclines().len() > 0 {
+        log_debug!(logging::RECONSTRUCTOR, "Unable to parallelise for loop, body has synclines");
+        return None;
+    }
+
+    // Everything the body uses, except the loop variable
+    let mut candidates = inenv.clone();
+    let bindings = deconstructor::pattern_bindings(&a.deref().node);
+    candidates.remove_env(bindings.clone());
+    let uses = candidates.clone();
+
+    // Reductions, which each chunk can accumulate separately. Any other accumulation, such as adding
+    // floats or strings, is carried between iterations so it is still made in order.
+    let body_nodes: Vec<&DependencyNode> = inner_schedule.list().iter().filter_map(|tree| tree.get_spanning_tree()).map(|tree| tree.node).collect();
+    let reductions = reductions::find_reductions(&body_nodes, &candidates, accumulators);
+    for &(ref var, _) in &reductions {
+        candidates.remove_env(Environment::new(vec![var.clone()]));
+    }
+
+    // Variables only indexed by the loop variable, without two iterations touching the same element
+    let mut indexed = vec![];
+    if let ExprKind::Range(_, _, _) = b.deref().node {
+        if let Some(loopvar) = index_analysis::loop_variable(a) {
+            indexed = index_analysis::find_independent_indexing(&body_nodes, loopvar, &candidates);
+        }
+    }
+    for &(ref var, _) in &indexed {
+        candidates.remove_env(Environment::new(vec![var.clone()]));
+    }
+
+    // Variables no iteration writes are shared by the chunks, the rest are carried between iterations
+    let written = deconstructor::written_variables(&body_nodes);
+    let shared: Environment = candidates.clone().into_iter().filter(|var| !written.contains(var)).collect();
+    let carried: Environment = candidates.into_iter().filter(|var| written.contains(var)).collect();
+
+    let carried_iterations = if carried.len() > 0 {
+        // The prefix of each iteration can run in any chunk order, as it only reads variables no iteration writes
+        let mut readable = shared.clone();
+        for &(ref var, ref access) in &indexed {
+            if let &IndexAccess::ReadOnly = access {
+                readable.merge(Environment::new(vec![var.clone()]));
+            }
+        }
+        let body_node = *body_nodes.first()?;
+        let (prefix_stmts, tail_stmts, locals) = match split_loop_body(body_node, &bindings, &readable) {
+            Some(split) => split,
+            None => {
+                log_debug!(logging::RECONSTRUCTOR, "Unable to parallelise for loop, carried {:?} and the body cannot be split", carried);
+                return None;
+            },
+        };
+        if prefix_stmts.len() == 0 {
+            log_debug!(logging::RECONSTRUCTOR, "Unable to parallelise for loop, every statement waits for carried {:?}", carried);
+            return None;
+        }
+        Some(CarriedIterations {
+            prefix_stmts: prefix_stmts,
+            tail_stmts: tail_stmts,
+            locals: locals,
+            carried: carried,
+        })
+    } else {
+        None
+    };
+    log_debug!(logging::RECONSTRUCTOR, "Possible FORLOOP Parallelisation: reductions {:?}, indexed {:?}, shared {:?}, carried {:?}",
+               reductions, indexed, shared, carried_iterations.as_ref().map(|iterations| &iterations.carried));
+
+    // Without carried variables the whole body runs in any chunk order
+    let split_vars: Environment = reductions.iter().map(|&(ref var, _)| var.clone()).chain(indexed.iter().map(|&(ref var, _)| var.clone())).collect();
+    let verified = loop_rewrite_verified(stmtID!(exprstmt), &LoopRewrite {
+        body: &body_nodes,
+        uses: &uses,
+        header: &Environment::empty(),
+        shared: &shared,
+        split: &split_vars,
+        carried: carried_iterations.as_ref().map(|iterations| &iterations.carried).unwrap_or(&Environment::empty()),
+        unordered: carried_iterations.as_ref().map(|iterations| &iterations.prefix_stmts).unwrap_or(&exprblock.stmts),
+    });
+    if !verified {
+        return None;
+    }
+
+    let num_chunks = take_loop_threads(config, exprblock, spare_threads)?;
+    Some(parallel_for_loop(cx, stmtID!(exprstmt), split, loop_expr, exprblock.clone(), &reductions, &indexed, &shared, carried_iterations, num_chunks))
+}
+
+fn exprblock_into_statement<'a>(config: &Config, cx: &mut ExtCtxt, exprstmt: Stmt, inner_schedule: &Schedule<'a>, inenv: &Environment, all_synclines: &Vec<(StmtID, StmtID, &Environment)>, accumulators: &Accumulators, spare_threads: &mut Option<usize>) -> Stmt {
 
     // Convert inner schedules into blocks
     let mut inner_blocks_stmts = vec![];
     for inner_schedule_tree in inner_schedule.list() {
         let inner_schedule: Vec<ScheduleTree<'a>> = vec![inner_schedule_tree.clone()];
-        let mut inner_block_stmt = spawn_from_schedule_helper(config, cx, &inner_schedule, all_synclines);
+        let mut inner_block_stmt = spawn_from_schedule_helper(config, cx, &inner_schedule, all_synclines, accumulators, spare_threads, false);
         inner_blocks_stmts.append(&mut inner_block_stmt);
     }
     let mut inner_blocks = unwrap_stmts_to_blocks(&inner_blocks_stmts);
@@ -398,14 +1286,23 @@ fn exprblock_into_statement<'a>(config: &Config, cx: &mut ExtCtxt, exprstmt: Stm
         ExprKind::While(ref a, ref empty_block, ref b) => {
             let exprblock = inner_blocks.remove(0);
             assert!(stmtID!(empty_block) == stmtID!(exprblock), format!("stmtID!({:?}) == stmtID!({:?})", stmtID!(empty_block), stmtID!(exprblock)));
+            // Special Case: Return early
+            if let Some(stmt) = try_pipeline_while_loop(config, cx, &exprstmt, expr, &exprblock, inner_schedule, inenv, spare_threads) {
+                return stmt;
+            }
             ExprKind::While(a.clone(), P(exprblock), b.clone())
         } ,
         ExprKind::WhileLet(ref a, ref b, ref empty_block, ref c) => {
             let exprblock = inner_blocks.remove(0);
             assert!(stmtID!(empty_block) == stmtID!(exprblock), format!("stmtID!({:?}) == stmtID!({:?})", stmtID!(empty_block), stmtID!(exprblock)));
+            // Special Case: Return early
+            if let Some(stmt) = try_pipeline_while_loop(config, cx, &exprstmt, expr, &exprblock, inner_schedule, inenv, spare_threads) {
+                return stmt;
+            }
             ExprKind::WhileLet(a.clone(), b.clone(), P(exprblock), c.clone())
         },
         ExprKind::Loop(ref empty_block, ref a) => {
+            // The exit is somewhere in the body, so the iterations cannot be handed out ahead of time
             let exprblock = inner_blocks.remove(0);
             assert!(stmtID!(empty_block) == stmtID!(exprblock), format!("stmtID!({:?}) == stmtID!({:?})", stmtID!(empty_block), stmtID!(exprblock)));
             ExprKind::Loop(P(exprblock), a.clone())
@@ -462,170 +1359,11 @@ fn exprblock_into_statement<'a>(config: &Config, cx: &mut ExtCtxt, exprstmt: Stm
         // Special parallel loop iterations (maybe)
         ExprKind::ForLoop(ref a, ref b, ref empty_block, ref c) => {
             let exprblock = inner_blocks.remove(0);
-            eprintln!("exprblock in forloop: {:?}", exprblock);
+            log_trace!(logging::RECONSTRUCTOR, "exprblock in forloop: {:?}", exprblock);
             assert!(stmtID!(empty_block) == stmtID!(exprblock), format!("stmtID!({:?}) == stmtID!({:?})", stmtID!(empty_block), stmtID!(exprblock)));
-            if config.parallel_for_loops {
-                // Only attempt for _ in 0..10 {} kind (as copy trait probably implemented?)
-                if let ExprKind::Range(_,_,_) = b.deref().node {
-                    // Create a forward and backward mutable inenv without the a variable
-                    let mut forward_inenv = inenv.clone();
-                    let a_env = deconstructor::check_pattern(&mut vec![], &a.deref().node);
-                    forward_inenv.remove_env(a_env);
-                    eprintln!("Possible FORLOOP Parallelisation: {:?}", forward_inenv);
-                    let mut backward_inenv = vec![];
-                    let mut return_inenv = forward_inenv.clone();
-                    let mut additional_synclines = vec![];
-
-                    // Create a copy of inner_schedule as we might change our mind on for loop parallelisation
-                    let mut adapted_inner_schedule = inner_schedule.clone();
-
-                    // Start from top of subtree and add inenv as dependencies
-                    // Do not enter ExprBlocks as they are not guarenteed to be run
-                    adapted_inner_schedule.navigate_forward_avoid_exprblock(&mut (&mut forward_inenv, &mut backward_inenv), &|&mut (ref mut forward_inenv, ref mut backward_inenv), tree| {
-                        let menv = if let Some(spanning_tree) = tree.get_spanning_tree() {
-                            let &(ref env, _) = spanning_tree.node.get_env();
-                            Some(env.clone())
-                        } else {
-                            None
-                        };
-                        if let Some(env) = menv {
-                            for var in env.into_iter() {
-                                if forward_inenv.contains(&var) {
-                                    // Found first use of var: add a dependency syncline
-                                    let syncline_env = Environment::new(vec![var.clone()]);
-                                    forward_inenv.remove_env(syncline_env.clone());
-                                    backward_inenv.push((var, tree.get_spanning_tree().unwrap().node.get_stmtid()));
-                                    tree.get_deps_mut().unwrap().push((stmtID!(exprstmt), syncline_env));
-                                }
-                            }
-                        }
-                    });
-                    assert!(forward_inenv.len() == 0, format!("Did not consume all of forward_inenv: {:?}", forward_inenv));
-
-                    // Start from bottom of subtree and add inenv as releasing
-                    adapted_inner_schedule.navigate_backward_avoid_exprblock(&mut (&mut backward_inenv, &mut additional_synclines), &|&mut (ref mut backward_inenv, ref mut additional_synclines), tree| {
-                        let menv = if let Some(spanning_tree) = tree.get_spanning_tree() {
-                            let &(ref env, _) = spanning_tree.node.get_env();
-                            Some(env.clone())
-                        } else {
-                            None
-                        };
-                        if let Some(ref env) = menv {
-                            for ref var in env.clone().into_iter() {
-                                let mut mdep_stmtid = None;
-                                for &(ref this_var, ref this_dep_stmtid) in backward_inenv.iter() {
-                                    if Environment::new(vec![var.clone()]).contains(this_var) {
-                                        mdep_stmtid = Some(this_dep_stmtid);
-                                    }
-                                }
-
-                                if let Some(dep_stmtid) = mdep_stmtid {
-                                    // Variable was found in backward_inenv: Add a SyncTo line
-                                    let from = stmtID!(exprstmt);
-                                    let to = *dep_stmtid;
-                                    let syncline_env = Environment::new(vec![var.clone()]);
-                                    additional_synclines.push((from, to, syncline_env.clone()));
-                                    let syncline = ScheduleTree::SyncTo(from, to, syncline_env);
-                                    tree.get_spanning_tree_mut().unwrap().children.insert(0,syncline);
-                                }
-                            }
-                            // Remove from backward_inenv as we only need to find the var once
-                            backward_inenv.retain(|&(ref elem, _)| !env.contains(elem));
-                        }
-                    });
-                    let adapted_inner_schedule_json = match serde_json::to_string_pretty(&adapted_inner_schedule) {
-                        Ok(obj) => obj,
-                        Err(why) => panic!("Unable to convert AutoParallelise to JSON: {}", why),
-                    };
-                    eprintln!("Adpated_Inner_Schedule:\n{}\n", adapted_inner_schedule_json);
-                    assert!(backward_inenv.len() == 0, format!("Did not consume all of backward_inenv: {:?}", backward_inenv));
-
-                    // TODO: If a dependency is first line, and release is last line, do not parallelise (not worth it)
-
-                    // Add some code before the loop, at the beginning of each iteration, and at the end to deal with synclines between iterations
-                    let mut start_stmts = vec![];
-                    let mut iteration_stmts = vec![];
-                    let mut send_stmts = vec![];
-                    let mut collection_stmts = vec![];
-                    for &(ref stmtid1, ref stmtid2, ref env) in &additional_synclines {
-                        let line_name = syncline_name(stmtid1, stmtid2, env);
-                        // Start
-                        let sx0 = Ident::from_str(&format!("{}_send_0", line_name));
-                        let rx0 = Ident::from_str(&format!("{}_receive_0", line_name));
-                        let rxi = Ident::from_str(&format!("{}_receive_i", line_name));
-                        start_stmts.push(quote_stmt!(cx, let ($sx0, $rx0) = std::sync::mpsc::channel();).unwrap());
-                        start_stmts.push(quote_stmt!(cx, let mut $rxi = $rx0;).unwrap());
-                        // Iteration
-                        let sx = Ident::from_str(&format!("{}_send", line_name));
-                        let rx = Ident::from_str(&format!("{}_receive", line_name));
-                        let rxn = Ident::from_str(&format!("{}_receive_new", line_name));
-                        iteration_stmts.push(quote_stmt!(cx, let ($sx, $rxn) = std::sync::mpsc::channel();).unwrap());
-                        iteration_stmts.push(quote_stmt!(cx, let $rx = $rxi;).unwrap());
-                        iteration_stmts.push(quote_stmt!(cx, $rxi = $rxn;).unwrap());
-                        // Send
-                        let send_stmt = if env.len() > 0 {
-                            let envexpr = envtuple_expr(cx, env);
-                            quote_stmt!(cx, $sx0.send($envexpr).unwrap();).unwrap()
-                        } else {
-                            quote_stmt!(cx, $sx0.send(()).unwrap();).unwrap()
-                        };
-                        send_stmts.push(send_stmt);
-                        // Collection
-                        let collection_stmt = if env.len() > 0 {
-                            let envexpr = envtuple_pat(cx, env);
-                            quote_stmt!(cx, let $envexpr = $rxi.recv().unwrap();).unwrap()
-                        } else {
-                            quote_stmt!(cx, $rxi.recv().unwrap();).unwrap()
-                        };
-                        collection_stmts.push(collection_stmt);
-                    }
-
-                    // Create a new thread block out of new inner_schedule
-                    let mut adapted_all_synclines = adapted_inner_schedule.get_all_synclines();
-                    let mut adapted_inner_blocks_stmts = vec![];
-                    for inner_schedule_tree in adapted_inner_schedule.list() {
-                        let inner_schedule: Vec<ScheduleTree<'a>> = vec![inner_schedule_tree.clone()];
-                        let mut inner_block_stmt = spawn_from_schedule_helper(config, cx, &inner_schedule, &adapted_all_synclines);
-                        adapted_inner_blocks_stmts.append(&mut inner_block_stmt);
-                    }
-                    let adapted_inner_block = create_block(cx, adapted_inner_blocks_stmts, None);
-                    let thread_block = quote_stmt!(cx, ::std::thread::spawn(move || $adapted_inner_block);).unwrap();
-                    iteration_stmts.push(thread_block);
-                    iteration_stmts.push(quote_stmt!(cx, ()).unwrap());
-
-                    // Create block out of iteration_stmts
-                    let iteration_block = create_block(cx, iteration_stmts, None);
-
-                    // Reconstruct for loop with new block
-                    let for_loop_exprnode = ExprKind::ForLoop(a.clone(), b.clone(), P(iteration_block.clone()), c.clone());
-                    let mut for_loop_expr = expr.deref().clone();
-                    for_loop_expr.node = for_loop_exprnode;
-                    let for_loop_expr = P(for_loop_expr);
-                    let for_loop_stmt = quote_stmt!(cx, $for_loop_expr).unwrap();
-
-                    // Construct a block containing start, for_loop, end stmts
-                    let mut start_end_stmts = vec![];
-                    start_end_stmts.append(&mut start_stmts);
-                    start_end_stmts.push(for_loop_stmt);
-                    start_end_stmts.append(&mut send_stmts);
-                    start_end_stmts.append(&mut collection_stmts);
-                    if return_inenv.len() > 0 {
-                        let envexpr = envtuple_expr(cx, &return_inenv);
-                        start_end_stmts.push(quote_stmt!(cx, $envexpr).unwrap());
-                    }
-                    let start_end_block = create_block(cx, start_end_stmts, None);
-
-                    // Combine start_end_block with a let statement
-                    let stmt = if return_inenv.len() > 0 {
-                        let envexpr = envtuple_pat(cx, &return_inenv);
-                        quote_stmt!(cx, let $envexpr = $start_end_block;).unwrap()
-                    } else {
-                        quote_stmt!(cx, $start_end_block;).unwrap()
-                    };
-
-                    // Special Case: Return early
-                    return stmt;
-                }
+            // Special Case: Return early
+            if let Some(stmt) = try_parallel_for_loop(config, cx, &exprstmt, expr, &exprblock, inner_schedule, inenv, accumulators, spare_threads) {
+                return stmt;
             }
             ExprKind::ForLoop(a.clone(), b.clone(), P(exprblock), c.clone())
         },
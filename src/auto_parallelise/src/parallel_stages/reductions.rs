@@ -0,0 +1,273 @@
+use syntax::ast::{BinOpKind, Expr, ExprKind, LitKind, PatKind, Stmt, StmtKind, Ty, TyKind, UnOp};
+use std::collections::HashMap;
+use std::ops::Deref;
+
+use parallel_stages::deconstructor;
+use parallel_stages::dependency_analysis::{DependencyNode, Environment, PathName};
+use utils::logging;
+
+// Associative updates which can be made to a private accumulator on each thread
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum ReductionKind {
+    Add,    // x += e
+    Mul,    // x *= e
+    Push,   // x.push(e)
+    Insert, // x.insert(e)
+    Max,    // x = x.max(e), x = max(x, e)
+    Min,    // x = x.min(e), x = min(x, e)
+}
+
+// What a variable was declared as, which decides the reductions that can be combined in any order
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Accumulator {
+    Integer, // Addition and multiplication are associative, unlike floats and strings
+    Vec,     // Pushed items are extended in chunk order
+    Set,     // HashSet or BTreeSet
+    Unknown, // Declared without a type or initialiser that shows what it is, or declared more than once
+}
+
+// Accumulators of the variables declared by let statements, keyed by variable name
+pub type Accumulators = HashMap<String, Accumulator>;
+
+impl Accumulator {
+    fn allows(&self, kind: ReductionKind) -> bool {
+        match kind {
+            ReductionKind::Add |
+            ReductionKind::Mul => *self == Accumulator::Integer,
+            ReductionKind::Push => *self == Accumulator::Vec,
+            ReductionKind::Insert => *self == Accumulator::Set,
+            // Taking the max or min of the partial results gives the same answer for any type
+            ReductionKind::Max |
+            ReductionKind::Min => true,
+        }
+    }
+}
+
+// Finds the candidate variables that are only ever updated by a single kind of reduction
+pub fn find_reductions(nodes: &Vec<&DependencyNode>, candidates: &Environment, accumulators: &Accumulators) -> Vec<(PathName, ReductionKind)> {
+    let mut reductions = vec![];
+    for var in candidates.clone().into_iter() {
+        if let Ok(Some(kind)) = nodes_reduction(nodes.iter().map(|node| *node), &var) {
+            let accumulator = match var.len() {
+                1 => accumulators.get(&*var[0].name.as_str()).cloned().unwrap_or(Accumulator::Unknown),
+                _ => Accumulator::Unknown,
+            };
+            if !accumulator.allows(kind) {
+                log_debug!(logging::RECONSTRUCTOR, "Not a reduction: {:?} on {:?}, which is {:?}", kind, var, accumulator);
+                continue;
+            }
+            log_debug!(logging::RECONSTRUCTOR, "Found {:?} reduction on {:?}", kind, var);
+            reductions.push((var, kind));
+        }
+    }
+    reductions
+}
+
+// Finds the accumulator of every variable declared in the nodes, including inside their blocks
+pub fn declared_accumulators(nodes: &Vec<&DependencyNode>) -> Accumulators {
+    let mut accumulators = Accumulators::new();
+    for node in nodes {
+        collect_accumulators(node, &mut accumulators);
+    }
+    accumulators
+}
+
+fn collect_accumulators(node: &DependencyNode, accumulators: &mut Accumulators) {
+    match node {
+        &DependencyNode::Expr(ref stmt, _, _) => {
+            if let StmtKind::Local(ref local) = stmt.node {
+                if let PatKind::Ident(_, ref ident, None) = local.pat.node {
+                    let accumulator = match (&local.ty, &local.init) {
+                        (&Some(ref ty), _) => ty_accumulator(ty),
+                        (&None, &Some(ref init)) => init_accumulator(init),
+                        (&None, &None) => Accumulator::Unknown,
+                    };
+                    // Shadowed variables may be declared as something else
+                    let name = ident.node.name.to_string();
+                    let accumulator = match accumulators.get(&name) {
+                        Some(&declared) if declared != accumulator => Accumulator::Unknown,
+                        _ => accumulator,
+                    };
+                    accumulators.insert(name, accumulator);
+                }
+            }
+        },
+        &DependencyNode::Mac(_, _, _) => {},
+        &DependencyNode::Block(_, ref subtree, _, _) |
+        &DependencyNode::ExprBlock(_, ref subtree, _, _) => {
+            for subnode in subtree {
+                collect_accumulators(subnode, accumulators);
+            }
+        },
+    }
+}
+
+fn ty_accumulator(ty: &Ty) -> Accumulator {
+    match ty.node {
+        TyKind::Paren(ref ty1) => ty_accumulator(ty1),
+        TyKind::Path(None, ref path) => match path.segments.last() {
+            Some(segment) => match &*segment.identifier.name.as_str() {
+                "i8" | "i16" | "i32" | "i64" | "i128" | "isize" |
+                "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => Accumulator::Integer,
+                "Vec" => Accumulator::Vec,
+                "HashSet" | "BTreeSet" => Accumulator::Set,
+                _ => Accumulator::Unknown,
+            },
+            None => Accumulator::Unknown,
+        },
+        _ => Accumulator::Unknown,
+    }
+}
+
+// Integer literals, casts and the usual constructors show the type without it being written
+fn init_accumulator(expr: &Expr) -> Accumulator {
+    match expr.node {
+        ExprKind::Paren(ref expr1) |
+        ExprKind::Unary(UnOp::Neg, ref expr1) => init_accumulator(expr1),
+        ExprKind::Lit(ref lit) => match lit.node {
+            LitKind::Int(_, _) => Accumulator::Integer,
+            _ => Accumulator::Unknown,
+        },
+        ExprKind::Cast(_, ref ty) |
+        ExprKind::Type(_, ref ty) => ty_accumulator(ty),
+        ExprKind::Mac(ref mac) if mac.node.path == "vec" => Accumulator::Vec,
+        ExprKind::Call(ref func, _) => {
+            if let ExprKind::Path(None, ref path) = func.node {
+                let len = path.segments.len();
+                if len >= 2 {
+                    let ty_name = path.segments[len - 2].identifier.name.as_str();
+                    let func_name = path.segments[len - 1].identifier.name.as_str();
+                    if &*func_name == "new" || &*func_name == "with_capacity" {
+                        return match &*ty_name {
+                            "Vec" => Accumulator::Vec,
+                            "HashSet" | "BTreeSet" => Accumulator::Set,
+                            _ => Accumulator::Unknown,
+                        };
+                    }
+                }
+            }
+            Accumulator::Unknown
+        },
+        _ => Accumulator::Unknown,
+    }
+}
+
+// Ok(None) if var is not used, Ok(Some(kind)) if every use is the same reduction, Err otherwise
+fn nodes_reduction<'a, I>(nodes: I, var: &PathName) -> Result<Option<ReductionKind>, ()>
+where I: IntoIterator<Item = &'a DependencyNode> {
+    let mut found = None;
+    for node in nodes {
+        let node_kind = match node {
+            &DependencyNode::Expr(ref stmt, _, _) |
+            &DependencyNode::Mac(ref stmt, _, _) => {
+                let &(ref inenv, ref outenv) = node.get_env();
+                if !inenv.contains(var) && !outenv.contains(var) {
+                    continue;
+                }
+                match stmt_reduction(stmt, var) {
+                    Some(kind) => kind,
+                    None => return Err(()),
+                }
+            },
+            &DependencyNode::ExprBlock(ref stmt, ref subtree, _, _) => {
+                // The statement has had its blocks removed, so this only checks the header
+                let (header_inenv, _) = deconstructor::check_stmt(&mut vec![], stmt.deref());
+                if header_inenv.contains(var) {
+                    return Err(());
+                }
+                match nodes_reduction(subtree, var)? {
+                    Some(kind) => kind,
+                    None => continue,
+                }
+            },
+            &DependencyNode::Block(_, ref subtree, _, _) => {
+                match nodes_reduction(subtree, var)? {
+                    Some(kind) => kind,
+                    None => continue,
+                }
+            },
+        };
+
+        // Mixing kinds of reduction is not associative
+        if let Some(kind) = found {
+            if kind != node_kind {
+                return Err(());
+            }
+        }
+        found = Some(node_kind);
+    }
+    Ok(found)
+}
+
+fn stmt_reduction(stmt: &Stmt, var: &PathName) -> Option<ReductionKind> {
+    let expr = match stmt.node {
+        StmtKind::Expr(ref expr) |
+        StmtKind::Semi(ref expr) => expr,
+        _ => return None,
+    };
+
+    match expr.node {
+        ExprKind::AssignOp(ref op, ref lhs, ref rhs) if is_var(lhs, var) && !uses_var(rhs, var) => {
+            match op.node {
+                BinOpKind::Add => Some(ReductionKind::Add),
+                BinOpKind::Mul => Some(ReductionKind::Mul),
+                _ => None,
+            }
+        },
+
+        ExprKind::MethodCall(ref segment, ref exprl) if exprl.len() == 2 && is_var(&exprl[0], var) && !uses_var(&exprl[1], var) => {
+            match &*segment.identifier.name.as_str() {
+                "push" => Some(ReductionKind::Push),
+                "insert" => Some(ReductionKind::Insert),
+                _ => None,
+            }
+        },
+
+        ExprKind::Assign(ref lhs, ref rhs) if is_var(lhs, var) => {
+            // x = x.max(e)
+            if let ExprKind::MethodCall(ref segment, ref exprl) = rhs.node {
+                if exprl.len() == 2 && is_var(&exprl[0], var) && !uses_var(&exprl[1], var) {
+                    return minmax_kind(&*segment.identifier.name.as_str());
+                }
+            }
+            // x = max(x, e) or x = ::std::cmp::max(e, x)
+            if let ExprKind::Call(ref func, ref exprl) = rhs.node {
+                if let ExprKind::Path(_, ref path) = func.node {
+                    if exprl.len() == 2 && exprl.iter().filter(|arg| is_var(arg, var)).count() == 1 &&
+                       exprl.iter().filter(|arg| uses_var(arg, var)).count() == 1 {
+                        if let Some(segment) = path.segments.last() {
+                            return minmax_kind(&*segment.identifier.name.as_str());
+                        }
+                    }
+                }
+            }
+            None
+        },
+
+        _ => None,
+    }
+}
+
+fn minmax_kind(name: &str) -> Option<ReductionKind> {
+    match name {
+        "max" => Some(ReductionKind::Max),
+        "min" => Some(ReductionKind::Min),
+        _ => None,
+    }
+}
+
+fn is_var(expr: &Expr, var: &PathName) -> bool {
+    match expr.node {
+        ExprKind::Paren(ref expr1) => is_var(expr1, var),
+        ExprKind::Path(None, ref path) => {
+            path.segments.len() == var.len() &&
+            path.segments.iter().zip(var.iter()).all(|(segment, ident)| segment.identifier.name.as_str() == ident.name.as_str())
+        },
+        _ => false,
+    }
+}
+
+fn uses_var(expr: &Expr, var: &PathName) -> bool {
+    let (inenv, outenv) = deconstructor::check_expr(&mut vec![], expr);
+    inenv.contains(var) || outenv.contains(var)
+}
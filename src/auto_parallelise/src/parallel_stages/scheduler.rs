@@ -1,13 +1,66 @@
+use syntax::ast::StmtKind;
+
 use parallel_stages::dependency_analysis::{DependencyTree, DependencyNode, StmtID, Environment, InOutEnvironment};
+use parallel_stages::{cost_model, fallback};
+use parallel_stages::profiler::Profile;
+use utils::logging;
 
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Schedule<'a>(Vec<ScheduleTree<'a>>);
 impl<'a> Schedule<'a> {
     pub fn new(list: Vec<ScheduleTree<'a>>) -> Self {
-        // Sort list so return statement is last element in the list
+        // Sort list so return statement is last element in the list. The sort is stable, so equal
+        // elements stay in statement order
         let mut list = list;
-        list.sort_by(|a, b| b.get_highest_stmtid().partial_cmp(&a.get_highest_stmtid()).unwrap());
-        Schedule(list)
+        list.sort_by(|a, b| b.get_highest_stmtid().cmp(&a.get_highest_stmtid()));
+
+        // Items are placed before any thread is spawned, see spawn_from_schedule_helper, so are kept
+        // out of the last place, which is run on the current thread and gives the return value
+        let (mut items, mut others): (Vec<ScheduleTree<'a>>, Vec<ScheduleTree<'a>>) = list.into_iter().partition(|tree| tree.is_item());
+
+        // The final statement of the block gives its value, which may have been added to any tree
+        if let Some(last) = (0..others.len()).max_by_key(|&i| others[i].get_last_stmtid()) {
+            let tree = others.remove(last);
+            others.push(tree);
+        }
+        items.append(&mut others);
+        Schedule(items)
+    }
+
+    // Branches too small for their own thread are merged into their parent's thread
+    pub fn merge_small_branches(&mut self, min_work: u32) {
+        merge_small_branches(&mut self.0, min_work);
+    }
+
+    // Maps the branches onto max_threads threads, including the current thread, critical path first. The
+    // max_threads - 1 spawned branches with the most work keep their thread, and the rest are run by their
+    // parent's thread. A branch which waits for another branch may then wait forever, which the verifier
+    // finds. Loops spawn their chunks from the threads left, see reconstructor::spawn_from_schedule.
+    // Returns the number of branches which are no longer spawned.
+    pub fn limit_threads(&mut self, max_threads: usize) -> usize {
+        if max_threads == 0 {
+            return 0;
+        }
+        let mut candidates = vec![];
+        collect_spawned_branches(&self.0, true, &mut candidates);
+        candidates.sort_by(|&(stmtid_a, work_a, _), &(stmtid_b, work_b, _)| work_b.cmp(&work_a).then(stmtid_a.cmp(&stmtid_b)));
+
+        let workers = max_threads - 1;
+        let over_limit: Vec<(StmtID, u32, bool)> = candidates.into_iter().skip(workers).collect();
+        let waiting = over_limit.iter().filter(|&&(_, _, waits)| waits).count();
+        if waiting > 0 {
+            log_debug!(logging::SCHEDULER, "{} branches over the thread limit wait for other branches", waiting);
+        }
+        let sequential: Vec<StmtID> = over_limit.into_iter().map(|(stmtid, _, _)| stmtid).collect();
+        mark_sequential(&mut self.0, true, &sequential);
+        sequential.len()
+    }
+
+    // Number of threads the schedule spawns, not counting the current thread or any loops
+    pub fn spawned_branches(&self) -> usize {
+        let mut candidates = vec![];
+        collect_spawned_branches(&self.0, true, &mut candidates);
+        candidates.len()
     }
 
     pub fn get_all_synclines(&self) -> Vec<(StmtID, StmtID, &Environment)> {
@@ -75,19 +128,19 @@ pub enum ScheduleTree<'a> {
 }
 
 impl<'a> ScheduleTree<'a>{
-    fn new(prereqs: Vec<(StmtID, Environment)>, node: &'a DependencyNode) -> Self {
+    fn new(prereqs: Vec<(StmtID, Environment)>, node: &'a DependencyNode, profile: &Profile) -> Self {
         if prereqs.len() > 0 {
-            eprintln!("Got a prereq: {:?}, for node {:?}", prereqs, node);
+            log_trace!(logging::SCHEDULER, "Got a prereq: {:?}, for node {:?}", prereqs, node);
         }
         match node {
             &DependencyNode::Expr(_, _, _) |
             &DependencyNode::Mac(_, _, _) => {
-                ScheduleTree::Node(prereqs, SpanningTree::new(node, 0)) //TODO: get extra weight
+                ScheduleTree::Node(prereqs, SpanningTree::new(node, 0, profile))
             },
 
             &DependencyNode::Block(_, ref tree, _, _) |
             &DependencyNode::ExprBlock(_, ref tree, _, _) => {
-                ScheduleTree::Block(prereqs, SpanningTree::new(node, 0), create_schedule(tree))
+                ScheduleTree::Block(prereqs, SpanningTree::new(node, 0, profile), create_schedule(tree, profile))
             },
         }
     }
@@ -116,6 +169,49 @@ impl<'a> ScheduleTree<'a>{
         }
     }
 
+    // Estimated work done by this branch, not including synclines
+    pub fn get_work(&self) -> u32 {
+        match self {
+            &ScheduleTree::Node(_, ref tree) |
+            &ScheduleTree::Block(_, ref tree, _) => {
+                tree.children.iter().fold(tree.weight, |total, child| total.saturating_add(child.get_work()))
+            },
+            &ScheduleTree::SyncTo(_, _, _) => 0,
+        }
+    }
+
+    // Whether this branch is run by its parent's thread instead of being spawned
+    pub fn is_sequential(&self) -> bool {
+        match self.get_spanning_tree() {
+            Some(tree) => tree.sequential,
+            None => false,
+        }
+    }
+
+    // Whether anything in this branch waits for another branch
+    pub fn has_prereqs(&self) -> bool {
+        match self {
+            &ScheduleTree::Node(ref prereqs, ref tree) |
+            &ScheduleTree::Block(ref prereqs, ref tree, _) => {
+                if prereqs.len() > 0 || tree.children.iter().any(|child| child.has_prereqs()) {
+                    return true;
+                }
+                if let &ScheduleTree::Block(_, _, ref schedule) = self {
+                    return schedule.list().iter().any(|child| child.has_prereqs());
+                }
+                false
+            },
+            &ScheduleTree::SyncTo(_, _, _) => false,
+        }
+    }
+
+    // A branch is only worth a thread if it does more than min_work on top of sending its synclines.
+    // Branches which wait for others have to keep their own thread, otherwise they could wait forever.
+    pub fn worth_spawning(&self, min_work: u32) -> bool {
+        let overhead = min_work.saturating_add(cost_model::CHANNEL_COST * self.get_all_synclines().len() as u32);
+        self.get_work() > overhead || self.has_prereqs()
+    }
+
     fn get_all_synclines(&self) -> Vec<(StmtID, StmtID, &Environment)> {
         let mut synclines = vec![];
         match self {
@@ -133,6 +229,22 @@ impl<'a> ScheduleTree<'a>{
         synclines
     }
 
+    fn is_item(&self) -> bool {
+        match self.get_spanning_tree().and_then(|spanning_tree| spanning_tree.node.get_stmt()) {
+            Some(stmt) => match stmt.node {
+                StmtKind::Item(_) => true,
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    // The last statement of the branch, including the nodes added to it
+    fn get_last_stmtid(&self) -> Option<StmtID> {
+        let spanning_tree = self.get_spanning_tree()?;
+        spanning_tree.children.iter().filter_map(|child| child.get_last_stmtid()).chain(Some(spanning_tree.node.get_stmtid())).max()
+    }
+
     fn get_highest_stmtid(&self) -> Option<StmtID> {
         match self {
             &ScheduleTree::Node(_, ref tree) => Some(tree.node.get_stmtid()),
@@ -224,15 +336,46 @@ pub struct SpanningTree<'a> {
     pub node: &'a DependencyNode,
     pub weight: u32,
     pub children: Vec<ScheduleTree<'a>>,
+    pub sequential: bool, // Run by the parent's thread, see Schedule::merge_small_branches and Schedule::limit_threads
 }
 
 impl<'a> SpanningTree<'a> {
-    pub fn new(node: &'a DependencyNode, extra_weight: u32) -> Self {
+    pub fn new(node: &'a DependencyNode, extra_weight: u32, profile: &Profile) -> Self {
         SpanningTree {
             node: node,
-            weight: extra_weight + performance_metric(&node),
+            weight: extra_weight.saturating_add(cost_model::node_cost(&node, profile)),
             children: vec![],
+            sequential: false,
+        }
+    }
+
+    // Work done along this branch until stmtid has finished
+    fn get_path_weight(&self, stmtid: StmtID) -> Option<u32> {
+        if self.node.get_stmtid() == stmtid {
+            return Some(self.weight);
+        }
+        for child in &self.children {
+            if let Some(child_tree) = child.get_spanning_tree() {
+                if let Some(weight) = child_tree.get_path_weight(stmtid) {
+                    return Some(self.weight.saturating_add(weight));
+                }
+            }
+        }
+        None
+    }
+
+    // Whether stmtid is on this branch and is a let without a value, such as `let x;`
+    fn declares_uninitialised(&self, stmtid: StmtID) -> bool {
+        if self.node.get_stmtid() == stmtid {
+            return match self.node.get_stmt() {
+                Some(stmt) => match stmt.node {
+                    StmtKind::Local(ref local) => local.init.is_none(),
+                    _ => false,
+                },
+                None => false,
+            };
         }
+        self.children.iter().filter_map(|child| child.get_spanning_tree()).any(|child_tree| child_tree.declares_uninitialised(stmtid))
     }
 
     fn get_by_stmtid(&mut self, stmtid: StmtID) -> Option<&mut SpanningTree<'a>> {
@@ -251,8 +394,8 @@ impl<'a> SpanningTree<'a> {
         }
     }
 
-    fn add_child(&mut self, prereqs: Vec<(StmtID, Environment)>, node:&'a DependencyNode) {
-        self.children.push(ScheduleTree::new(prereqs, node));
+    fn add_child(&mut self, prereqs: Vec<(StmtID, Environment)>, node:&'a DependencyNode, profile: &Profile) {
+        self.children.push(ScheduleTree::new(prereqs, node, profile));
     }
 
     fn add_sync_to(&mut self, pre: StmtID, node: StmtID, env: Environment) {
@@ -261,8 +404,94 @@ impl<'a> SpanningTree<'a> {
 
 }
 
-pub fn create_schedule(deptree: &DependencyTree) -> Schedule {
-    eprintln!("create_schedule()");
+// The blocks of an ExprBlock are each rebuilt on the current thread, in order
+fn is_exprblock(tree: &ScheduleTree) -> bool {
+    match tree.get_spanning_tree() {
+        Some(spanning_tree) => match spanning_tree.node {
+            &DependencyNode::ExprBlock(_, _, _, _) => true,
+            _ => false,
+        },
+        None => false,
+    }
+}
+
+// Marks the small branches as sequential, see worth_spawning
+fn merge_small_branches<'a>(trees: &mut Vec<ScheduleTree<'a>>, min_work: u32) {
+    merge_small_branches_helper(trees, min_work, true);
+}
+
+fn merge_small_branches_helper<'a>(trees: &mut Vec<ScheduleTree<'a>>, min_work: u32, spawns: bool) {
+    for tree in trees.iter_mut() {
+        let inner_spawns = !is_exprblock(tree);
+        if let &mut ScheduleTree::Block(_, _, ref mut schedule) = tree {
+            merge_small_branches_helper(schedule.list_mut(), min_work, inner_spawns);
+        }
+        if let Some(spanning_tree) = tree.get_spanning_tree_mut() {
+            merge_small_branches_helper(&mut spanning_tree.children, min_work, true);
+        }
+    }
+    if !spawns {
+        return;
+    }
+
+    let num_trees = trees.len();
+    for tree in trees.iter_mut().take(num_trees.saturating_sub(1)) {
+        if !tree.worth_spawning(min_work) {
+            if let Some(spanning_tree) = tree.get_spanning_tree_mut() {
+                spanning_tree.sequential = true;
+            }
+        }
+    }
+    order_sequential_last(trees);
+}
+
+// Every branch except the last of each list is spawned: (StmtID, work, waits for another branch)
+fn collect_spawned_branches<'a>(trees: &Vec<ScheduleTree<'a>>, spawns: bool, candidates: &mut Vec<(StmtID, u32, bool)>) {
+    for i in 0..trees.len() {
+        if let Some(spanning_tree) = trees[i].get_spanning_tree() {
+            if spawns && i + 1 < trees.len() && !spanning_tree.sequential {
+                candidates.push((spanning_tree.node.get_stmtid(), trees[i].get_work(), trees[i].has_prereqs()));
+            }
+            collect_spawned_branches(&spanning_tree.children, true, candidates);
+        }
+        if let ScheduleTree::Block(_, _, ref schedule) = trees[i] {
+            collect_spawned_branches(schedule.list(), !is_exprblock(&trees[i]), candidates);
+        }
+    }
+}
+
+fn mark_sequential<'a>(trees: &mut Vec<ScheduleTree<'a>>, spawns: bool, sequential: &Vec<StmtID>) {
+    for tree in trees.iter_mut() {
+        let inner_spawns = !is_exprblock(tree);
+        if let &mut ScheduleTree::Block(_, _, ref mut schedule) = tree {
+            mark_sequential(schedule.list_mut(), inner_spawns, sequential);
+        }
+        if let Some(spanning_tree) = tree.get_spanning_tree_mut() {
+            if spawns && sequential.contains(&spanning_tree.node.get_stmtid()) {
+                spanning_tree.sequential = true;
+            }
+            mark_sequential(&mut spanning_tree.children, true, sequential);
+        }
+    }
+    if spawns {
+        order_sequential_last(trees);
+    }
+}
+
+// Moves the sequential branches to just before the last branch, which runs on the parent thread.
+// They are then run by the parent thread while the spawned branches are running.
+fn order_sequential_last<'a>(trees: &mut Vec<ScheduleTree<'a>>) {
+    if let Some(last) = trees.pop() {
+        let (mut spawned, mut sequential): (Vec<ScheduleTree<'a>>, Vec<ScheduleTree<'a>>) = trees.drain(..).partition(|tree| !tree.is_sequential());
+        trees.append(&mut spawned);
+        trees.append(&mut sequential);
+        trees.push(last);
+    }
+}
+
+// Profiled statements are weighted by their measured cost, the rest are estimated
+pub fn create_schedule<'a>(deptree: &'a DependencyTree, profile: &Profile) -> Schedule<'a> {
+    log_trace!(logging::SCHEDULER, "create_schedule()");
     // Find all the independent nodes in the current block
     let mut schedule_trees: Vec<ScheduleTree> = vec![];
     let mut dependent_nodes = vec![];
@@ -270,7 +499,7 @@ pub fn create_schedule(deptree: &DependencyTree) -> Schedule {
         let deps_stmtids = node.get_deps_stmtids(deptree);
         if deps_stmtids.len() == 0 {
             // Independent nodes should create a new spanning_tree
-            schedule_trees.push(ScheduleTree::new(vec![], node));
+            schedule_trees.push(ScheduleTree::new(vec![], node, profile));
         } else {
             // Dependent nodes are stored in a list to be added later
             dependent_nodes.push((node, deps_stmtids));
@@ -278,24 +507,25 @@ pub fn create_schedule(deptree: &DependencyTree) -> Schedule {
     }
 
     // Create multiple maximum spanning tree, but each node can only appear once
-    maximum_spanning_trees(&mut schedule_trees, &mut dependent_nodes);
+    maximum_spanning_trees(&mut schedule_trees, &mut dependent_nodes, profile);
 
     Schedule::new(schedule_trees)
 }
 
 fn maximum_spanning_trees<'a>(schedule_trees: &mut Vec<ScheduleTree<'a>>,
-                              dependent_nodes: &mut Vec<(&'a DependencyNode, Vec<StmtID>)>) {
+                              dependent_nodes: &mut Vec<(&'a DependencyNode, Vec<StmtID>)>,
+                              profile: &Profile) {
     let mut num_remaining;
     while dependent_nodes.len() > 0 {
         num_remaining = dependent_nodes.len();
-        eprintln!("num_remaining: {}", num_remaining);
+        log_trace!(logging::SCHEDULER, "num_remaining: {}", num_remaining);
 
         // Check for nodes with all their dependencies on the spanning_tree
-        // Add the node to the longest dependency
+        // Add the node to the heaviest dependency, the one expected to finish last
 
         dependent_nodes.retain(|&(ref node, ref deps_stmtids)| {
             let node_envin = node.get_env().0.clone();
-            eprintln!("node_envin: {:?}", node_envin);
+            log_trace!(logging::SCHEDULER, "node_envin: {:?}", node_envin);
 
             // If they have a single dependency
             let mut best_nodes_ids = vec![]; // (TreeID,Weight)
@@ -305,10 +535,11 @@ fn maximum_spanning_trees<'a>(schedule_trees: &mut Vec<ScheduleTree<'a>>,
                 // Find the tree nodes that the dependency matches
                 let mut tree_id_pair: Option<(StmtID,usize,u32)> = None;
                 for tree_id in 0..schedule_trees.len() {
-                    if let Some(child_tree) = schedule_trees[tree_id].get_spanning_tree_mut() {
-                        let result = child_tree.get_by_stmtid(*dep_stmtid);
-                        if let Some(_) = result {
-                            tree_id_pair = Some((*dep_stmtid,tree_id,0));//TODO: add weight
+                    if let Some(child_tree) = schedule_trees[tree_id].get_spanning_tree() {
+                        if let Some(weight) = child_tree.get_path_weight(*dep_stmtid) {
+                            // A variable declared without a value cannot be sent to another thread before it is assigned
+                            let weight = if child_tree.declares_uninitialised(*dep_stmtid) { u32::max_value() } else { weight };
+                            tree_id_pair = Some((*dep_stmtid,tree_id,weight));
                         }
                     } else {
                         panic!();
@@ -348,16 +579,16 @@ fn maximum_spanning_trees<'a>(schedule_trees: &mut Vec<ScheduleTree<'a>>,
                                 if let Some(tree_node) = result {
                                     // Get outenv for tree_node
                                     let &(_, ref treeoutenv) = tree_node.node.get_env();
-                                    eprintln!("tree_node: {:?}", tree_node.node);
-                                    eprintln!("treeoutenv: {:?}", treeoutenv);
+                                    log_trace!(logging::SCHEDULER, "tree_node: {:?}", tree_node.node);
+                                    log_trace!(logging::SCHEDULER, "treeoutenv: {:?}", treeoutenv);
                                     // Remove all the elements that this satisfes
                                     let mut diff_env = treeoutenv.clone();
                                     diff_env.remove_env(node_envin.clone());
-                                    eprintln!("treeoutenv-node_envin: {:?}", diff_env);
+                                    log_trace!(logging::SCHEDULER, "treeoutenv-node_envin: {:?}", diff_env);
                                     // Want to keep removed elements from diff_env
                                     let mut sync_env = treeoutenv.clone();
                                     sync_env.remove_env(diff_env);
-                                    eprintln!("sync_env: {:?}", sync_env);
+                                    log_trace!(logging::SCHEDULER, "sync_env: {:?}", sync_env);
                                     for var in sync_env.into_iter() {
                                         tree_node.add_sync_to(node_stmtid, node.get_stmtid(), Environment::new(vec![var.clone()]));
                                         prereqs.push((node_stmtid, Environment::new(vec![var])));
@@ -372,14 +603,14 @@ fn maximum_spanning_trees<'a>(schedule_trees: &mut Vec<ScheduleTree<'a>>,
                     }
                     //assert!(prereqs.len() + 1 == best_nodes_ids.len());
                     if prereqs.len() > 0 {
-                        eprintln!("Had prereqs: {:?}", prereqs);
+                        log_trace!(logging::SCHEDULER, "Had prereqs: {:?}", prereqs);
                     }
 
                     // Add node to best branch
                     if let Some(child_tree) = schedule_trees[best_tree_id].get_spanning_tree_mut() {
                         let result = child_tree.get_by_stmtid(best_stmtid);
                         if let Some(tree_node) = result {
-                            tree_node.add_child(prereqs, node);
+                            tree_node.add_child(prereqs, node, profile);
                             assert!(keep_node);
                             keep_node = false;
                         } else {
@@ -395,24 +626,13 @@ fn maximum_spanning_trees<'a>(schedule_trees: &mut Vec<ScheduleTree<'a>>,
             keep_node
         });
 
-        // Check to see if nothing was added in the last iteration
+        // Check to see if nothing was added in the last iteration, as the remaining nodes depend on
+        // nodes which are never added
         if num_remaining <= dependent_nodes.len() {
-            panic!("Stuck in an infinite loop");
+            let stmts = dependent_nodes.iter().map(|&(ref node, ref deps_stmtids)| {
+                (node.get_stmtid(), format!("depends on {:?}", deps_stmtids))
+            }).collect();
+            fallback::give_up("Some statements depend on statements which could not be scheduled".to_owned(), stmts);
         }
     }
 }
-
-fn performance_metric(node: &DependencyNode) -> u32 {
-    match node {
-        &DependencyNode::Expr(_, _, _) => 1,
-        &DependencyNode::ExprBlock(_, ref nodes,_, _) |
-        &DependencyNode::Block(_, ref nodes,_, _) => {
-            let mut total = 1;
-            for node in nodes {
-                total += performance_metric(node);
-            }
-            total
-        },
-        &DependencyNode::Mac(_, _, _) => 1,
-    }
-}
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+
+use syntax::ast::Stmt;
+use syntax::print::pprust;
+
+use parallel_stages::{deconstructor, fallback};
+use parallel_stages::dependency_analysis::{DependencyTree, DependencyNode, StmtID, Environment, PathName};
+use parallel_stages::scheduler::{Schedule, ScheduleTree};
+use utils::logging;
+
+// A problem with a schedule, and the statement it was found at
+pub type ScheduleError = (StmtID, String);
+
+// Checks the schedule before it is reconstructed, as any mistake in it would make the generated
+// code deadlock, panic on a closed channel or race. The schedule is modelled as the events of each
+// thread the reconstructor would create, and which events have to happen before others.
+pub fn verify_schedule<'a>(deptree: &'a DependencyTree, schedule: &'a Schedule<'a>) -> Result<(), Vec<ScheduleError>> {
+    let mut graph = EventGraph::new();
+    graph.add_list(deptree, schedule.list(), 0, 0, true);
+
+    let mut errors = vec![];
+    graph.match_synclines(&mut errors);
+    graph.check_acyclic(&mut errors);
+    if errors.len() == 0 {
+        // Only meaningful once every receive is known to happen after its send
+        graph.check_dependencies(&mut errors);
+    }
+    if errors.len() > 0 {
+        log_debug!(logging::SCHEDULER, "Schedule failed verification: {:?}", errors);
+        Err(errors)
+    } else {
+        Ok(())
+    }
+}
+
+// How the reconstructor splits the iterations of a loop between threads, which run the body at the
+// same time. Every variable the body uses from outside the loop has to be shared, split or carried.
+pub struct LoopRewrite<'a> {
+    pub body: &'a Vec<&'a DependencyNode>,
+    pub uses: &'a Environment, // Used by the body from outside the loop, except the loop bindings
+    pub header: &'a Environment, // Read by the loop header, which runs ahead of the body
+    pub shared: &'a Environment, // Every thread gets a copy, so no iteration may write them
+    pub split: &'a Environment, // Each thread has its own part or partial result
+    pub carried: &'a Environment, // Handed between threads in the order of the iterations
+    pub unordered: &'a Vec<Stmt>, // Run by the threads in any order, so cannot use carried variables
+}
+
+// Checks a parallel for loop or pipelined while loop as it is built, as the schedule only has the loop
+// as a single statement. Any mistake would make the threads race on a variable.
+pub fn verify_loop_rewrite(stmtid: StmtID, rewrite: &LoopRewrite) -> Result<(), Vec<ScheduleError>> {
+    let mut errors = vec![];
+    for var in rewrite.uses.clone().into_iter() {
+        let ways = [rewrite.shared, rewrite.split, rewrite.carried].iter().filter(|env| env.contains(&var)).count();
+        if ways == 0 {
+            errors.push((stmtid, format!("uses {}, which is not shared, split or carried between threads", path_name(&var))));
+        } else if ways > 1 {
+            errors.push((stmtid, format!("gives {} to the threads in more than one way", path_name(&var))));
+        }
+    }
+
+    let written = deconstructor::written_variables(rewrite.body);
+    for var in written.into_iter() {
+        if rewrite.shared.contains(&var) {
+            errors.push((stmtid, format!("writes {}, which every thread has a copy of", path_name(&var))));
+        }
+        if rewrite.header.contains(&var) {
+            errors.push((stmtid, format!("writes {}, which the loop header reads ahead of the body", path_name(&var))));
+        }
+    }
+
+    for stmt in rewrite.unordered {
+        let (inenv, outenv) = deconstructor::check_stmt(&mut vec![], stmt);
+        for var in inenv.into_iter().chain(outenv.into_iter()) {
+            if rewrite.carried.contains(&var) {
+                errors.push((stmtID!(stmt), format!("`{}` runs out of order, but uses carried {}", pprust::stmt_to_string(stmt), path_name(&var))));
+            }
+        }
+    }
+
+    if errors.len() > 0 {
+        log_debug!(logging::RECONSTRUCTOR, "Loop rewrite failed verification: {:?}", errors);
+        Err(errors)
+    } else {
+        Ok(())
+    }
+}
+
+// Where a syncline is sent from or received at: (producer, consumer, variable, event, thread)
+type SynclineEnd<'a> = (StmtID, StmtID, &'a Environment, usize, usize);
+
+struct Statement<'a> {
+    node: &'a DependencyNode,
+    deptree: &'a DependencyTree, // The block the statement is in, which its dependencies refer to
+    begin: usize,
+    end: usize,
+    thread: usize,
+}
+
+struct EventGraph<'a> {
+    events: Vec<(Option<StmtID>, String)>,
+    edges: Vec<Vec<usize>>, // Events which can only happen after this one
+    threads: Vec<(Option<usize>, usize)>, // (Parent thread, event of the spawn), the current thread is 0
+    sends: Vec<SynclineEnd<'a>>,
+    receives: Vec<SynclineEnd<'a>>,
+    statements: HashMap<StmtID, Statement<'a>>,
+}
+
+impl<'a> EventGraph<'a> {
+    // Starts with the event of the function being called, on the current thread
+    fn new() -> Self {
+        EventGraph {
+            events: vec![(None, "function start".to_owned())],
+            edges: vec![vec![]],
+            threads: vec![(None, 0)],
+            sends: vec![],
+            receives: vec![],
+            statements: HashMap::new(),
+        }
+    }
+
+    fn add_event(&mut self, stmtid: Option<StmtID>, after: usize, description: String) -> usize {
+        let event = self.events.len();
+        self.events.push((stmtid, description));
+        self.edges.push(vec![]);
+        self.edges[after].push(event);
+        event
+    }
+
+    // Mirrors spawn_from_schedule_helper: every branch but the last is spawned unless it is sequential,
+    // and spawned threads are joined once the last branch has finished. Returns the last event.
+    fn add_list(&mut self, deptree: &'a DependencyTree, trees: &'a Vec<ScheduleTree<'a>>, after: usize, thread: usize, spawns: bool) -> usize {
+        let mut current = after;
+        let mut spawned_ends = vec![];
+        for i in 0..trees.len() {
+            match trees[i] {
+                ScheduleTree::SyncTo(from, to, ref env) => {
+                    current = self.add_event(Some(from), current, format!("send {} to {:?}", env_names(env), to));
+                    self.sends.push((from, to, env, current, thread));
+                },
+                _ => {
+                    if spawns && i + 1 < trees.len() && !trees[i].is_sequential() {
+                        let stmtid = trees[i].get_spanning_tree().map(|tree| tree.node.get_stmtid());
+                        let spawn = self.add_event(stmtid, current, "spawn thread".to_owned());
+                        let new_thread = self.threads.len();
+                        self.threads.push((Some(thread), spawn));
+                        spawned_ends.push(self.add_branch(deptree, &trees[i], spawn, new_thread));
+                    } else {
+                        current = self.add_branch(deptree, &trees[i], current, thread);
+                    }
+                },
+            }
+        }
+        if spawned_ends.len() > 0 {
+            let join = self.add_event(None, current, "join threads".to_owned());
+            for end in spawned_ends {
+                self.edges[end].push(join);
+            }
+            current = join;
+        }
+        current
+    }
+
+    // Receives the prereqs, runs the statement and then its children
+    fn add_branch(&mut self, deptree: &'a DependencyTree, tree: &'a ScheduleTree<'a>, after: usize, thread: usize) -> usize {
+        let mut current = after;
+        let (prereqs, spanning_tree) = match tree {
+            &ScheduleTree::Node(ref prereqs, ref spanning_tree) |
+            &ScheduleTree::Block(ref prereqs, ref spanning_tree, _) => (prereqs, spanning_tree),
+            &ScheduleTree::SyncTo(_, _, _) => panic!("Unreachable case"),
+        };
+        let stmtid = spanning_tree.node.get_stmtid();
+        for &(from, ref env) in prereqs {
+            current = self.add_event(Some(stmtid), current, format!("receive {} from {:?}", env_names(env), from));
+            self.receives.push((from, stmtid, env, current, thread));
+        }
+
+        let begin = self.add_event(Some(stmtid), current, "start".to_owned());
+        current = begin;
+        if let &ScheduleTree::Block(_, _, ref schedule) = tree {
+            match spanning_tree.node {
+                &DependencyNode::Block(_, ref inner_deptree, _, _) => {
+                    current = self.add_list(inner_deptree, schedule.list(), current, thread, true);
+                },
+                &DependencyNode::ExprBlock(_, ref inner_deptree, _, _) => {
+                    // The blocks of an ExprBlock are each rebuilt on the current thread
+                    current = self.add_list(inner_deptree, schedule.list(), current, thread, false);
+                },
+                _ => panic!("Unexpected node type in block {:?}", spanning_tree.node),
+            }
+        }
+        let end = self.add_event(Some(stmtid), current, "finish".to_owned());
+        self.statements.insert(stmtid, Statement {
+            node: spanning_tree.node,
+            deptree: deptree,
+            begin: begin,
+            end: end,
+            thread: thread,
+        });
+
+        self.add_list(deptree, &spanning_tree.children, end, thread, true)
+    }
+
+    // Each receive needs exactly one send on another thread, otherwise it would wait forever or
+    // the sender would be dropped before sending
+    fn match_synclines(&mut self, errors: &mut Vec<ScheduleError>) {
+        let mut syncline_edges = vec![];
+        for &(from, to, env, receive, receive_thread) in &self.receives {
+            let senders: Vec<&SynclineEnd> = self.sends.iter().filter(|&&(send_from, send_to, send_env, _, _)| {
+                send_from == from && send_to == to && same_env(send_env, env)
+            }).collect();
+            match senders.len() {
+                0 => errors.push((to, format!("receives {} from {:?}, but nothing sends it", env_names(env), from))),
+                1 => {
+                    let &(_, _, _, send, send_thread) = senders[0];
+                    // Only the blocks of an ExprBlock send and receive on one thread, where the send comes first
+                    if send_thread == receive_thread && !self.happens_before(send, receive) {
+                        errors.push((to, format!("waits for {} from {:?} on the thread that sends it later", env_names(env), from)));
+                    }
+                    syncline_edges.push((send, receive));
+                },
+                num_senders => errors.push((to, format!("receives {} from {:?}, but it is sent {} times", env_names(env), from, num_senders))),
+            }
+        }
+        for &(from, to, env, _, _) in &self.sends {
+            if !self.receives.iter().any(|&(receive_from, receive_to, receive_env, _, _)| receive_from == from && receive_to == to && same_env(receive_env, env)) {
+                errors.push((from, format!("sends {} to {:?}, but nothing receives it", env_names(env), to)));
+            }
+        }
+        for (send, receive) in syncline_edges {
+            self.edges[send].push(receive);
+        }
+    }
+
+    // A cycle of events that each wait for the one before is a deadlock
+    fn check_acyclic(&self, errors: &mut Vec<ScheduleError>) {
+        // 0 = unvisited, 1 = on the current path, 2 = finished
+        let mut state = vec![0; self.events.len()];
+        let mut path = vec![];
+        for event in 0..self.events.len() {
+            if state[event] == 0 {
+                if let Some(cycle) = self.find_cycle(event, &mut state, &mut path) {
+                    let descriptions: Vec<String> = cycle.iter().map(|&event| self.describe(event)).collect();
+                    let stmtid = cycle.iter().filter_map(|&event| self.events[event].0).next().unwrap_or((0, 0));
+                    errors.push((stmtid, format!("deadlocks, as each of these waits for the next: {}", descriptions.join(" -> "))));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn find_cycle(&self, event: usize, state: &mut Vec<u8>, path: &mut Vec<usize>) -> Option<Vec<usize>> {
+        state[event] = 1;
+        path.push(event);
+        for &next in &self.edges[event] {
+            if state[next] == 1 {
+                let position = path.iter().position(|&path_event| path_event == next).unwrap();
+                let mut cycle = path[position..].to_vec();
+                cycle.push(next);
+                return Some(cycle);
+            } else if state[next] == 0 {
+                if let Some(cycle) = self.find_cycle(next, state, path) {
+                    return Some(cycle);
+                }
+            }
+        }
+        path.pop();
+        state[event] = 2;
+        None
+    }
+
+    // Every statement has to run after the statements it depends on. Variables it reads from another
+    // thread were either moved into its thread when it was spawned, or are received back over a syncline.
+    fn check_dependencies(&self, errors: &mut Vec<ScheduleError>) {
+        let mut stmtids: Vec<&StmtID> = self.statements.keys().collect();
+        stmtids.sort();
+        for stmtid in stmtids {
+            let statement = &self.statements[stmtid];
+            for dep_stmtid in statement.node.get_deps_stmtids(statement.deptree) {
+                let dependency = match self.statements.get(&dep_stmtid) {
+                    Some(dependency) => dependency,
+                    None => {
+                        errors.push((*stmtid, format!("depends on {:?}, which is not in the schedule", dep_stmtid)));
+                        continue;
+                    },
+                };
+                if !self.happens_before(dependency.end, statement.begin) {
+                    errors.push((*stmtid, format!("can run before {:?}, which it depends on", dep_stmtid)));
+                    continue;
+                }
+                if dependency.thread == statement.thread {
+                    continue;
+                }
+
+                // Same as the environment the scheduler sends over synclines
+                let &(_, ref dep_outenv) = dependency.node.get_env();
+                let &(ref inenv, _) = statement.node.get_env();
+                let mut unused_env = dep_outenv.clone();
+                unused_env.remove_env(inenv.clone());
+                let mut used_env = dep_outenv.clone();
+                used_env.remove_env(unused_env);
+                for var in used_env.into_iter() {
+                    if !self.moved_on_spawn(dependency, statement) && !self.received(&var, dependency, statement) {
+                        errors.push((*stmtid, format!("uses {} from {:?}, which is moved to another thread and not received back", path_name(&var), dep_stmtid)));
+                    }
+                }
+            }
+        }
+    }
+
+    // The statement is in a thread spawned by the dependency's thread after the dependency finished
+    fn moved_on_spawn(&self, dependency: &Statement, statement: &Statement) -> bool {
+        let mut thread = statement.thread;
+        while let (Some(parent), spawn) = self.threads[thread] {
+            if parent == dependency.thread {
+                return self.happens_before(dependency.end, spawn);
+            }
+            thread = parent;
+        }
+        false
+    }
+
+    // The variable is sent after the dependency finished and is received before the statement starts
+    fn received(&self, var: &PathName, dependency: &Statement, statement: &Statement) -> bool {
+        let dep_stmtid = dependency.node.get_stmtid();
+        self.receives.iter().any(|&(from, _, env, receive, _)| {
+            from == dep_stmtid && env.contains(var) && self.happens_before(receive, statement.begin)
+        })
+    }
+
+    fn happens_before(&self, first: usize, second: usize) -> bool {
+        let mut visited = vec![false; self.events.len()];
+        let mut stack = vec![first];
+        while let Some(event) = stack.pop() {
+            if event == second {
+                return true;
+            }
+            if !visited[event] {
+                visited[event] = true;
+                stack.extend(self.edges[event].iter().cloned());
+            }
+        }
+        false
+    }
+
+    fn describe(&self, event: usize) -> String {
+        match self.events[event] {
+            (Some(stmtid), ref description) => format!("{:?} {}", stmtid, description),
+            (None, ref description) => description.clone(),
+        }
+    }
+}
+
+// Idents in an Environment are gensyms, so only their names can be compared
+fn same_env(env1: &Environment, env2: &Environment) -> bool {
+    env1.into_depstr() == env2.into_depstr()
+}
+
+fn path_name(path: &PathName) -> String {
+    format!("`{}`", fallback::path_name(path))
+}
+
+fn env_names(env: &Environment) -> String {
+    env.clone().into_iter().map(|path| path_name(&path)).collect::<Vec<String>>().join(", ")
+}
@@ -0,0 +1,111 @@
+use serde_json;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use utils;
+use utils::logging;
+use parallel_stages::parallelism::ParallelismReport;
+use plugin::shared_state::{Config, EncodedDependencyTree};
+
+// Format of the cache file, caches in any other format are discarded
+const CACHE_VERSION: u32 = 2;
+
+// Kept between builds, so functions that have not changed are not analysed again
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AnalysisCache {
+    version: u32,
+    entries: Vec<CacheEntry>,
+}
+
+// Everything found about a function, valid while the body of the function is unchanged. The schedule
+// is not kept, as it borrows the nodes of the deptree built during that build, and is quick to create again.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub function: String,
+    pub source_hash: u64,
+    pub encoded_deptree: EncodedDependencyTree,
+    pub config_hash: Option<u64>, // Config that the report was made with
+    pub report: Option<ParallelismReport>,
+}
+
+impl AnalysisCache {
+    pub fn new() -> Self {
+        AnalysisCache {
+            version: CACHE_VERSION,
+            entries: vec![],
+        }
+    }
+
+    // A cache that cannot be read is only a missed speedup, so it is replaced instead of failing the build
+    pub fn load(path: &Path) -> Self {
+        match utils::read_file(&path.to_string_lossy()) {
+            Some(json) => match serde_json::from_str::<AnalysisCache>(&json) {
+                Ok(ref cache) if cache.version != CACHE_VERSION => {
+                    log_debug!(logging::PLUGIN, "Discarding {} with format version {}", path.display(), cache.version);
+                    AnalysisCache::new()
+                },
+                Ok(cache) => cache,
+                Err(why) => {
+                    log_warn!(logging::PLUGIN, "Discarding unreadable cache {}: {}", path.display(), why);
+                    AnalysisCache::new()
+                },
+            },
+            None => AnalysisCache::new(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) {
+        let cache_json = match serde_json::to_string(&self) {
+            Ok(json) => json,
+            Err(why) => panic!("Unable to convert AnalysisCache to JSON: {}", why),
+        };
+        utils::write_file(path, &cache_json);
+    }
+
+    pub fn get(&self, function: &str, source_hash: u64) -> Option<&CacheEntry> {
+        self.entries.iter().find(|entry| entry.function == function && entry.source_hash == source_hash)
+    }
+
+    // Only the latest version of each function is kept
+    pub fn insert_deptree(&mut self, function: &str, source_hash: u64, encoded_deptree: EncodedDependencyTree) {
+        self.entries.retain(|entry| entry.function != function);
+        self.entries.push(CacheEntry {
+            function: function.to_owned(),
+            source_hash: source_hash,
+            encoded_deptree: encoded_deptree,
+            config_hash: None,
+            report: None,
+        });
+    }
+
+    // The report is only cached alongside the analysis it was made from
+    pub fn insert_report(&mut self, function: &str, source_hash: u64, config_hash: u64, report: ParallelismReport) {
+        for entry in self.entries.iter_mut() {
+            if entry.function == function && entry.source_hash == source_hash {
+                entry.config_hash = Some(config_hash);
+                entry.report = Some(report);
+                return;
+            }
+        }
+    }
+
+    // The report of a function, if it was made with the same config
+    pub fn get_report(&self, function: &str, source_hash: u64, config_hash: u64) -> Option<&ParallelismReport> {
+        match self.get(function, source_hash) {
+            Some(entry) if entry.config_hash == Some(config_hash) => entry.report.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+// Reports depend on the config, so they are only reused while it is unchanged
+pub fn config_hash(config: &Config) -> u64 {
+    let config_json = match serde_json::to_string(config) {
+        Ok(json) => json,
+        Err(why) => panic!("Unable to convert Config to JSON: {}", why),
+    };
+    let mut hasher = DefaultHasher::new();
+    config_json.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,69 @@
+use serde_json;
+use toml;
+use std::path::Path;
+
+use CONFIG_FILE;
+use TOML_CONFIG_FILE;
+use utils;
+use utils::logging;
+use plugin::shared_state::Config;
+
+// The first config found in the manifest directory is used: autoparallelise.toml, then
+// [package.metadata.autoparallelise] in Cargo.toml, then the json autoparallelise.config.
+// An invalid config gives an error message pointing at the offending line.
+pub fn load_config(manifest_dir: &Path) -> Result<Config, String> {
+    let toml_path = manifest_dir.join(TOML_CONFIG_FILE);
+    if let Some(source) = utils::read_file(&toml_path.to_string_lossy()) {
+        log_debug!(logging::PLUGIN, "Using config from {}", toml_path.display());
+        return toml::from_str(&source).map_err(|why| config_error(&toml_path, &source, &why.to_string(), None));
+    }
+
+    let manifest_path = manifest_dir.join("Cargo.toml");
+    if let Some(source) = utils::read_file(&manifest_path.to_string_lossy()) {
+        let manifest: toml::Value = toml::from_str(&source).map_err(|why| config_error(&manifest_path, &source, &why.to_string(), None))?;
+        let metadata = manifest.get("package")
+                               .and_then(|package| package.get("metadata"))
+                               .and_then(|metadata| metadata.get("autoparallelise"));
+        if let Some(metadata) = metadata {
+            log_debug!(logging::PLUGIN, "Using config from [package.metadata.autoparallelise] in {}", manifest_path.display());
+            return metadata.clone().try_into().map_err(|why| config_error(&manifest_path, &source, &why.to_string(), None));
+        }
+    }
+
+    let json_path = manifest_dir.join(CONFIG_FILE);
+    if let Some(source) = utils::read_file(&json_path.to_string_lossy()) {
+        log_debug!(logging::PLUGIN, "Using config from {}", json_path.display());
+        return serde_json::from_str(&source).map_err(|why| config_error(&json_path, &source, &why.to_string(), Some(why.line())));
+    }
+
+    Ok(Config::default())
+}
+
+// Formatted like a compiler error, with the file, line and the line itself
+fn config_error(path: &Path, source: &str, message: &str, line: Option<usize>) -> String {
+    match line.or_else(|| error_line(source, message)) {
+        Some(line) => {
+            let line_source = source.lines().nth(line - 1).unwrap_or("");
+            format!("{}:{}: invalid autoparallelise config: {}\n    {}", path.display(), line, message, line_source.trim())
+        },
+        None => format!("{}: invalid autoparallelise config: {}", path.display(), message),
+    }
+}
+
+// Syntax errors give their line, other errors only name the key they are for
+fn error_line(source: &str, message: &str) -> Option<usize> {
+    if let Some(index) = message.rfind("at line ") {
+        let digits: String = message[index + "at line ".len()..].chars().take_while(|c| c.is_digit(10)).collect();
+        if let Ok(line) = digits.parse() {
+            return Some(line);
+        }
+    }
+
+    // For example "unknown field `max_thread`, expected one of ..." or "invalid type ... for key `autoparallelise.max_threads`"
+    let key = message.split('`').nth(1)?;
+    let key = key.rsplit('.').next()?;
+    source.lines().position(|line| {
+        let line = line.trim_left();
+        line.starts_with(key) && line[key.len()..].trim_left().starts_with('=')
+    }).map(|index| index + 1)
+}
@@ -2,13 +2,25 @@ use rustc::lint::{LintArray, LintPass, EarlyContext, EarlyLintPass};
 use syntax::ast;
 use syntax_pos::Span;
 use syntax::visit::{self, FnKind};
+use syntax::print::pprust;
 
 use serde_json;
 
+use std::fs;
+
 use AutoParallelise;
 use CompilerStage;
-use parallel_stages::dependency_analysis;
-use plugin::shared_state::{Function};
+use REPORT_FILE;
+use HTML_SECTIONS_FILE;
+use HTML_REPORT_FILE;
+use STALE_FILE;
+use CACHE_FILE;
+use utils;
+use utils::logging::{self, Level};
+use parallel_stages::{dependency_analysis, parallelism, fallback};
+use rendering::{html, report};
+use plugin::cache::AnalysisCache;
+use plugin::shared_state::{self, Function};
 
 impl LintPass for AutoParallelise {
     fn get_lints(&self) -> LintArray {
@@ -17,6 +29,25 @@ impl LintPass for AutoParallelise {
 }
 
 impl EarlyLintPass for AutoParallelise {
+    // Keep track of the modules and impls of each function, so functions can be told apart in lib.rs and its
+    // modules, and methods of different types with the same name are not mixed up
+    fn check_item(&mut self, _: &EarlyContext, item: &ast::Item) {
+        match item.node {
+            ast::ItemKind::Mod(_) => self.module_path.push(item.ident.name.to_string()),
+            ast::ItemKind::Impl(_, _, _, _, ref trait_ref, ref self_ty, _) => self.module_path.push(impl_name(trait_ref, self_ty)),
+            _ => {},
+        }
+    }
+    fn check_item_post(&mut self, _: &EarlyContext, item: &ast::Item) {
+        match item.node {
+            ast::ItemKind::Mod(_) |
+            ast::ItemKind::Impl(_, _, _, _, _, _, _) => {
+                self.module_path.pop();
+            },
+            _ => {},
+        }
+    }
+
     fn check_fn(&mut self, _context: &EarlyContext, _fnkind: visit::FnKind, _fndecl: &ast::FnDecl, _span: Span, _nodeid: ast::NodeId) {
         // Only need to analyse function during the analysis stage
         if !self.config.plugin_enabled || self.compiler_stage != CompilerStage::Analysis {
@@ -29,33 +60,79 @@ impl EarlyLintPass for AutoParallelise {
             FnKind::ItemFn(ident, _, _, _, _, block) |
             // fn foo(&self), i.e. obj.foo();
             FnKind::Method(ident, _, _, block) => {
-                eprintln!("\n\n{:?}", _fndecl);
-                let ident_name: String = ident.name.to_string();
+                let ident_name: String = shared_state::qualified_name(&self.module_path, &ident.name.as_str());
                 let ident_ctxt: String = format!("{:?}", ident.ctxt);
                 let input_types = vec![]; // TODO
-                for ref arg in &_fndecl.inputs {
-                    eprintln!("ARG: {:?}, {:?}", arg.ty.node, arg.pat);
+                let source_hash = utils::source_hash(_context.sess.codemap(), block.span);
+
+                // Functions which are not parallelised do not need analysing
+                if !self.config.for_function(&ident_name).plugin_enabled {
+                    self.save();
+                    return;
                 }
 
-                let deptree = dependency_analysis::analyse_block(&block);
-                eprintln!("DEPTREE:");
-                for node in &deptree {
-                    let node_json = match serde_json::to_string_pretty(&node) {
-                        Ok(obj) => obj,
-                        Err(why) => panic!("Unable to convert deptree to JSON: {}", why),
-                    };
-                    eprintln!("{}", node_json);
+                // Functions kept from an earlier analysis do not need analysing again
+                if self.functions.iter().any(|func| func.ident_name == ident_name && func.source_hash == source_hash) {
+                    log_debug!(logging::ANALYSIS, "{} has not changed since it was analysed", ident_name);
+                    self.save();
+                    return;
                 }
+                self.functions.retain(|func| func.ident_name != ident_name);
+
+                // Reuse the analysis from an earlier build when the function has not changed
+                let cache_path = self.state_path(CACHE_FILE);
+                let mut cache = if self.config.cache {
+                    AnalysisCache::load(&cache_path)
+                } else {
+                    AnalysisCache::new()
+                };
+                let cached_deptree = cache.get(&ident_name, source_hash).map(|entry| entry.encoded_deptree.clone());
+                let encoded_deptree = match cached_deptree {
+                    Some(encoded_deptree) => {
+                        log_debug!(logging::ANALYSIS, "Using cached analysis of {}", ident_name);
+                        encoded_deptree
+                    },
+                    None => {
+                        log_debug!(logging::ANALYSIS, "Analysing {}", ident_name);
+                        log_trace!(logging::ANALYSIS, "{:?}", _fndecl);
+                        for ref arg in &_fndecl.inputs {
+                            log_trace!(logging::ANALYSIS, "ARG: {:?}, {:?}", arg.ty.node, arg.pat);
+                        }
 
-                // convert deptree into encoded_deptree
-                let encoded_deptree = dependency_analysis::encode_deptree(&deptree);
+                        let deptree = match fallback::catch_unparallelisable(|| dependency_analysis::analyse_block(&block)) {
+                            Ok(deptree) => deptree,
+                            Err(unparallelisable) => {
+                                // Kept without a dependency tree, the modification stage fails the same way and warns
+                                log_debug!(logging::ANALYSIS, "Unable to analyse {}: {}", ident_name, unparallelisable.reason);
+                                vec![]
+                            },
+                        };
+                        if logging::enabled(logging::ANALYSIS, Level::Debug) {
+                            for node in &deptree {
+                                let node_json = match serde_json::to_string_pretty(&node) {
+                                    Ok(obj) => obj,
+                                    Err(why) => panic!("Unable to convert deptree to JSON: {}", why),
+                                };
+                                log_debug!(logging::ANALYSIS, "DEPTREE:\n{}", node_json);
+                            }
+                        }
 
-                eprintln!("ENCODED_DEPTREE:");
-                eprintln!("{:?}", encoded_deptree);
+                        // convert deptree into encoded_deptree
+                        let encoded_deptree = dependency_analysis::encode_deptree(&deptree, _context.sess.codemap());
+                        log_trace!(logging::ANALYSIS, "ENCODED_DEPTREE:\n{:?}", encoded_deptree);
+
+                        if self.config.cache {
+                            cache.insert_deptree(&ident_name, source_hash, encoded_deptree.clone());
+                            cache.save(&cache_path);
+                        }
+                        encoded_deptree
+                    },
+                };
 
                 self.functions.push(Function {
                     ident_name: ident_name,
                     ident_ctxt: ident_ctxt,
+                    source_hash: source_hash,
 
                     output_type: None,
 
@@ -84,11 +161,38 @@ impl EarlyLintPass for AutoParallelise {
             self.save();
             match self.compiler_stage {
                 CompilerStage::Analysis => {
-                    eprintln!("[auto_parallelise] Recompile to apply parallelization modifications");
+                    // Reports are rebuilt as each function is modified
+                    let _ = fs::remove_file(self.state_path(REPORT_FILE));
+                    let _ = fs::remove_file(self.state_path(HTML_SECTIONS_FILE));
+                    let _ = fs::remove_file(self.state_path(STALE_FILE));
+                    log_info!(logging::PLUGIN, "Recompile to apply parallelization modifications");
                     ::std::process::exit(1);
                 },
                 CompilerStage::Modification => {
-                    eprintln!("[auto_parallelise] Parallelised Compilation Complete");
+                    let stale = self.load_stale();
+                    if stale.len() > 0 {
+                        // Keep the analysis of the other functions, so only the stale functions are analysed again
+                        self.functions.retain(|func| !stale.contains(&func.ident_name));
+                        self.save_for_stage(CompilerStage::Analysis);
+                        let _ = fs::remove_file(self.state_path(STALE_FILE));
+                        log_info!(logging::PLUGIN, "Recompile to analyse {} again", stale.join(", "));
+                        ::std::process::exit(1);
+                    }
+                    let report_path = self.state_path(REPORT_FILE);
+                    let reports = parallelism::load_reports(&report_path);
+                    if reports.len() > 0 {
+                        log_info!(logging::PLUGIN, "Available parallelism (written to {}):\n{}", report_path.display(), report::reports_to_table(&reports));
+                    }
+                    let sections = html::load_sections(&self.state_path(HTML_SECTIONS_FILE));
+                    if sections.len() > 0 {
+                        let html_path = match self.output_dir() {
+                            Some(output_dir) => output_dir.join(HTML_REPORT_FILE),
+                            None => self.state_path(HTML_REPORT_FILE),
+                        };
+                        utils::write_file(&html_path, &html::report_to_html(&self.crate_name, &sections));
+                        log_info!(logging::PLUGIN, "HTML report written to {}", html_path.display());
+                    }
+                    log_info!(logging::PLUGIN, "Parallelised Compilation Complete");
                     self.delete();
                     // Sometimes compile works, sometimes not. Instead always fail, and use script to copy to a new crate without auto_parallelise
                     // TODO: Remove when nightly compiler bug affecting macros is fixed
@@ -100,3 +204,11 @@ impl EarlyLintPass for AutoParallelise {
         }
     }
 }
+
+// Foo for inherent impls and <Foo as Trait> for trait impls, as in a path to one of its methods
+fn impl_name(trait_ref: &Option<ast::TraitRef>, self_ty: &ast::Ty) -> String {
+    match trait_ref {
+        &Some(ref trait_ref) => format!("<{} as {}>", pprust::ty_to_string(self_ty), pprust::path_to_string(&trait_ref.path)),
+        &None => pprust::ty_to_string(self_ty),
+    }
+}
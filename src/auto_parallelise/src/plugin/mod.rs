@@ -1,3 +1,5 @@
 pub mod linter;
 pub mod syntax_extension;
 pub mod shared_state;
+pub mod cache;
+pub mod config;
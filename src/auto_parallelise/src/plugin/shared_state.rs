@@ -1,16 +1,21 @@
 use serde_json;
 
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 
 use utils;
-use parallel_stages::dependency_analysis::StmtID;
+use parallel_stages::cost_model;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct AutoParallelise {
+    pub version: u32, // Format of the save file, see SAVE_FILE_VERSION
+    pub crate_name: String, // Crate name and target, see crate_key
+    pub state_dir: String,
     pub compiler_stage: CompilerStage,
     pub linter_level: u32, // Used to determine when linter has finished
+    pub module_path: Vec<String>, // Modules enclosing the item being linted
     pub functions: Vec<Function>,
     pub config: Config,
 }
@@ -24,8 +29,9 @@ pub enum CompilerStage {
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Function {
     // Function Identifier
-    pub ident_name: String, // TODO: Need to fully qualify name
+    pub ident_name: String, // Qualified by the modules it is in, see qualified_name
     pub ident_ctxt: String,
+    pub source_hash: u64, // Hash of the function body when it was analysed
 
     pub output_type: Option<String>, // TODO: Remove?
 
@@ -38,30 +44,112 @@ pub struct Function {
 }
 
 
+// Functions in different modules may have the same name, so are identified by their module path.
+// Methods also include the type they are implemented for, e.g. module::<Foo as Trait>::method.
+pub fn qualified_name(module_path: &Vec<String>, ident_name: &str) -> String {
+    let mut path = module_path.clone();
+    path.push(ident_name.to_owned());
+    path.join("::")
+}
+
+// Identifies a statement in both stages, as spans can move between them
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StableID {
+    pub path: Vec<usize>, // Index of the statement in each enclosing block, outermost first
+    pub hash: u64, // Hash of the statement's source
+}
+
 // Depencency Tree
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum EncodedDependencyNode {
-    Expr(StmtID, Vec<usize>, EncodedInOutEnvironment), // Statement ID and Dependency indicies
-    Block(StmtID, EncodedDependencyTree, Vec<usize>, EncodedInOutEnvironment),
-    ExprBlock(StmtID, EncodedDependencyTree, Vec<usize>, EncodedInOutEnvironment),
-    Mac(StmtID, Vec<usize>, EncodedInOutEnvironment)
+    Expr(StableID, Vec<usize>, EncodedInOutEnvironment), // Statement ID and Dependency indicies
+    Block(StableID, EncodedDependencyTree, Vec<usize>, EncodedInOutEnvironment),
+    ExprBlock(StableID, EncodedDependencyTree, Vec<usize>, EncodedInOutEnvironment),
+    Mac(StableID, Vec<usize>, EncodedInOutEnvironment)
 }
 pub type EncodedDependencyTree = Vec<EncodedDependencyNode>;
 pub type EncodedEnvironment = Vec<Vec<(String, Vec<u32>)>>;
 pub type EncodedInOutEnvironment = (EncodedEnvironment, EncodedEnvironment);
 
+// Read from autoparallelise.toml, [package.metadata.autoparallelise] in Cargo.toml or autoparallelise.config, see plugin::config.
+// Any field can be left out to use its default.
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default = "Config::default")]
 pub struct Config {
-    pub plugin_enabled: bool,
+    pub plugin_enabled: bool, // Parallelises functions. When false the crate is compiled unchanged
     pub parallel_function_body: bool, // Wraps entire function body in a thread. Changes return type of function
-    pub parallel_for_loops: bool,
+    pub parallel_for_loops: bool, // Splits the iterations of for loops between threads
+    pub parallel_while_loops: bool, // Runs the header of while loops on one thread, handing the iterations to workers
+    pub loop_chunks: usize, // Number of chunks a parallel for loop is split into. 0 uses the number of cores
+    pub min_thread_work: u32, // Estimated work a branch or loop must do to be given threads, in cost model units
+    pub max_threads: usize, // Most threads a function runs at once, including the current thread, see Schedule::limit_threads. 0 is unlimited
+    pub instrument: bool, // Outputs sequential code which records the time taken by each statement into the profile
+    pub use_profile: bool, // Weights the schedule using the times recorded in the profile
+    pub call_costs: BTreeMap<String, u32>, // Estimated work of calls to functions or methods with these names, such as sleep = 1000. Other calls cost cost_model::CALL_COST
+    pub output_dir: Option<String>, // Writes <fn>.deptree.dot, <fn>.schedule.dot and <fn>.schedule.json for each function
+    pub cache: bool, // Reuses the analysis of functions that have not changed since the last build, see CACHE_FILE
+    pub log: String, // Log filter such as "warn" or "info,scheduler=trace", overridden by the AUTOPARALLELISE_LOG environment variable
+    pub functions: BTreeMap<String, FunctionConfig>, // Overrides for single functions, keyed by qualified name such as "module::function"
+}
+
+// Settings that can be changed for a single function. Fields that are left out keep the value from the crate's config.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FunctionConfig {
+    pub plugin_enabled: Option<bool>,
+    pub parallel_function_body: Option<bool>,
+    pub parallel_for_loops: Option<bool>,
+    pub parallel_while_loops: Option<bool>,
+    pub loop_chunks: Option<usize>,
+    pub min_thread_work: Option<u32>,
+    pub max_threads: Option<usize>,
+    pub instrument: Option<bool>,
+    pub use_profile: Option<bool>,
 }
+
 impl Config {
     pub fn default() -> Self {
         Config {
             plugin_enabled: true,
             parallel_function_body: false,
             parallel_for_loops: true,
+            parallel_while_loops: true,
+            loop_chunks: 0,
+            min_thread_work: cost_model::THREAD_COST,
+            max_threads: 0,
+            instrument: false,
+            use_profile: false,
+            call_costs: BTreeMap::new(),
+            output_dir: None,
+            cache: true,
+            log: "info".to_owned(),
+            functions: BTreeMap::new(),
+        }
+    }
+
+    // The config with any overrides for the function applied
+    pub fn for_function(&self, qualified_name: &str) -> Config {
+        let mut config = self.clone();
+        if let Some(overrides) = self.functions.get(qualified_name) {
+            config.plugin_enabled = overrides.plugin_enabled.unwrap_or(config.plugin_enabled);
+            config.parallel_function_body = overrides.parallel_function_body.unwrap_or(config.parallel_function_body);
+            config.parallel_for_loops = overrides.parallel_for_loops.unwrap_or(config.parallel_for_loops);
+            config.parallel_while_loops = overrides.parallel_while_loops.unwrap_or(config.parallel_while_loops);
+            config.loop_chunks = overrides.loop_chunks.unwrap_or(config.loop_chunks);
+            config.min_thread_work = overrides.min_thread_work.unwrap_or(config.min_thread_work);
+            config.max_threads = overrides.max_threads.unwrap_or(config.max_threads);
+            config.instrument = overrides.instrument.unwrap_or(config.instrument);
+            config.use_profile = overrides.use_profile.unwrap_or(config.use_profile);
+        }
+        config
+    }
+
+    pub fn num_loop_chunks(&self) -> usize {
+        // Also limited by max_threads when the loop is built, see reconstructor::spawn_from_schedule
+        if self.loop_chunks > 0 {
+            self.loop_chunks
+        } else {
+            utils::num_cpus()
         }
     }
 
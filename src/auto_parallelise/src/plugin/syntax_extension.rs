@@ -1,17 +1,24 @@
 use syntax::ptr::P;
 use syntax::ast::{self, ItemKind};
 use syntax::ext::base::{MultiItemModifier, ExtCtxt, Annotatable};
-use syntax_pos::Span;
+use syntax_pos::{BytePos, Span};
 use syntax::print::pprust;
 
 use serde_json;
 
 use AutoParallelise;
 use CompilerStage;
+use PROFILE_FILE;
+use REPORT_FILE;
+use CACHE_FILE;
+use HTML_SECTIONS_FILE;
+use utils;
+use utils::logging::{self, Level};
 
-use parallel_stages::{dependency_analysis, reconstructor, scheduler};
-use rendering::dot;
-use plugin::shared_state::Function;
+use parallel_stages::{dependency_analysis, reconstructor, scheduler, profiler, parallelism, verifier, fallback};
+use rendering::{dot, html};
+use plugin::cache::{self, AnalysisCache};
+use plugin::shared_state::{self, Function};
 
 
 impl MultiItemModifier for AutoParallelise {
@@ -20,88 +27,290 @@ impl MultiItemModifier for AutoParallelise {
         if !self.config.plugin_enabled || self.compiler_stage != CompilerStage::Modification {
             return vec![_item];
         }
+        // The first part of the module path is the crate
+        let module_path: Vec<String> = cx.current_expansion.module.mod_path.iter().skip(1).map(|ident| ident.name.to_string()).collect();
+        match _item {
+            Annotatable::Item(ref item) => {
+                let qualified_name = shared_state::qualified_name(&module_path, &item.ident.name.as_str());
+                self.expand_function(cx, item, qualified_name, false)
+            },
+            Annotatable::ImplItem(ref method) => {
+                // Methods are parallelised as functions, then turned back into methods
+                let item = match method_to_function(method) {
+                    Some(item) => P(item),
+                    None => {
+                        cx.span_err(method.span, "#[autoparallelise] can only be used on functions and methods");
+                        return vec![_item.clone()];
+                    },
+                };
+                let qualified_name = self.method_name(cx, &module_path, &item);
+                self.expand_function(cx, &item, qualified_name, true).into_iter().map(|annotatable| match annotatable {
+                    Annotatable::Item(function) => Annotatable::ImplItem(P(function_to_method(function.into_inner(), method))),
+                    annotatable => annotatable,
+                }).collect()
+            },
+            _ => {
+                cx.span_err(_span, "#[autoparallelise] can only be used on functions and methods");
+                vec![_item]
+            },
+        }
+    }
+}
+
+impl AutoParallelise {
+    fn expand_function(&self, cx: &mut ExtCtxt, item: &P<ast::Item>, qualified_name: String, is_method: bool) -> Vec<Annotatable> {
         let mut output = vec![];
-        // Unwrap item
-        if let Annotatable::Item(ref item) = _item {
-            // Find function name and the analysed function
-            let func_ident = item.ident;
-            let func_name = func_ident.name.to_string();
-            eprintln!("\n\n{:?}", func_name); // Function Id
-
-            if let ItemKind::Fn(ref _fndecl, ref _unsafety, ref _constness, ref _abi, ref _generics, ref _block) = item.node {
-                eprintln!("{:?}", _fndecl); // Function decl
-                eprintln!("Unsafety: {}", _unsafety);
-
-                // Find function from analysed stage
-                let mut maybe_analysed_function: Option<&Function> = None;
-                for func in &self.functions {
-                    if func.ident_name == func_name {
-                        maybe_analysed_function = Some(func);
+        // Find function name and the analysed function
+        let func_ident = item.ident;
+        let func_name = func_ident.name.to_string();
+        log_debug!(logging::PLUGIN, "Modifying {}", qualified_name);
+        let config = self.config.for_function(&qualified_name);
+        if !config.plugin_enabled {
+            return leave_unchanged(cx, item);
+        }
+
+        if let ItemKind::Fn(ref _fndecl, ref _unsafety, ref _constness, ref _abi, ref _generics, ref _block) = item.node {
+            log_trace!(logging::PLUGIN, "{:?}", _fndecl); // Function decl
+            log_trace!(logging::PLUGIN, "Unsafety: {}", _unsafety);
+
+            // Find function from analysed stage, ignoring it if the function has changed since
+            let source_hash = utils::source_hash(cx.codemap(), _block.span);
+            let mut maybe_analysed_function: Option<&Function> = None;
+            for func in &self.functions {
+                if func.ident_name == qualified_name && func.source_hash == source_hash {
+                    maybe_analysed_function = Some(func);
+                }
+            }
+            // Profile relative to this crate, as the instrumented code is run from elsewhere
+            let profile_path = utils::manifest_dir().join(PROFILE_FILE);
+
+            if config.instrument {
+                // Output the sequential function with timings instead of parallelising it
+                let instrumented_block = profiler::instrument_block(cx, &qualified_name, &profile_path, _block.clone());
+                let (_, instrumented_function) = reconstructor::create_function(cx, item, &func_name, false, instrumented_block.into_inner());
+                print_generated(cx, item, &[&instrumented_function]);
+                output.push(Annotatable::Item(P(instrumented_function)));
+            } else if let Some(analysed_function) = maybe_analysed_function {
+                // Merge the dependency trees
+                let mut base_deptree = match fallback::catch_unparallelisable(|| dependency_analysis::analyse_block(&_block)) {
+                    Ok(deptree) => deptree,
+                    Err(unparallelisable) => return leave_sequential(cx, item, &qualified_name, unparallelisable),
+                };
+                if let Err(errors) = dependency_analysis::replace_dependencies(&mut base_deptree, &analysed_function.encoded_deptree, cx.codemap()) {
+                    // Leave the function unchanged rather than parallelise it with the wrong dependencies
+                    for (stmtid, error) in errors {
+                        let span = match stmtid {
+                            Some((lo, hi)) => Span::new(BytePos(lo), BytePos(hi), item.span.ctxt()),
+                            None => item.span,
+                        };
+                        cx.span_err(span, &format!("{} does not match its analysis: {}. Rerun the analysis stage", qualified_name, error));
                     }
+                    return leave_unchanged(cx, item);
                 }
-                if let Some(analysed_function) = maybe_analysed_function {
-                    // Merge the dependency trees
-                    let mut base_deptree = dependency_analysis::analyse_block(&_block);
-                    dependency_analysis::replace_dependencies(&mut base_deptree, &analysed_function.encoded_deptree);
 
-                    eprintln!("DEPTREE:");
+                let deptree_dot = dot::deptree_to_dot(&base_deptree);
+                if logging::enabled(logging::ANALYSIS, Level::Debug) {
                     for node in &base_deptree {
                         let node_json = match serde_json::to_string_pretty(&node) {
                             Ok(obj) => obj,
                             Err(why) => panic!("Unable to convert deptree to JSON: {}", why),
                         };
-                        eprintln!("{}", node_json);
+                        log_debug!(logging::ANALYSIS, "DEPTREE:\n{}", node_json);
                     }
+                }
+                log_debug!(logging::ANALYSIS, "DOT deptree output:\n{}", deptree_dot);
 
-                    eprintln!("DOT deptree output:");
-                    eprintln!("{}", dot::deptree_to_dot(&base_deptree));
-
-                    // Produce a schedule
-                    let schedule = scheduler::create_schedule(&base_deptree);
-                    let schedule_json = match serde_json::to_string_pretty(&schedule) {
-                        Ok(obj) => obj,
-                        Err(why) => panic!("Unable to convert AutoParallelise to JSON: {}", why),
-                    };
-                    eprintln!("SCHEDULE:\n{}\n", schedule_json);
-
-                    eprintln!("DOT schedule output:");
-                    eprintln!("{}", dot::schedule_to_dot(&schedule));
-
-                    // Convert schedule into multi-threadded code
-                    let parstmts = reconstructor::spawn_from_schedule(&self.config, cx, schedule);
-                    let parblock = reconstructor::create_block(cx, parstmts, None);
-                    if self.config.parallel_function_body {
-                        // Surround function body in a thread
-                        let parthread = quote_stmt!(cx, ::std::thread::spawn(move || $parblock)).unwrap();
-                        let parthreadblock = reconstructor::create_block(cx, vec![parthread], None);
-                        // Convert function into use new_block
-                        let (parident, parfunction) = reconstructor::create_function(cx, item, &format!("{}_parallel", func_name), true, parthreadblock);
-                        let (seqident, seqfunction) = reconstructor::create_seq_fn(cx, &func_name, &parident, &item);
-                        // Prints the function
-                        println!("{}\n{}\n", pprust::item_to_string(&parfunction), pprust::item_to_string(&seqfunction));
-
-                        output.push(Annotatable::Item(P(parfunction)));
-                        output.push(Annotatable::Item(P(seqfunction)));
+                // Produce a schedule
+                let mut profile = if config.use_profile {
+                    profiler::load_profile(&profile_path, &qualified_name)
+                } else {
+                    profiler::Profile::new()
+                };
+                // Calls are not timed by the profile, so their costs come from the config
+                profile.call_costs = config.call_costs.clone();
+                // Not cached like the deptree and report, as it borrows base_deptree and is cheap to create
+                let mut schedule = match fallback::catch_unparallelisable(|| scheduler::create_schedule(&base_deptree, &profile)) {
+                    Ok(schedule) => schedule,
+                    Err(unparallelisable) => return leave_sequential(cx, item, &qualified_name, unparallelisable),
+                };
+                schedule.merge_small_branches(config.min_thread_work);
+                let limited = schedule.limit_threads(config.max_threads);
+                if let Err(errors) = verifier::verify_schedule(&base_deptree, &schedule) {
+                    // Leave the function sequential rather than output code that could deadlock or race
+                    let reason = if limited > 0 {
+                        format!("its schedule cannot run on {} threads without deadlocking", config.max_threads)
                     } else {
-                        // Do not surround in a thread
-                        let (parident, parfunction) = reconstructor::create_function(cx, item, &func_name, false, parblock);
-                        // Prints the function
-                        println!("{}\n", pprust::item_to_string(&parfunction));
+                        "its schedule failed verification".to_owned()
+                    };
+                    return leave_sequential(cx, item, &qualified_name, fallback::Unparallelisable {
+                        reason: reason,
+                        stmts: errors.into_iter().map(|(stmtid, error)| (stmtid, format!("statement {}", error))).collect(),
+                    });
+                }
 
-                        output.push(Annotatable::Item(P(parfunction)));
-                    }
+                // Reports depend on the profile as well as the config, so are not reused when it is used
+                let cache_path = self.state_path(CACHE_FILE);
+                let config_hash = cache::config_hash(&config);
+                let mut cache = if config.cache {
+                    AnalysisCache::load(&cache_path)
+                } else {
+                    AnalysisCache::new()
+                };
+                let cached_report = if config.use_profile {
+                    None
+                } else {
+                    cache.get_report(&qualified_name, source_hash, config_hash).cloned()
+                };
+                let report = match cached_report {
+                    Some(report) => report,
+                    None => parallelism::analyse_parallelism(&qualified_name, &base_deptree, &schedule, &profile),
+                };
+                parallelism::save_report(&self.state_path(REPORT_FILE), report.clone());
+                let schedule_json = match serde_json::to_string_pretty(&schedule) {
+                    Ok(obj) => obj,
+                    Err(why) => panic!("Unable to convert Schedule to JSON: {}", why),
+                };
+                if config.cache {
+                    cache.insert_report(&qualified_name, source_hash, config_hash, report);
+                    cache.save(&cache_path);
+                }
+                let schedule_dot = dot::schedule_to_dot(&schedule);
+                log_debug!(logging::SCHEDULER, "SCHEDULE:\n{}", schedule_json);
+                log_debug!(logging::SCHEDULER, "DOT schedule output:\n{}", schedule_dot);
+
+                // Add this function to the HTML report
+                let source = cx.codemap().span_to_snippet(item.span).ok();
+                let source = source.as_ref().map(|source| (&**source, item.span.lo().0));
+                let section = html::function_to_html(&qualified_name, source, &base_deptree, &schedule, &deptree_dot);
+                html::save_section(&self.state_path(HTML_SECTIONS_FILE), html::HtmlSection {
+                    function: qualified_name.clone(),
+                    html: section,
+                });
+
+                // Write the graphs for this function into the output directory
+                if let Some(output_dir) = self.output_dir() {
+                    let file_name = qualified_name.replace("::", ".");
+                    utils::write_file(&output_dir.join(format!("{}.deptree.dot", file_name)), &deptree_dot);
+                    utils::write_file(&output_dir.join(format!("{}.schedule.dot", file_name)), &schedule_dot);
+                    utils::write_file(&output_dir.join(format!("{}.schedule.json", file_name)), &schedule_json);
+                }
+
+                // Convert schedule into multi-threadded code
+                let parstmts = reconstructor::spawn_from_schedule(&config, cx, schedule);
+                let parblock = reconstructor::create_block(cx, parstmts, None);
+                let parblock = reconstructor::canonical_names(parblock, &base_deptree);
+                // Methods would need self to call the parallel function, so their body is not wrapped
+                if config.parallel_function_body && !is_method {
+                    // Surround function body in a thread
+                    let parthread = quote_stmt!(cx, ::std::thread::spawn(move || $parblock)).unwrap();
+                    let parthreadblock = reconstructor::create_block(cx, vec![parthread], None);
+                    // Convert function into use new_block
+                    let (parident, parfunction) = reconstructor::create_function(cx, item, &format!("{}_parallel", func_name), true, parthreadblock);
+                    let (seqident, seqfunction) = reconstructor::create_seq_fn(cx, &func_name, &parident, &item);
+                    // Prints the function
+                    print_generated(cx, item, &[&parfunction, &seqfunction]);
 
+                    output.push(Annotatable::Item(P(parfunction)));
+                    output.push(Annotatable::Item(P(seqfunction)));
                 } else {
-                    panic!("{} was not found as an analysed function", func_name);
+                    // Do not surround in a thread
+                    let (parident, parfunction) = reconstructor::create_function(cx, item, &func_name, false, parblock);
+                    // Prints the function
+                    print_generated(cx, item, &[&parfunction]);
+
+                    output.push(Annotatable::Item(P(parfunction)));
                 }
+
             } else {
-                panic!("ItemKind was not FN");
+                // Leave the function unchanged until it has been analysed again
+                cx.span_warn(item.span, &format!("{} has changed since it was analysed and will be analysed again", qualified_name));
+                self.mark_stale(&qualified_name);
+                return leave_unchanged(cx, item);
             }
         } else {
-            panic!("Annotatable was not Item");
+            panic!("ItemKind was not FN");
         }
 
         output
-        //vec![_item]
     }
+
+    // The linter names methods by their impl, which is not known here, so the name is found from the analysed
+    // functions in the same module with the same method name and body. Methods with the same body have the
+    // same analysis, so it does not matter which of them is found.
+    fn method_name(&self, cx: &ExtCtxt, module_path: &Vec<String>, item: &ast::Item) -> String {
+        let method_name = item.ident.name.to_string();
+        if let ItemKind::Fn(_, _, _, _, _, ref block) = item.node {
+            let source_hash = utils::source_hash(cx.codemap(), block.span);
+            let prefix: String = module_path.iter().map(|name| format!("{}::", name)).collect();
+            let suffix = format!("::{}", method_name);
+            for func in &self.functions {
+                if func.source_hash == source_hash && func.ident_name.len() > prefix.len() + suffix.len() &&
+                   func.ident_name.starts_with(&prefix) && func.ident_name.ends_with(&suffix) {
+                    return func.ident_name.clone();
+                }
+            }
+        }
+        // Changed since it was analysed, so it is left unchanged until it is analysed again
+        shared_state::qualified_name(module_path, &method_name)
+    }
+}
+
+fn method_to_function(method: &ast::ImplItem) -> Option<ast::Item> {
+    match method.node {
+        ast::ImplItemKind::Method(ref sig, ref block) => Some(ast::Item {
+            ident: method.ident,
+            attrs: method.attrs.clone(),
+            id: method.id,
+            node: ItemKind::Fn(sig.decl.clone(), sig.unsafety, sig.constness, sig.abi, method.generics.clone(), block.clone()),
+            vis: method.vis.clone(),
+            span: method.span,
+            tokens: None,
+        }),
+        _ => None,
+    }
+}
+
+// Keeps the attributes and visibility of the original method
+fn function_to_method(function: ast::Item, method: &ast::ImplItem) -> ast::ImplItem {
+    match function.node {
+        ItemKind::Fn(decl, unsafety, constness, abi, generics, block) => ast::ImplItem {
+            ident: function.ident,
+            generics: generics,
+            node: ast::ImplItemKind::Method(ast::MethodSig {
+                unsafety: unsafety,
+                constness: constness,
+                abi: abi,
+                decl: decl,
+            }, block),
+            ..method.clone()
+        },
+        _ => panic!("ItemKind was not FN"),
+    }
+}
+
+// Warns why the function could not be parallelised, pointing at each statement involved, and leaves it unchanged
+fn leave_sequential(cx: &mut ExtCtxt, item: &P<ast::Item>, qualified_name: &str, unparallelisable: fallback::Unparallelisable) -> Vec<Annotatable> {
+    let mut warning = cx.struct_span_warn(item.span, &format!("{} is left sequential: {}", qualified_name, unparallelisable.reason));
+    for ((lo, hi), note) in unparallelisable.stmts {
+        warning.span_note(Span::new(BytePos(lo), BytePos(hi), item.span.ctxt()), &note);
+    }
+    warning.emit();
+    leave_unchanged(cx, item)
+}
+
+// The generated code is read from stdout, so functions which are not changed are printed too
+fn leave_unchanged(cx: &ExtCtxt, item: &P<ast::Item>) -> Vec<Annotatable> {
+    print_generated(cx, item, &[item]);
+    vec![Annotatable::Item(item.clone())]
+}
+
+// Prints the items generated from an item, after a comment giving the lines of the source they replace
+fn print_generated(cx: &ExtCtxt, item: &ast::Item, generated: &[&ast::Item]) {
+    let lo = cx.codemap().lookup_char_pos(item.span.lo());
+    let hi = cx.codemap().lookup_char_pos(item.span.hi());
+    println!("// autoparallelise: {}:{}-{}", lo.file.name, lo.line, hi.line);
+    for generated_item in generated {
+        println!("{}", pprust::item_to_string(generated_item));
+    }
+    println!("");
 }
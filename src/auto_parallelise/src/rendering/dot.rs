@@ -1,7 +1,7 @@
 use syntax::print::pprust;
 
 use parallel_stages::{dependency_analysis, scheduler};
-use self::dependency_analysis::{DependencyTree, DependencyNode};
+use self::dependency_analysis::{DependencyTree, DependencyNode, StmtID};
 use self::scheduler::{Schedule, ScheduleTree};
 
 pub fn deptree_to_dot(deptree: &DependencyTree) -> String {
@@ -54,25 +54,69 @@ fn subdeptree_to_dot(prefix: &str, deptree: &DependencyTree) -> String {
 }
 
 
+// Colours given to each thread in schedule_to_dot
+const THREAD_COLOURS: [&str; 8] = ["lightblue", "lightpink", "palegreen", "khaki", "plum", "lightsalmon", "lightcyan", "wheat"];
+
 pub fn schedule_to_dot(schedule: &Schedule) -> String {
     let mut output = "".to_owned();
+    let mut num_threads = 1;
     output.push_str("digraph G {\n");
-    output.push_str(&subschedule_to_dot(schedule));
+    output.push_str("node [style=filled];\n");
+    output.push_str(&subschedule_to_dot(schedule.list(), None, 0, true, &mut num_threads));
     output.push_str("}\n");
     output
 }
 
-fn subschedule_to_dot(schedule: &Schedule) -> String {
+fn schedule_node_name(stmtid: &StmtID) -> String {
+    let &(lo, hi) = stmtid;
+    format!("\"{}-{}\"", lo, hi)
+}
+
+// Follows the reconstructor: every tree in a list except the last is given a new thread, unless it is sequential.
+// The blocks of an ExprBlock are all run on the thread of the ExprBlock.
+fn subschedule_to_dot(trees: &Vec<ScheduleTree>, parent: Option<&str>, thread: usize, spawns: bool, num_threads: &mut usize) -> String {
     let mut output = "".to_owned();
-    let schlist = schedule.list();
-    if schlist.len() > 1 {
+    for i in 0..trees.len() {
+        match &trees[i] {
+            &ScheduleTree::Node(_, ref tree) |
+            &ScheduleTree::Block(_, ref tree, _) => {
+                let tree_thread = if spawns && i + 1 < trees.len() && !tree.sequential {
+                    *num_threads += 1;
+                    *num_threads - 1
+                } else {
+                    thread
+                };
+                let colour = THREAD_COLOURS[tree_thread % THREAD_COLOURS.len()];
+                let name = schedule_node_name(&tree.node.get_stmtid());
+                let label = match tree.node.get_stmt() {
+                    Some(stmt) => pprust::stmt_to_string(stmt).replace("\"", "\\\""),
+                    None => "{ }".to_owned(),
+                };
+                output.push_str(&format!("{} [label=\"{}\", fillcolor={}, tooltip=\"thread {}, weight {}\"];\n", name, label, colour, tree_thread, tree.weight));
+                if let Some(parent_name) = parent {
+                    output.push_str(&format!("{} -> {};\n", parent_name, name));
+                }
 
-    }
-    for node in schlist {
-        match node {
-            &ScheduleTree::Node(ref prereqs, ref tree) => {},
-            &ScheduleTree::Block(ref prereqs, ref tree, ref subschedule) => {},
-            &ScheduleTree::SyncTo(ref to, ref from, ref env) => {},
+                // Inner schedule of the block
+                if let &ScheduleTree::Block(_, _, ref subschedule) = &trees[i] {
+                    let inner_spawns = match tree.node {
+                        &DependencyNode::ExprBlock(_, _, _, _) => false,
+                        _ => true,
+                    };
+                    let &(lo, hi) = &tree.node.get_stmtid();
+                    output.push_str(&format!("subgraph \"cluster{}-{}\" {{\n", lo, hi));
+                    output.push_str(&subschedule_to_dot(subschedule.list(), Some(&name), tree_thread, inner_spawns, num_threads));
+                    output.push_str("}\n");
+                }
+
+                output.push_str(&subschedule_to_dot(&tree.children, Some(&name), tree_thread, true, num_threads));
+            },
+            &ScheduleTree::SyncTo(ref from, ref to, ref env) => {
+                let vars: Vec<String> = env.clone().into_depstr().into_iter().map(|path| {
+                    path.into_iter().map(|(var, _)| var).collect::<Vec<String>>().join("::")
+                }).collect();
+                output.push_str(&format!("{} -> {} [style=dashed, color=red, label=\"{}\"];\n", schedule_node_name(from), schedule_node_name(to), vars.join(", ")));
+            },
         }
     }
     output
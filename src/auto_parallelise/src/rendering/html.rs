@@ -0,0 +1,319 @@
+use serde_json;
+use syntax::print::pprust;
+use std::path::Path;
+
+use utils;
+
+use parallel_stages::{dependency_analysis, scheduler};
+use self::dependency_analysis::{DependencyTree, DependencyNode, Environment, PathName, StmtID};
+use self::scheduler::{Schedule, ScheduleTree};
+
+// The report for a single function, saved as each function is modified
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HtmlSection {
+    pub function: String,
+    pub html: String,
+}
+
+// Sections are saved as each function is modified, replacing any older section for the same function
+pub fn save_section(path: &Path, section: HtmlSection) {
+    let mut sections = load_sections(path);
+    sections.retain(|old_section| old_section.function != section.function);
+    sections.push(section);
+    let sections_json = match serde_json::to_string(&sections) {
+        Ok(json) => json,
+        Err(why) => panic!("Unable to convert HTML report sections to JSON: {}", why),
+    };
+    utils::write_file(path, &sections_json);
+}
+
+pub fn load_sections(path: &Path) -> Vec<HtmlSection> {
+    match utils::read_file(&path.to_string_lossy()) {
+        Some(json) => match serde_json::from_str(&json) {
+            Ok(sections) => sections,
+            Err(why) => panic!("Unable to parse {} as json: {}", path.display(), why),
+        },
+        None => vec![],
+    }
+}
+
+// Everything is inlined so the report can be opened or shared without the rest of the output
+pub fn report_to_html(crate_name: &str, sections: &Vec<HtmlSection>) -> String {
+    let mut output = "".to_owned();
+    output.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    output.push_str(&format!("<title>auto_parallelise report for {}</title>\n", escape(crate_name)));
+    output.push_str(STYLE);
+    output.push_str("</head>\n<body>\n");
+    output.push_str(&format!("<h1>auto_parallelise report for {}</h1>\n", escape(crate_name)));
+    output.push_str("<ul class=\"contents\">\n");
+    for section in sections {
+        output.push_str(&format!("<li><a href=\"#fn-{0}\">{0}</a></li>\n", escape(&section.function)));
+    }
+    output.push_str("</ul>\n");
+    for section in sections {
+        output.push_str(&section.html);
+    }
+    output.push_str(SCRIPT);
+    output.push_str("</body>\n</html>\n");
+    output
+}
+
+// Source of the function is given with the position of its first byte, so statements can be found by their StmtID
+pub fn function_to_html(func_name: &str, source: Option<(&str, u32)>, deptree: &DependencyTree, schedule: &Schedule, deptree_dot: &str) -> String {
+    let mut output = "".to_owned();
+    output.push_str(&format!("<section id=\"fn-{}\">\n", escape(func_name)));
+    output.push_str(&format!("<h2>{}</h2>\n", escape(func_name)));
+    output.push_str("<div class=\"columns\">\n");
+
+    output.push_str("<div class=\"column\">\n<h3>Source</h3>\n");
+    match source {
+        Some((source, source_lo)) => {
+            let mut spans = vec![];
+            collect_spans(deptree, &mut spans);
+            output.push_str(&format!("<pre class=\"source\">{}</pre>\n", highlight_source(func_name, source, source_lo, spans)));
+        },
+        None => output.push_str("<p>Source unavailable</p>\n"),
+    }
+    output.push_str("</div>\n");
+
+    output.push_str("<div class=\"column\">\n<h3>Statement</h3>\n");
+    output.push_str("<p class=\"hint\">Click a statement to see why it depends on its predecessors</p>\n");
+    output.push_str(&deptree_details(func_name, deptree));
+    output.push_str("</div>\n");
+    output.push_str("</div>\n");
+
+    output.push_str("<h3>Schedule</h3>\n");
+    output.push_str(&schedule_to_list(func_name, schedule.list(), true));
+
+    output.push_str("<h3>Synclines</h3>\n");
+    let synclines = schedule.get_all_synclines();
+    if synclines.len() > 0 {
+        output.push_str("<table>\n<tr><th>From</th><th>To</th><th>Variables</th></tr>\n");
+        for (from, to, env) in synclines {
+            output.push_str(&format!("<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n", stmt_link(func_name, &from, &stmtid_name(&from)), stmt_link(func_name, &to, &stmtid_name(&to)), escape(&env_to_strings(env).join(", "))));
+        }
+        output.push_str("</table>\n");
+    } else {
+        output.push_str("<p>No synclines</p>\n");
+    }
+
+    // Not rendered, as that would need graphviz. The source can be pasted into dot -Tsvg
+    output.push_str("<details>\n<summary>Dependency graph (DOT source)</summary>\n");
+    output.push_str(&format!("<pre>{}</pre>\n", escape(deptree_dot)));
+    output.push_str("</details>\n");
+    output.push_str("</section>\n");
+    output
+}
+
+fn collect_spans(deptree: &DependencyTree, spans: &mut Vec<StmtID>) {
+    for node in deptree {
+        spans.push(node.get_stmtid());
+        match node {
+            &DependencyNode::Block(_, ref subtree, _, _) |
+            &DependencyNode::ExprBlock(_, ref subtree, _, _) => collect_spans(subtree, spans),
+            _ => {},
+        }
+    }
+}
+
+// Wraps each statement of the source in a span. Statement spans from the AST are nested, never overlapping.
+fn highlight_source(func_name: &str, source: &str, source_lo: u32, mut spans: Vec<StmtID>) -> String {
+    spans.retain(|&(lo, hi)| lo >= source_lo && hi <= source_lo + source.len() as u32 && lo < hi);
+    // Outer statements are opened before the statements inside them
+    spans.sort_by(|&(lo1, hi1), &(lo2, hi2)| lo1.cmp(&lo2).then(hi2.cmp(&hi1)));
+    spans.dedup();
+
+    let mut output = "".to_owned();
+    let mut open: Vec<u32> = vec![];
+    let mut next_span = 0;
+    for (i, c) in source.char_indices() {
+        let pos = source_lo + i as u32;
+        while open.last().map_or(false, |&hi| hi <= pos) {
+            open.pop();
+            output.push_str("</span>");
+        }
+        while next_span < spans.len() && spans[next_span].0 <= pos {
+            let stmtid = spans[next_span];
+            output.push_str(&format!("<span class=\"stmt\" data-stmt=\"{}\">", stmt_key(func_name, &stmtid)));
+            open.push(stmtid.1);
+            next_span += 1;
+        }
+        output.push_str(&escape(&c.to_string()));
+    }
+    for _ in open {
+        output.push_str("</span>");
+    }
+    output
+}
+
+// A hidden panel for each statement, shown when the statement is clicked
+fn deptree_details(func_name: &str, deptree: &DependencyTree) -> String {
+    let mut output = "".to_owned();
+    for node in deptree {
+        let stmtid = node.get_stmtid();
+        let &(ref inenv, ref outenv) = node.get_env();
+        output.push_str(&format!("<div class=\"details\" id=\"details-{}\">\n", stmt_key(func_name, &stmtid)));
+        output.push_str(&format!("<pre>{}</pre>\n", escape(&node_label(node))));
+        output.push_str(&format!("<p>Reads: {}</p>\n", escape(&env_to_strings(inenv).join(", "))));
+        output.push_str(&format!("<p>Writes: {}</p>\n", escape(&env_to_strings(outenv).join(", "))));
+
+        let deps = node.get_deps();
+        if deps.len() > 0 {
+            output.push_str("<p>Depends on:</p>\n<ul>\n");
+            for dep in deps {
+                let dep_node = &deptree[dep];
+                let reasons = dependency_reasons(node, dep_node);
+                output.push_str(&format!("<li>{} because of {}</li>\n", stmt_link(func_name, &dep_node.get_stmtid(), &node_label(dep_node)), escape(&reasons.join(", "))));
+            }
+            output.push_str("</ul>\n");
+        } else {
+            output.push_str("<p>No dependencies within its block</p>\n");
+        }
+        output.push_str("</div>\n");
+
+        match node {
+            &DependencyNode::Block(_, ref subtree, _, _) |
+            &DependencyNode::ExprBlock(_, ref subtree, _, _) => output.push_str(&deptree_details(func_name, subtree)),
+            _ => {},
+        }
+    }
+    output
+}
+
+// Parts of the environment that make the node wait for its dependency: reading what the dependency
+// writes, or writing what the dependency reads or writes
+fn dependency_reasons(node: &DependencyNode, dep_node: &DependencyNode) -> Vec<String> {
+    let &(ref inenv, ref outenv) = node.get_env();
+    let &(ref dep_inenv, ref dep_outenv) = dep_node.get_env();
+    let mut reasons = vec![];
+    for path in inenv.clone().into_iter() {
+        if dep_outenv.contains(&path) {
+            reasons.push(format!("reading {}", path_to_string(&path)));
+        }
+    }
+    for path in outenv.clone().into_iter() {
+        if dep_inenv.contains(&path) {
+            reasons.push(format!("writing {} after it is read", path_to_string(&path)));
+        }
+        if dep_outenv.contains(&path) {
+            reasons.push(format!("writing {} after it is written", path_to_string(&path)));
+        }
+    }
+    if reasons.len() == 0 {
+        reasons.push("ordering".to_owned());
+    }
+    reasons
+}
+
+fn schedule_to_list(func_name: &str, trees: &Vec<ScheduleTree>, spawns: bool) -> String {
+    if trees.len() == 0 {
+        return "".to_owned();
+    }
+    let mut output = "".to_owned();
+    output.push_str("<ul class=\"schedule\">\n");
+    for i in 0..trees.len() {
+        match &trees[i] {
+            &ScheduleTree::Node(ref prereqs, ref tree) |
+            &ScheduleTree::Block(ref prereqs, ref tree, _) => {
+                // Follows the reconstructor, see dot::schedule_to_dot
+                let placement = if spawns && i + 1 < trees.len() && !tree.sequential {
+                    "new thread"
+                } else {
+                    "current thread"
+                };
+                output.push_str(&format!("<li>{} <span class=\"info\">({}, weight {})</span>", stmt_link(func_name, &tree.node.get_stmtid(), &node_label(tree.node)), placement, tree.weight));
+                for &(ref stmtid, ref env) in prereqs {
+                    output.push_str(&format!(" <span class=\"syncline\">waits for {} carrying {}</span>", stmt_link(func_name, stmtid, &stmtid_name(stmtid)), escape(&env_to_strings(env).join(", "))));
+                }
+                output.push_str("\n");
+
+                if let &ScheduleTree::Block(_, _, ref subschedule) = &trees[i] {
+                    let inner_spawns = match tree.node {
+                        &DependencyNode::ExprBlock(_, _, _, _) => false,
+                        _ => true,
+                    };
+                    output.push_str(&schedule_to_list(func_name, subschedule.list(), inner_spawns));
+                }
+                output.push_str(&schedule_to_list(func_name, &tree.children, true));
+                output.push_str("</li>\n");
+            },
+            &ScheduleTree::SyncTo(_, ref to, ref env) => {
+                output.push_str(&format!("<li class=\"syncline\">sends {} to {}</li>\n", escape(&env_to_strings(env).join(", ")), stmt_link(func_name, to, &stmtid_name(to))));
+            },
+        }
+    }
+    output.push_str("</ul>\n");
+    output
+}
+
+fn node_label(node: &DependencyNode) -> String {
+    match node.get_stmt() {
+        Some(stmt) => pprust::stmt_to_string(stmt),
+        None => "{ }".to_owned(),
+    }
+}
+
+fn stmt_key(func_name: &str, stmtid: &StmtID) -> String {
+    let &(lo, hi) = stmtid;
+    format!("{}-{}-{}", escape(func_name), lo, hi)
+}
+
+fn stmtid_name(stmtid: &StmtID) -> String {
+    let &(lo, hi) = stmtid;
+    format!("{}-{}", lo, hi)
+}
+
+fn stmt_link(func_name: &str, stmtid: &StmtID, label: &str) -> String {
+    format!("<code class=\"stmt-link\" data-stmt=\"{}\">{}</code>", stmt_key(func_name, stmtid), escape(label))
+}
+
+fn path_to_string(path: &PathName) -> String {
+    path.iter().map(|ident| ident.name.to_string()).collect::<Vec<String>>().join("::")
+}
+
+fn env_to_strings(env: &Environment) -> Vec<String> {
+    let strings: Vec<String> = env.clone().into_iter().map(|path| path_to_string(&path)).collect();
+    if strings.len() == 0 {
+        vec!["nothing".to_owned()]
+    } else {
+        strings
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
+}
+
+const STYLE: &str = "<style>
+body { font-family: sans-serif; margin: 2em; }
+section { border-top: 1px solid #ccc; margin-top: 2em; }
+.columns { display: flex; }
+.column { flex: 1; min-width: 0; margin-right: 1em; }
+pre { background: #f6f6f6; padding: 0.5em; overflow-x: auto; }
+.stmt { cursor: pointer; border-bottom: 1px dotted #999; }
+.stmt:hover { background: #e8f0ff; }
+.stmt-link { cursor: pointer; color: #0645ad; }
+.selected { background: #ffe98a !important; }
+.details { display: none; }
+.info { color: #666; }
+.syncline { color: #c00; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; }
+</style>
+";
+
+// Only the innermost statement that was clicked is selected
+const SCRIPT: &str = "<script>
+document.addEventListener('click', function(event) {
+    var target = event.target.closest('[data-stmt]');
+    if (!target) { return; }
+    var key = target.getAttribute('data-stmt');
+    var section = target.closest('section');
+    section.querySelectorAll('.selected').forEach(function(elem) { elem.classList.remove('selected'); });
+    section.querySelectorAll('.details').forEach(function(elem) { elem.style.display = 'none'; });
+    section.querySelectorAll('[data-stmt=\"' + key + '\"]').forEach(function(elem) { elem.classList.add('selected'); });
+    var details = document.getElementById('details-' + key);
+    if (details) { details.style.display = 'block'; }
+});
+</script>
+";
@@ -1 +1,3 @@
 pub mod dot;
+pub mod report;
+pub mod html;
@@ -0,0 +1,12 @@
+use parallel_stages::parallelism::ParallelismReport;
+
+pub fn reports_to_table(reports: &Vec<ParallelismReport>) -> String {
+    let name_width = reports.iter().map(|report| report.function.len()).max().unwrap_or(0).max("Function".len());
+    let mut output = "".to_owned();
+    output.push_str(&format!("{:<width$} | {:>10} | {:>13} | {:>9} | {:>13}\n", "Function", "Total Work", "Critical Path", "Max Width", "Speedup Bound", width = name_width));
+    output.push_str(&format!("{}-+-{}-+-{}-+-{}-+-{}\n", "-".repeat(name_width), "-".repeat(10), "-".repeat(13), "-".repeat(9), "-".repeat(13)));
+    for report in reports {
+        output.push_str(&format!("{:<width$} | {:>10} | {:>13} | {:>9} | {:>13.2}\n", report.function, report.total_work, report.critical_path, report.max_width, report.speedup_bound, width = name_width));
+    }
+    output
+}
@@ -0,0 +1,193 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use sequential_program_generator::{self as generator, Block, ExprKind, StmtKind, Variable};
+
+use tests::*;
+use utils;
+use plugin::shared_state::Config;
+
+// Programs are generated and reduced by sequential-program-generator, as in generated-test.sh
+static NUM_PROGRAMS: usize = 3;
+static PROGRAM_SIZE: usize = 10;
+// Each build is run with different inputs, as a race may only show up for some of them
+static RUNS_PER_BUILD: usize = 10;
+// The parallel program is built for each of these max_threads, 0 is unlimited
+static THREAD_COUNTS: [usize; 4] = [0, 1, 2, 4];
+// Generated programs read stdin[0] to stdin[19] from their arguments
+static NUM_INPUTS: usize = 20;
+
+// The first build which failed, or run where the parallel program printed something different
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    pub max_threads: usize,
+    pub inputs: Vec<String>,
+    pub build_error: Option<String>,
+    pub sequential_output: String,
+    pub parallel_output: String,
+}
+
+// The generated programs are different every time, so this is run by hand with --ignored.
+// A failure can be made into a fixed test with check_program.
+#[test]
+#[ignore]
+fn generated_programs() {
+    for _ in 0..NUM_PROGRAMS {
+        check_program(generator::generate_block(PROGRAM_SIZE, &vec![], &vec![]));
+    }
+}
+
+// Covers each kind of generated statement, so the differential test always runs on something
+#[test]
+fn fixed_program() {
+    let a = Variable::Int("a".to_owned());
+    let i = Variable::Int("i".to_owned());
+    let x = Variable::Int("x".to_owned());
+    let l = Variable::List("l".to_owned());
+    let var = |var: &Variable| Box::new(ExprKind::Var(var.clone()));
+    check_program(vec![
+        StmtKind::NewVar(a.clone()),
+        StmtKind::NewVar(l.clone()),
+        StmtKind::ForRange(i.clone(), Box::new(ExprKind::Value(0)), Box::new(ExprKind::StdinValue(0)), vec![
+            StmtKind::Assignment(a.clone(), Box::new(ExprKind::Add(var(&a), Box::new(ExprKind::Mul(var(&i), Box::new(ExprKind::StdinValue(1))))))),
+            StmtKind::Push(l.clone(), var(&a)),
+        ]),
+        StmtKind::IfLt(var(&a), Box::new(ExprKind::StdinValue(2)), vec![
+            StmtKind::Pop(l.clone()),
+        ]),
+        StmtKind::ForList(x.clone(), l.clone(), vec![
+            StmtKind::Print(Box::new(ExprKind::Sub(var(&x), Box::new(ExprKind::StdinValue(3))))),
+        ]),
+        StmtKind::Print(var(&a)),
+        StmtKind::Print(var(&l)),
+    ]);
+}
+
+// Panics with the reduced program if any build fails, or any run differs from the sequential program
+pub fn check_program(program: Block) {
+    let inputs: Vec<Vec<String>> = (0..RUNS_PER_BUILD).map(run_inputs).collect();
+    let source = generator::program_to_string(&program);
+    let mismatch = match find_mismatch(&source, &THREAD_COUNTS, &inputs) {
+        Ok(mismatch) => mismatch,
+        Err(build_error) => panic!("The generated program does not compile\nProgram:\n{}\nBuild Output:\n{}", source, build_error),
+    };
+    if let Some(mismatch) = mismatch {
+        let (reduced, mismatch) = reduce(program, mismatch);
+        let reduced = generator::program_to_string(&reduced);
+        match mismatch.build_error {
+            Some(ref build_error) => panic!("Parallel build failed with max_threads = {}\nReduced Program:\n{}\nBuild Output:\n{}",
+                                            mismatch.max_threads, reduced, build_error),
+            None => panic!("Parallel output differs with max_threads = {} and inputs {}\nReduced Program:\n{}\nSequential Output:\n{}\nParallel Output:\n{}",
+                           mismatch.max_threads, mismatch.inputs.join(" "), reduced, mismatch.sequential_output, mismatch.parallel_output),
+        }
+    }
+}
+
+// Inputs between -20 and 20, different for every run but the same for every build
+fn run_inputs(run: usize) -> Vec<String> {
+    let mut seed = (run as u64).wrapping_mul(2654435761).wrapping_add(1);
+    (0..NUM_INPUTS).map(|_| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        format!("{}", ((seed >> 33) % 41) as i64 - 20)
+    }).collect()
+}
+
+// Builds the program sequentially and in parallel for each thread count, and compares the exact
+// output of every run. A parallel build that fails to compile is also a mismatch, but a program
+// which does not compile sequentially has nothing to compare so is an error.
+pub fn find_mismatch(program: &str, thread_counts: &[usize], inputs: &Vec<Vec<String>>) -> Result<Option<Mismatch>, String> {
+    let folder = create_program_crate(program);
+    let path = Path::new(&folder);
+    let mut build_config = Config::default();
+
+    // Sequential build
+    build_config.plugin_enabled = false;
+    compile(&build_config, &path);
+    if let Err(build_error) = build(&path) {
+        let _ = fs::remove_dir_all(&path);
+        return Err(build_error);
+    }
+    let sequential_outputs: Vec<String> = inputs.iter().map(|args| run_with_args(&path, args)).collect();
+
+    // Parallel builds
+    let mut mismatch = None;
+    for &max_threads in thread_counts {
+        build_config.plugin_enabled = true;
+        build_config.max_threads = max_threads;
+        let parallel_code = compile(&build_config, &path);
+        let parallel_folder = folder_code_and_compile(&parallel_code, &path);
+        let parallel_path = Path::new(&parallel_folder);
+        if let Err(build_error) = build(&parallel_path) {
+            mismatch = Some(Mismatch {
+                max_threads: max_threads,
+                inputs: vec![],
+                build_error: Some(build_error),
+                sequential_output: String::new(),
+                parallel_output: String::new(),
+            });
+        } else {
+            for (args, sequential_output) in inputs.iter().zip(&sequential_outputs) {
+                let parallel_output = run_with_args(&parallel_path, args);
+                if parallel_output != *sequential_output {
+                    mismatch = Some(Mismatch {
+                        max_threads: max_threads,
+                        inputs: args.clone(),
+                        build_error: None,
+                        sequential_output: sequential_output.clone(),
+                        parallel_output: parallel_output,
+                    });
+                    break;
+                }
+            }
+        }
+        let _ = fs::remove_dir_all(&parallel_path);
+        if mismatch.is_some() {
+            break;
+        }
+    }
+    let _ = fs::remove_dir_all(&path);
+    Ok(mismatch)
+}
+
+// The build error of a crate, it has already been built so this only checks the result
+fn build(path: &Path) -> Result<(), String> {
+    let output = Command::new("cargo").arg("build").arg("--release")
+                         .current_dir(&path)
+                         .output().expect(&format!("Unable to build {}", path.display()));
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("{}", String::from_utf8_lossy(&output.stderr)))
+    }
+}
+
+// A crate for the program which uses the plugin being tested
+fn create_program_crate(program: &str) -> String {
+    let folder = create_tmpfolder();
+    fs::create_dir_all(format!("{}/src/", folder)).expect(&format!("Unable to create {}/src", folder));
+    let manifest = format!("[package]\nname = \"generated\"\nversion = \"0.1.0\"\n\n[dependencies]\nauto_parallelise = {{ path = \"{}\" }}\n", env!("CARGO_MANIFEST_DIR"));
+    utils::write_file(Path::new(&format!("{}/Cargo.toml", folder)), &manifest);
+    let source = format!("#![feature(plugin)]\n#![plugin(auto_parallelise)]\n#[autoparallelise]\n{}", program.trim_left());
+    utils::write_file(Path::new(&format!("{}/src/main.rs", folder)), &source);
+    folder
+}
+
+// Removes statements while the outputs still differ, using the generator's delta debugging.
+// Only the failing build and inputs are checked again, and removing a declaration the rest of the
+// program needs makes it not compile, so that candidate is not kept.
+fn reduce(program: Block, mismatch: Mismatch) -> (Block, Mismatch) {
+    let thread_counts = [mismatch.max_threads];
+    let inputs = vec![mismatch.inputs.clone()];
+    let mut mismatch = mismatch;
+    let reduced = generator::reduce(program, &mut |candidate| {
+        match find_mismatch(&generator::program_to_string(candidate), &thread_counts, &inputs) {
+            Ok(Some(candidate_mismatch)) => {
+                mismatch = candidate_mismatch;
+                true
+            },
+            Ok(None) | Err(_) => false,
+        }
+    });
+    (reduced, mismatch)
+}
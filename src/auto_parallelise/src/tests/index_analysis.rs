@@ -0,0 +1,78 @@
+use syntax;
+use syntax::ast::Ident;
+use syntax::codemap::{FileName, FilePathMapping};
+use syntax::parse::{self, ParseSess};
+
+use parallel_stages::dependency_analysis::{self, DependencyNode, Environment};
+use parallel_stages::index_analysis::{self, IndexAccess};
+
+#[test]
+fn index_analysis_nested_index_write() {
+    assert_disjoint_writes("{ a[i][0] = x; }");
+}
+
+#[test]
+fn index_analysis_method_receiver_write() {
+    assert_disjoint_writes("{ a[i].push(x); }");
+}
+
+#[test]
+fn index_analysis_mutable_borrow_write() {
+    assert_disjoint_writes("{ let element = &mut a[i]; *element = x; }");
+}
+
+#[test]
+fn index_analysis_field_write() {
+    assert_disjoint_writes("{ a[i].field = x; }");
+}
+
+#[test]
+fn index_analysis_read_only() {
+    match find_access("{ let y = a[i] + a[i + 1]; }") {
+        Some(IndexAccess::ReadOnly) => {},
+        access => panic!("Expected a to be read only, got {:?}", access),
+    }
+}
+
+#[test]
+fn index_analysis_read_only_method_receiver() {
+    // Methods known not to mutate their receiver only read the element
+    match find_access("{ let y = a[i].clone(); let z = a[i + 1].len(); }") {
+        Some(IndexAccess::ReadOnly) => {},
+        access => panic!("Expected a to be read only, got {:?}", access),
+    }
+}
+
+#[test]
+fn index_analysis_carried_write() {
+    // Iteration i writes the element iteration i + 1 reads
+    let access = find_access("{ let y = a[i].len(); a[i + 1].push(y); }");
+    assert!(access.is_none(), "Expected a to be carried between iterations, got {:?}", access);
+}
+
+fn assert_disjoint_writes(body: &str) {
+    match find_access(body) {
+        Some(IndexAccess::DisjointWrites(_)) => {},
+        access => panic!("Expected {} to write to disjoint elements of a, got {:?}", body, access),
+    }
+}
+
+// How the body of a loop over i uses a, or None if a is not indexed independently
+fn find_access(body: &str) -> Option<IndexAccess> {
+    syntax::with_globals(|| {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let mut parser = parse::new_parser_from_source_str(&sess, FileName::Custom("body".to_owned()), body.to_owned());
+        let block = match parser.parse_block() {
+            Ok(block) => block,
+            Err(mut diagnostic) => {
+                diagnostic.emit();
+                panic!("Unable to parse {}", body);
+            },
+        };
+        let deptree = dependency_analysis::analyse_block(&block);
+        let nodes: Vec<&DependencyNode> = deptree.iter().collect();
+        let candidates = Environment::new(vec![vec![Ident::from_str("a")]]);
+        let mut indexed = index_analysis::find_independent_indexing(&nodes, Ident::from_str("i"), &candidates);
+        indexed.pop().map(|(_, access)| access)
+    })
+}
@@ -1,9 +1,18 @@
 pub mod static_programs;
-
-use std::path::Path;
+#[cfg(test)]
+pub mod differential;
+#[cfg(test)]
+pub mod snapshots;
+#[cfg(test)]
+pub mod verifier;
+#[cfg(test)]
+pub mod scheduler;
+pub mod index_analysis;
+
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use utils;
-use SAVE_FILE;
+use STATE_DIR;
 use CONFIG_FILE;
 use plugin::shared_state::Config;
 use std::fs;
@@ -13,19 +22,19 @@ fn clean(folder: &Path) {
     Command::new("cargo").arg("clean")
             .current_dir(&folder)
             .output().expect("Unable to run cargo clean");
-    Command::new("rm").arg(SAVE_FILE)
+    Command::new("rm").arg("-rf").arg(STATE_DIR)
             .current_dir(&folder)
-            .output().expect(&format!("Unable to rm {}", SAVE_FILE));
+            .output().expect(&format!("Unable to rm {}", STATE_DIR));
     Command::new("rm").arg(CONFIG_FILE)
             .current_dir(&folder)
             .output().expect(&format!("Unable to rm {}", CONFIG_FILE));
 }
 
 fn compile(build_config: &Config, folder: &Path) -> String {
-    // Remove the .autoparallelise file
-    Command::new("rm").arg(SAVE_FILE)
+    // Remove the state of any earlier compile
+    Command::new("rm").arg("-rf").arg(STATE_DIR)
             .current_dir(&folder)
-            .output().expect(&format!("Unable to rm {}", SAVE_FILE));
+            .output().expect(&format!("Unable to rm {}", STATE_DIR));
 
     // Set the config
     let mut config_path_buf = folder.to_path_buf();
@@ -63,9 +72,15 @@ fn create_tmpfolder() -> String {
 
 
 fn run(path: &Path) -> String {
+    run_with_args(path, &vec![])
+}
+
+fn run_with_args(path: &Path, args: &Vec<String>) -> String {
     let cmdoutput = Command::new("cargo")
             .arg("run")
 	    .arg("--release")
+            .arg("--")
+            .args(args)
             .current_dir(&path)
             .output()
             .expect(&format!("Unable to run {}", path.display()));
@@ -73,39 +88,87 @@ fn run(path: &Path) -> String {
 }
 
 
+// Each item printed by the plugin follows a line giving the lines of the source it replaces
+static GENERATED_PREFIX: &'static str = "// autoparallelise: ";
+
+// Code printed for the lines lo to hi of a source file
+struct GeneratedCode {
+    file: String,
+    lo: usize,
+    hi: usize,
+    code: String,
+}
+
+fn parse_generated_code(parallel_code: &String) -> Vec<GeneratedCode> {
+    let mut generated: Vec<GeneratedCode> = vec![];
+    for line in parallel_code.lines() {
+        if line.starts_with(GENERATED_PREFIX) {
+            // file:lo-hi, where the file may contain a colon
+            let location: Vec<&str> = line[GENERATED_PREFIX.len()..].rsplitn(2, ':').collect();
+            let lines: Vec<usize> = location[0].split('-').map(|line| line.parse::<usize>().unwrap()).collect();
+            generated.push(GeneratedCode {
+                file: location[1].to_owned(),
+                lo: lines[0],
+                hi: lines[1],
+                code: "".to_owned(),
+            });
+        } else if let Some(current) = generated.last_mut() {
+            current.code.push_str(line);
+            current.code.push_str("\n");
+        }
+    }
+    generated
+}
+
+fn source_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).expect(&format!("Unable to read {}", dir.display())) {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.append(&mut source_files(&path));
+        } else if path.extension().map(|extension| extension == "rs").unwrap_or(false) {
+            files.push(path);
+        }
+    }
+    files
+}
+
 fn folder_code_and_compile(parallel_code: &String, sequential_path: &Path) -> String {
     // Create temp folder
     let parallel_folder = create_tmpfolder();
-    fs::create_dir_all(format!("{}/src/", parallel_folder));
     println!("parallel_folder: {}", parallel_folder);
     let parallel_path = Path::new(&parallel_folder);
 
-    // Copy Cargo.toml into new folder
-    Command::new("cp").arg("Cargo.toml").arg(format!("{}/Cargo.toml", parallel_folder))
+    // Copy Cargo.toml and every source file into new folder
+    Command::new("cp").arg("-r").arg("Cargo.toml").arg("src").arg(fs::canonicalize(&parallel_path).unwrap())
             .current_dir(&sequential_path)
-            .output().expect("Unable to copy cargo.toml");
-
-    // Read in original source code to extract imports
-    let original_source = utils::read_file(&format!("{}/src/main.rs", sequential_path.display())).unwrap();
-    let mut import_str = original_source.lines().filter(|line| {
-        line.starts_with("use") || line.starts_with("extern") ||
-        (line.starts_with("#!") && !line.contains("plugin"))
-    }).fold("".to_owned(), |acc, x| {
-        let mut acc2 = acc.clone();
-        acc2.push_str(&x);
-        acc2.push_str("\n");
-        acc2
-    });
-
-    // Combine imports and paralell code
-    let mut parallel_code_with_imports = import_str;
-    parallel_code_with_imports.push_str(parallel_code);
-
-    // Save the code to src/main.rs
-    let mut pathbuf = parallel_path.to_path_buf();
-    pathbuf.push("src/main.rs");
-    let parallel_code_path = pathbuf.as_path();
-    utils::write_file(&parallel_code_path, &parallel_code_with_imports);
+            .output().expect("Unable to copy crate");
+
+    // Replace each function with the code printed for it, from the bottom of each file up so the
+    // line numbers of the functions above do not change
+    let mut generated = parse_generated_code(parallel_code);
+    generated.sort_by(|a, b| (&a.file, b.lo).cmp(&(&b.file, a.lo)));
+    for generated_code in generated {
+        let source_path = parallel_path.join(&generated_code.file);
+        let source = utils::read_file(&source_path.to_string_lossy()).expect(&format!("Unable to read {}", source_path.display()));
+        let mut lines: Vec<String> = source.lines().map(|line| line.to_owned()).collect();
+        lines.splice(generated_code.lo - 1..generated_code.hi, vec![generated_code.code]);
+        utils::write_file(&source_path, &lines.join("\n"));
+    }
+
+    // Remove the plugin, which is not needed by the generated code
+    for source_path in source_files(&parallel_path.join("src")) {
+        let source = utils::read_file(&source_path.to_string_lossy()).unwrap();
+        let source_without_plugin = source.lines().filter(|line| {
+            !line.trim().starts_with("#![plugin") && line.trim() != "#[autoparallelise]"
+        }).fold("".to_owned(), |acc, x| {
+            let mut acc2 = acc.clone();
+            acc2.push_str(&x);
+            acc2.push_str("\n");
+            acc2
+        });
+        utils::write_file(&source_path, &source_without_plugin);
+    }
 
     // Compile with plugin disabled and run
     let mut build_config = Config::default();
@@ -130,12 +193,17 @@ fn compare_outputs(sequential_output: &String, parallel_output: &String) {
 }
 
 fn test_foldered_program(folder: &str) {
+    test_foldered_program_with_config(folder, &Config::default());
+}
+
+// Builds the program sequentially and with the given config, and returns the code printed by the plugin
+fn test_foldered_program_with_config(folder: &str, config: &Config) -> String {
     // Create path and make sure it is clean
     let path = Path::new(&folder);
     clean(&path);
 
     // Configure build options
-    let mut build_config = Config::default();
+    let mut build_config = config.clone();
 
     // Sequential build
     println!("Sequential Build");
@@ -160,4 +228,14 @@ fn test_foldered_program(folder: &str) {
 
     // Remove parallel folder
     fs::remove_dir_all(&parallel_path);
+    parallel_code
+}
+
+// The code printed for the function with the given name
+fn generated_function(parallel_code: &String, name: &str) -> String {
+    let signature = format!("fn {}(", name);
+    match parse_generated_code(parallel_code).into_iter().find(|generated| generated.code.contains(&signature)) {
+        Some(generated) => generated.code,
+        None => panic!("No code was printed for {}", name),
+    }
 }
@@ -0,0 +1,58 @@
+use syntax;
+use syntax::codemap::{FileName, FilePathMapping};
+use syntax::parse::{self, ParseSess};
+
+use parallel_stages::{dependency_analysis, scheduler, profiler, verifier};
+
+// Four independent branches, three of which are spawned without a thread limit
+static BRANCHES: &'static str = "{ let a = slow_value(1); let b = slow_value(2); let c = slow_value(3); let d = slow_value(4); a + b + c + d }";
+// Two branches joined by a syncline, where the branch of b is spawned and a waits for it
+static SYNCLINE: &'static str = "{ let mut a = slow_value(1); let b = slow_value(2); a += b; let c = slow_value(3); a + c }";
+
+#[test]
+fn scheduler_unlimited_threads() {
+    with_limited_schedule(BRANCHES, 0, |limited, spawned| {
+        assert_eq!(limited, 0);
+        assert_eq!(spawned, 3);
+    });
+}
+
+#[test]
+fn scheduler_limit_threads() {
+    for max_threads in 1..4 {
+        with_limited_schedule(BRANCHES, max_threads, |limited, spawned| {
+            assert!(spawned <= max_threads - 1, "Spawned {} branches with max_threads = {}", spawned, max_threads);
+            assert_eq!(limited + spawned, 3);
+        });
+    }
+}
+
+#[test]
+fn scheduler_limit_threads_with_syncline() {
+    with_limited_schedule(SYNCLINE, 1, |_, spawned| {
+        assert_eq!(spawned, 0);
+    });
+}
+
+// Schedules the block with at most max_threads threads and verifies the schedule, then checks the
+// number of branches which were no longer spawned and the number which still are
+fn with_limited_schedule<C>(block: &str, max_threads: usize, check: C) where C: FnOnce(usize, usize) {
+    syntax::with_globals(|| {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let mut parser = parse::new_parser_from_source_str(&sess, FileName::Custom("block".to_owned()), block.to_owned());
+        let block = match parser.parse_block() {
+            Ok(block) => block,
+            Err(mut diagnostic) => {
+                diagnostic.emit();
+                panic!("Unable to parse {}", block);
+            },
+        };
+        let deptree = dependency_analysis::analyse_block(&block);
+        let mut schedule = scheduler::create_schedule(&deptree, &profiler::Profile::new());
+        let limited = schedule.limit_threads(max_threads);
+        if let Err(errors) = verifier::verify_schedule(&deptree, &schedule) {
+            panic!("Schedule with max_threads = {} failed verification: {:?}", max_threads, errors);
+        }
+        check(limited, schedule.spawned_branches());
+    })
+}
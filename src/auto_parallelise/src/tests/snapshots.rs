@@ -0,0 +1,130 @@
+use syntax;
+use syntax::codemap::{FileName, FilePathMapping};
+use syntax::ext::base::{DummyResolver, ExtCtxt};
+use syntax::ext::expand::ExpansionConfig;
+use syntax::parse::{self, ParseSess};
+use syntax::print::pprust;
+
+use serde_json;
+
+use std::env;
+use std::path::Path;
+
+use utils;
+use parallel_stages::{dependency_analysis, scheduler, reconstructor, profiler};
+use plugin::shared_state::Config;
+
+// Each snippet in SNAPSHOT_DIR is a block, and has a golden file for each stage next to it.
+// Found from the manifest, so the tests can be run from any directory.
+static SNAPSHOT_DIR: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/snapshots");
+// Set to rewrite the golden files with the current output, e.g. AUTOPARALLELISE_UPDATE_SNAPSHOTS=1 cargo test snapshots
+static UPDATE_ENV_VAR: &'static str = "AUTOPARALLELISE_UPDATE_SNAPSHOTS";
+
+#[test]
+fn snapshots_independent_statements() {
+    check_snapshots("independent_statements")
+}
+
+#[test]
+fn snapshots_syncline() {
+    check_snapshots("syncline")
+}
+
+#[test]
+fn snapshots_nested_block() {
+    check_snapshots("nested_block")
+}
+
+#[test]
+fn snapshots_for_loop() {
+    check_snapshots("for_loop")
+}
+
+#[test]
+fn snapshots_parallel_for_loop() {
+    check_snapshots_with_config("parallel_for_loop", &loop_config())
+}
+
+#[test]
+fn snapshots_reduction() {
+    check_snapshots_with_config("reduction", &loop_config())
+}
+
+#[test]
+fn snapshots_pipelined_while_loop() {
+    check_snapshots_with_config("pipelined_while_loop", &loop_config())
+}
+
+// Every loop is worth splitting, into the same number of chunks on any machine
+fn loop_config() -> Config {
+    let mut config = Config::default();
+    config.min_thread_work = 0;
+    config.loop_chunks = 2;
+    config
+}
+
+fn check_snapshots(name: &str) {
+    check_snapshots_with_config(name, &Config::default())
+}
+
+// Runs the analysis, scheduler and reconstructor in process, without the two compiler stages
+fn check_snapshots_with_config(name: &str, config: &Config) {
+    let snippet_path = Path::new(SNAPSHOT_DIR).join(format!("{}.rs", name));
+    let snippet = match utils::read_file(&snippet_path.to_string_lossy()) {
+        Some(snippet) => snippet,
+        None => panic!("Unable to read {}", snippet_path.display()),
+    };
+
+    syntax::with_globals(|| {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let block = {
+            let mut parser = parse::new_parser_from_source_str(&sess, FileName::Custom(name.to_owned()), snippet);
+            match parser.parse_block() {
+                Ok(block) => block,
+                Err(mut diagnostic) => {
+                    diagnostic.emit();
+                    panic!("Unable to parse {}", snippet_path.display());
+                },
+            }
+        };
+
+        let deptree = dependency_analysis::analyse_block(&block);
+        let deptree_json = match serde_json::to_string_pretty(&deptree) {
+            Ok(obj) => obj,
+            Err(why) => panic!("Unable to convert deptree to JSON: {}", why),
+        };
+        check_golden(name, "deptree.json", &deptree_json);
+
+        let schedule = scheduler::create_schedule(&deptree, &profiler::Profile::new());
+        let schedule_json = match serde_json::to_string_pretty(&schedule) {
+            Ok(obj) => obj,
+            Err(why) => panic!("Unable to convert Schedule to JSON: {}", why),
+        };
+        check_golden(name, "schedule.json", &schedule_json);
+
+        let mut resolver = DummyResolver;
+        let mut cx = ExtCtxt::new(&sess, ExpansionConfig::default("snapshots".to_owned()), &mut resolver);
+        let parstmts = reconstructor::spawn_from_schedule(config, &mut cx, schedule);
+        let parblock = reconstructor::create_block(&mut cx, parstmts, None);
+        let parblock = reconstructor::canonical_names(parblock, &deptree);
+        check_golden(name, "output.rs", &pprust::block_to_string(&parblock));
+    });
+}
+
+// Golden files are only written when asked to, so a missing golden file fails instead of passing
+fn check_golden(name: &str, stage: &str, actual: &str) {
+    let golden_path = Path::new(SNAPSHOT_DIR).join(format!("{}.{}", name, stage));
+    let actual = format!("{}\n", actual);
+    if env::var(UPDATE_ENV_VAR).is_ok() {
+        println!("Writing {}", golden_path.display());
+        utils::write_file(&golden_path, &actual);
+        return;
+    }
+    match utils::read_file(&golden_path.to_string_lossy()) {
+        Some(golden) => {
+            assert!(golden == actual, "{} is out of date, set {} to update it.\nExpected:\n{}\nActual:\n{}",
+                    golden_path.display(), UPDATE_ENV_VAR, golden, actual);
+        },
+        None => panic!("{} does not exist, set {} to create it.\nActual:\n{}", golden_path.display(), UPDATE_ENV_VAR, actual),
+    }
+}
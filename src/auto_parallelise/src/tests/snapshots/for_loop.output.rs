@@ -0,0 +1 @@
+{ let mut total = 0; for i in 0..10 { total += slow_value(i); } total }
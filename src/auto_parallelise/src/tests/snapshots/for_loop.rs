@@ -0,0 +1,7 @@
+{
+    let mut total = 0;
+    for i in 0..10 {
+        total += slow_value(i);
+    }
+    total
+}
@@ -0,0 +1,19 @@
+{
+    let (syncline_1_2_b_send, syncline_1_2_b_receive) =
+        ::std::sync::mpsc::channel();
+    let thread_1 =
+        ::std::thread::spawn(move ||
+                                 {
+                                     let b = slow_value(2);
+                                     syncline_1_2_b_send.send((b,)).unwrap()
+                                 });
+    let return_value =
+        {
+            let a = slow_value(1);
+            let (mut b,) = syncline_1_2_b_receive.recv().unwrap();
+            let c = a + b;
+            c
+        };
+    thread_1.join().unwrap();
+    return_value
+}
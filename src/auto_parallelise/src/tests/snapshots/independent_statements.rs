@@ -0,0 +1,6 @@
+{
+    let a = slow_value(1);
+    let b = slow_value(2);
+    let c = a + b;
+    c
+}
@@ -0,0 +1,9 @@
+{
+    let x = slow_value(1);
+    {
+        let y = x + 1;
+        use_value(y);
+    }
+    let z = x * 2;
+    z
+}
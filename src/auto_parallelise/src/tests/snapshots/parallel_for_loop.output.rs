@@ -0,0 +1,59 @@
+{
+    let (syncline_1_2_values_send, syncline_1_2_values_receive) =
+        ::std::sync::mpsc::channel();
+    let thread_1 =
+        ::std::thread::spawn(move ||
+                                 {
+                                     let mut values = vec!(0 ; 10);
+                                     syncline_1_2_values_send.send((values,)).unwrap()
+                                 });
+    let return_value =
+        {
+            let offset = slow_value(0);
+            let (mut values,) = syncline_1_2_values_receive.recv().unwrap();
+            {
+                let mut forloop_2_threads = vec!();
+                let mut forloop_2_chunks = vec!();
+                let mut forloop_2_items =
+                    (0..10).into_iter().collect::<Vec<_>>().into_iter();
+                let forloop_2_chunk_size =
+                    ::std::cmp::max(1,
+                                    (forloop_2_items.len() + 2usize - 1) /
+                                        2usize);
+                {
+                    while forloop_2_items.len() > 0 {
+                        let forloop_2_chunk: Vec<_> =
+                            forloop_2_items.by_ref().take(forloop_2_chunk_size).collect();
+                        let forloop_2_values_init = values.clone();
+                        forloop_2_chunks.push(forloop_2_chunk.clone());
+                        let offset = offset.clone();
+                        let forloop_2_thread =
+                            ::std::thread::spawn(move ||
+                                                     {
+                                                         let mut values =
+                                                             forloop_2_values_init;
+                                                         for i in
+                                                             forloop_2_chunk {
+                                                             values[i] =
+                                                                 slow_value(i)
+                                                                     + offset;
+                                                         }
+                                                         (values,)
+                                                     });
+                        forloop_2_threads.push(forloop_2_thread)
+                    }
+                }
+                for (forloop_2_thread, forloop_2_chunk) in
+                    forloop_2_threads.into_iter().zip(forloop_2_chunks) {
+                    let (mut forloop_2_values_partial,) =
+                        forloop_2_thread.join().unwrap();
+                    for i in forloop_2_chunk {
+                        values[i] = forloop_2_values_partial[i].clone()
+                    }
+                }
+            }
+            values
+        };
+    thread_1.join().unwrap();
+    return_value
+}
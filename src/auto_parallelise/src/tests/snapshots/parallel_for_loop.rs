@@ -0,0 +1,8 @@
+{
+    let offset = slow_value(0);
+    let mut values = vec![0; 10];
+    for i in 0..10 {
+        values[i] = slow_value(i) + offset;
+    }
+    values
+}
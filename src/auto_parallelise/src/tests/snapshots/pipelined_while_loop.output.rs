@@ -0,0 +1,78 @@
+{
+    let (syncline_1_2_checksum_send, syncline_1_2_checksum_receive) =
+        ::std::sync::mpsc::channel();
+    let thread_1 =
+        ::std::thread::spawn(move ||
+                                 {
+                                     let mut checksum = 0;
+                                     syncline_1_2_checksum_send.send((checksum,)).unwrap()
+                                 });
+    let return_value =
+        {
+            let mut lines = input.lines();
+            let (mut checksum,) =
+                syncline_1_2_checksum_receive.recv().unwrap();
+            let (mut checksum,) =
+                {
+                    let (whileloop_2_order_send_0,
+                         whileloop_2_order_receive_0) =
+                        ::std::sync::mpsc::channel();
+                    let mut whileloop_2_order_receive_i =
+                        whileloop_2_order_receive_0;
+                    let mut whileloop_2_senders = vec!();
+                    let mut whileloop_2_threads = vec!();
+                    for _ in 0..2usize {
+                        let (whileloop_2_work_send, whileloop_2_work) =
+                            ::std::sync::mpsc::channel();
+                        whileloop_2_senders.push(whileloop_2_work_send);
+                        let whileloop_2_thread =
+                            ::std::thread::spawn(move ||
+                                                     {
+                                                         for (whileloop_2_bindings,
+                                                              whileloop_2_order_receive,
+                                                              whileloop_2_order_send)
+                                                             in
+                                                             whileloop_2_work
+                                                             {
+                                                             let (mut line,) =
+                                                                 whileloop_2_bindings;
+                                                             let value =
+                                                                 slow_value(line);
+                                                             let (mut checksum,) =
+                                                                 whileloop_2_order_receive.recv().unwrap();
+                                                             checksum =
+                                                                 checksum * 31
+                                                                     + value;
+                                                             whileloop_2_order_send.send((checksum,)).unwrap()
+                                                         }
+                                                     });
+                        whileloop_2_threads.push(whileloop_2_thread);
+                    }
+                    whileloop_2_order_send_0.send((checksum,)).unwrap();
+                    let mut whileloop_2_iteration = 0;
+                    while let Some(line) = lines.next() {
+                        let (whileloop_2_order_send,
+                             whileloop_2_order_receive_new) =
+                            ::std::sync::mpsc::channel();
+                        let whileloop_2_order_receive =
+                            ::std::mem::replace(&mut whileloop_2_order_receive_i,
+                                                whileloop_2_order_receive_new);
+                        whileloop_2_senders[whileloop_2_iteration %
+                                                whileloop_2_senders.len()].send(((line,),
+                                                                                 whileloop_2_order_receive,
+                                                                                 whileloop_2_order_send)).unwrap();
+                        whileloop_2_iteration += 1
+                    }
+                    ::std::mem::drop(whileloop_2_senders);
+                    for whileloop_2_thread in whileloop_2_threads {
+                        whileloop_2_thread.join().unwrap();
+                    }
+                    let (mut checksum,) =
+                        whileloop_2_order_receive_i.recv().unwrap();
+                    (checksum,)
+                };
+            checksum
+        };
+    thread_1.join().unwrap();
+    return_value
+}
@@ -0,0 +1,9 @@
+{
+    let mut lines = input.lines();
+    let mut checksum = 0;
+    while let Some(line) = lines.next() {
+        let value = slow_value(line);
+        checksum = checksum * 31 + value;
+    }
+    checksum
+}
@@ -0,0 +1,60 @@
+{
+    let (syncline_0_2_total_send, syncline_0_2_total_receive) =
+        ::std::sync::mpsc::channel();
+    let thread_0 =
+        ::std::thread::spawn(move ||
+                                 {
+                                     let mut total = 0;
+                                     syncline_0_2_total_send.send((total,)).unwrap()
+                                 });
+    let return_value =
+        {
+            let mut found = vec!();
+            let (mut total,) = syncline_0_2_total_receive.recv().unwrap();
+            {
+                fn reduction_zero<T: Default>(_: &T) -> T { T::default() }
+                let mut forloop_2_threads = vec!();
+                let mut forloop_2_items =
+                    (0..10).into_iter().collect::<Vec<_>>().into_iter();
+                let forloop_2_chunk_size =
+                    ::std::cmp::max(1,
+                                    (forloop_2_items.len() + 2usize - 1) /
+                                        2usize);
+                {
+                    while forloop_2_items.len() > 0 {
+                        let forloop_2_chunk: Vec<_> =
+                            forloop_2_items.by_ref().take(forloop_2_chunk_size).collect();
+                        let forloop_2_found_init = reduction_zero(&found);
+                        let forloop_2_total_init = reduction_zero(&total);
+                        let forloop_2_thread =
+                            ::std::thread::spawn(move ||
+                                                     {
+                                                         let mut found =
+                                                             forloop_2_found_init;
+                                                         let mut total =
+                                                             forloop_2_total_init;
+                                                         for i in
+                                                             forloop_2_chunk {
+                                                             let value =
+                                                                 slow_value(i);
+                                                             total += value;
+                                                             found.push(value);
+                                                         }
+                                                         (found, total)
+                                                     });
+                        forloop_2_threads.push(forloop_2_thread)
+                    }
+                }
+                for forloop_2_thread in forloop_2_threads {
+                    let (mut forloop_2_found_partial,
+                         mut forloop_2_total_partial) =
+                        forloop_2_thread.join().unwrap();
+                    found.extend(forloop_2_found_partial);
+                    total += forloop_2_total_partial
+                }
+            }
+            (total, found)
+        };
+    thread_0.join().unwrap();
+    return_value
+}
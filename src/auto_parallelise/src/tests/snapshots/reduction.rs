@@ -0,0 +1,10 @@
+{
+    let mut total = 0;
+    let mut found = vec![];
+    for i in 0..10 {
+        let value = slow_value(i);
+        total += value;
+        found.push(value);
+    }
+    (total, found)
+}
@@ -0,0 +1,21 @@
+{
+    let (syncline_3_4_b_send, syncline_3_4_b_receive) =
+        ::std::sync::mpsc::channel();
+    let thread_1 =
+        ::std::thread::spawn(move ||
+                                 {
+                                     let mut b = slow_value(2);
+                                     b += slow_value(4);
+                                     syncline_3_4_b_send.send((b,)).unwrap()
+                                 });
+    let return_value =
+        {
+            let mut a = slow_value(1);
+            a += slow_value(3);
+            let (mut b,) = syncline_3_4_b_receive.recv().unwrap();
+            let c = a * b;
+            c
+        };
+    thread_1.join().unwrap();
+    return_value
+}
@@ -0,0 +1,8 @@
+{
+    let mut a = slow_value(1);
+    let mut b = slow_value(2);
+    a += slow_value(3);
+    b += slow_value(4);
+    let c = a * b;
+    c
+}
@@ -1,4 +1,5 @@
 use tests::*;
+use plugin::shared_state::Config;
 
 #[test]
 fn password_simple() {
@@ -14,3 +15,66 @@ fn simple_example() {
 fn fibinacci() {
     test_foldered_program("../fibinacci")
 }
+
+#[test]
+fn fallback_example() {
+    // Spawn every branch, so the declarations are used from other threads
+    let mut build_config = Config::default();
+    build_config.min_thread_work = 0;
+    let parallel_code = test_foldered_program_with_config("../fallback-example", &build_config);
+
+    // The nested function is declared before the threads that call it
+    let code = generated_function(&parallel_code, "sum_of_doubles");
+    let declared = code.find("fn double(").expect("double was not declared");
+    let spawned = code.find("spawn").expect("sum_of_doubles was not parallelised");
+    assert!(declared < spawned, "double is declared after a thread is spawned:\n{}", code);
+
+    // Left as it was
+    let code = generated_function(&parallel_code, "sum_of_pair");
+    assert!(!code.contains("spawn"), "sum_of_pair was parallelised:\n{}", code);
+}
+
+#[test]
+fn module_example() {
+    // Spawn every branch, so the functions are only left unchanged if they could not be found
+    let mut build_config = Config::default();
+    build_config.min_thread_work = 0;
+    let parallel_code = test_foldered_program_with_config("../module-example", &build_config);
+
+    // Each function and method was matched with its own analysis
+    let generated = parse_generated_code(&parallel_code);
+    assert_eq!(generated.len(), 4);
+    for generated_code in generated {
+        assert!(generated_code.code.contains("spawn"), "Not parallelised:\n{}", generated_code.code);
+    }
+}
+
+#[test]
+fn loop_example() {
+    // The loops are small, so are only split when any amount of work is worth a thread
+    let mut build_config = Config::default();
+    build_config.min_thread_work = 0;
+    build_config.loop_chunks = 3;
+    let parallel_code = test_foldered_program_with_config("../loop-example", &build_config);
+
+    // Each loop must have been split between threads rather than left sequential
+    for &(function, rewrite) in &[
+        ("print_offset_steps", "forloop_"),
+        ("print_running_total", "_order_send_0"),
+        ("print_indexed_steps", "_starts_shared"),
+        ("print_indexed_steps", "_steps_partial"),
+        ("print_slice_steps", "_chunk.iter()"),
+        ("print_slice_steps", ".collect::<Vec<_>>()"),
+        ("print_reductions", "_total_partial"),
+        ("print_reductions", "_parity_partial"),
+        ("print_reductions", "reduction_one"),
+        ("print_ordered_sums", "_order_send_0"),
+    ] {
+        let code = generated_function(&parallel_code, function);
+        assert!(code.contains(rewrite), "{} was not parallelised with {}:\n{}", function, rewrite, code);
+    }
+
+    // Adding floats is not associative, so mean must not be split into partial sums
+    let code = generated_function(&parallel_code, "print_ordered_sums");
+    assert!(!code.contains("_mean_partial"), "mean was reduced out of order:\n{}", code);
+}
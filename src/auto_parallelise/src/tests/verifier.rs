@@ -0,0 +1,178 @@
+use syntax;
+use syntax::ast::{Block, Ident};
+use syntax::codemap::{FileName, FilePathMapping};
+use syntax::parse::{self, ParseSess};
+
+use std::path::Path;
+
+use utils;
+use parallel_stages::{dependency_analysis, scheduler, profiler, verifier};
+use parallel_stages::dependency_analysis::{StmtID, Environment, DependencyNode};
+use parallel_stages::scheduler::ScheduleTree;
+
+// Two branches joined by a syncline, from the snapshot tests
+static SYNCLINE_SNIPPET: &'static str = "src/tests/snapshots/syncline.rs";
+// The body of a for loop over i, where x is local to each iteration
+static LOOP_BODY: &'static str = "{ let x = i + offset; total = total * 2 + x; }";
+
+#[test]
+fn verifier_accepts_schedule() {
+    with_syncline_schedule(|_| {}, |result| {
+        assert!(result.is_ok(), "Expected schedule to pass verification: {:?}", result);
+    });
+}
+
+#[test]
+fn verifier_rejects_missing_syncto() {
+    with_syncline_schedule(|trees| {
+        remove_synctos(trees);
+    }, |result| {
+        assert_rejected(result, "nothing sends it");
+    });
+}
+
+#[test]
+fn verifier_rejects_unsatisfiable_receive() {
+    // The producer of the syncline also waits for its consumer, which only runs after the producer
+    with_syncline_schedule(|trees| {
+        let (from, to, env) = first_syncto(trees).expect("Schedule has no synclines");
+        find_tree(trees, from).and_then(|tree| tree.get_deps_mut()).unwrap().push((to, env.clone()));
+        let consumer = find_tree(trees, to).and_then(|tree| tree.get_spanning_tree_mut()).unwrap();
+        consumer.children.push(ScheduleTree::SyncTo(to, from, env));
+    }, |result| {
+        assert_rejected(result, "deadlocks");
+    });
+}
+
+#[test]
+fn verifier_accepts_loop_rewrite() {
+    // total is carried, so only the let statement runs out of order
+    with_loop_rewrite(&["offset"], &["total"], 1, |result| {
+        assert!(result.is_ok(), "Expected loop rewrite to pass verification: {:?}", result);
+    });
+}
+
+#[test]
+fn verifier_rejects_written_shared_variable() {
+    with_loop_rewrite(&["offset", "total"], &[], 2, |result| {
+        assert_rejected(result, "which every thread has a copy of");
+    });
+}
+
+#[test]
+fn verifier_rejects_unordered_carried_variable() {
+    with_loop_rewrite(&["offset"], &["total"], 2, |result| {
+        assert_rejected(result, "uses carried `total`");
+    });
+}
+
+#[test]
+fn verifier_rejects_unhandled_variable() {
+    with_loop_rewrite(&["offset"], &[], 1, |result| {
+        assert_rejected(result, "uses `total`, which is not shared, split or carried");
+    });
+}
+
+fn parse_snippet(name: &str, snippet: String) -> Block {
+    let sess = ParseSess::new(FilePathMapping::empty());
+    let mut parser = parse::new_parser_from_source_str(&sess, FileName::Custom(name.to_owned()), snippet);
+    let block = match parser.parse_block() {
+        Ok(block) => block,
+        Err(mut diagnostic) => {
+            diagnostic.emit();
+            panic!("Unable to parse {}", name);
+        },
+    };
+    block.into_inner()
+}
+
+fn env(names: &[&str]) -> Environment {
+    Environment::new(names.iter().map(|name| vec![Ident::from_str(name)]).collect())
+}
+
+// Verifies LOOP_BODY split between threads with the given shared and carried variables, where the
+// first num_unordered statements of the body run out of order
+fn with_loop_rewrite<C>(shared: &[&str], carried: &[&str], num_unordered: usize, check: C) where C: FnOnce(Result<(), Vec<verifier::ScheduleError>>) {
+    syntax::with_globals(|| {
+        let block = parse_snippet("loop_body", LOOP_BODY.to_owned());
+        let deptree = dependency_analysis::analyse_block(&block);
+        let body: Vec<&DependencyNode> = deptree.iter().collect();
+        let unordered = block.stmts[..num_unordered].to_vec();
+        check(verifier::verify_loop_rewrite((0, 0), &verifier::LoopRewrite {
+            body: &body,
+            uses: &env(&["offset", "total"]),
+            header: &Environment::empty(),
+            shared: &env(shared),
+            split: &Environment::empty(),
+            carried: &env(carried),
+            unordered: &unordered,
+        }));
+    });
+}
+
+// Schedules the snippet, changes the schedule and then checks the result of verifying it
+fn with_syncline_schedule<F, C>(change: F, check: C) where F: FnOnce(&mut Vec<ScheduleTree>), C: FnOnce(Result<(), Vec<verifier::ScheduleError>>) {
+    let snippet_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(SYNCLINE_SNIPPET);
+    let snippet = match utils::read_file(&snippet_path.to_string_lossy()) {
+        Some(snippet) => snippet,
+        None => panic!("Unable to read {}", snippet_path.display()),
+    };
+
+    syntax::with_globals(|| {
+        let block = parse_snippet("syncline", snippet);
+        let deptree = dependency_analysis::analyse_block(&block);
+        let mut schedule = scheduler::create_schedule(&deptree, &profiler::Profile::new());
+        change(schedule.list_mut());
+        check(verifier::verify_schedule(&deptree, &schedule));
+    });
+}
+
+fn assert_rejected(result: Result<(), Vec<verifier::ScheduleError>>, expected: &str) {
+    match result {
+        Ok(()) => panic!("Expected verification to fail with \"{}\"", expected),
+        Err(errors) => assert!(errors.iter().any(|&(_, ref error)| error.contains(expected)),
+                               "Expected an error containing \"{}\", got {:?}", expected, errors),
+    }
+}
+
+fn remove_synctos(trees: &mut Vec<ScheduleTree>) {
+    trees.retain(|tree| match tree {
+        &ScheduleTree::SyncTo(_, _, _) => false,
+        _ => true,
+    });
+    for tree in trees.iter_mut() {
+        if let Some(spanning_tree) = tree.get_spanning_tree_mut() {
+            remove_synctos(&mut spanning_tree.children);
+        }
+    }
+}
+
+fn first_syncto(trees: &Vec<ScheduleTree>) -> Option<(StmtID, StmtID, Environment)> {
+    for tree in trees {
+        match tree {
+            &ScheduleTree::SyncTo(from, to, ref env) => return Some((from, to, env.clone())),
+            _ => if let Some(syncto) = tree.get_spanning_tree().and_then(|spanning_tree| first_syncto(&spanning_tree.children)) {
+                return Some(syncto);
+            },
+        }
+    }
+    None
+}
+
+fn find_tree<'a, 'b>(trees: &'b mut Vec<ScheduleTree<'a>>, stmtid: StmtID) -> Option<&'b mut ScheduleTree<'a>> {
+    for tree in trees.iter_mut() {
+        let found = match tree.get_spanning_tree() {
+            Some(spanning_tree) => spanning_tree.node.get_stmtid() == stmtid,
+            None => false,
+        };
+        if found {
+            return Some(tree);
+        }
+        if let Some(spanning_tree) = tree.get_spanning_tree_mut() {
+            if let Some(found_tree) = find_tree(&mut spanning_tree.children, stmtid) {
+                return Some(found_tree);
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,119 @@
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+
+// Overrides the log filter in the config, e.g. AUTOPARALLELISE_LOG=warn,scheduler=trace
+pub static LOG_ENV_VAR: &'static str = "AUTOPARALLELISE_LOG";
+
+// Each stage logs to its own target so it can be traced on its own
+pub static PLUGIN: &'static str = "plugin";
+pub static ANALYSIS: &'static str = "analysis";
+pub static SCHEDULER: &'static str = "scheduler";
+pub static RECONSTRUCTOR: &'static str = "reconstructor";
+pub static PROFILER: &'static str = "profiler";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn parse(name: &str) -> Option<Level> {
+        match &*name.trim().to_lowercase() {
+            "off" => Some(Level::Off),
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            &Level::Off => "OFF",
+            &Level::Error => "ERROR",
+            &Level::Warn => "WARN",
+            &Level::Info => "INFO",
+            &Level::Debug => "DEBUG",
+            &Level::Trace => "TRACE",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A default level, followed by levels for individual targets: "info,scheduler=trace,reconstructor=debug"
+#[derive(Clone, Debug)]
+pub struct LogFilter {
+    default: Level,
+    targets: Vec<(String, Level)>,
+}
+
+impl LogFilter {
+    pub fn parse(spec: &str) -> Self {
+        let mut filter = LogFilter {
+            default: Level::Info,
+            targets: vec![],
+        };
+        for part in spec.split(',').map(|part| part.trim()).filter(|part| part.len() > 0) {
+            let mut sections = part.splitn(2, '=');
+            let first = sections.next().unwrap();
+            match sections.next() {
+                Some(level) => match Level::parse(level) {
+                    Some(level) => filter.targets.push((first.trim().to_owned(), level)),
+                    None => panic!("Unknown log level {} for {} in log filter {:?}", level, first, spec),
+                },
+                None => match Level::parse(first) {
+                    Some(level) => filter.default = level,
+                    None => panic!("Unknown log level {} in log filter {:?}", first, spec),
+                },
+            }
+        }
+        filter
+    }
+
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        // Later entries for the same target take priority
+        let max_level = match self.targets.iter().rev().find(|&&(ref name, _)| name == target) {
+            Some(&(_, max_level)) => max_level,
+            None => self.default,
+        };
+        level != Level::Off && level <= max_level
+    }
+}
+
+// The compiler runs the plugin on a single thread, so the filter is kept per thread
+thread_local! {
+    static FILTER: RefCell<LogFilter> = RefCell::new(LogFilter::parse("info"));
+}
+
+// The environment variable takes priority over the config
+pub fn init(config_spec: &str) {
+    let spec = env::var(LOG_ENV_VAR).unwrap_or(config_spec.to_owned());
+    let new_filter = LogFilter::parse(&spec);
+    FILTER.with(|filter| *filter.borrow_mut() = new_filter);
+}
+
+// Used to skip building expensive debug output that would not be shown
+pub fn enabled(target: &str, level: Level) -> bool {
+    FILTER.with(|filter| filter.borrow().enabled(target, level))
+}
+
+pub fn log(target: &str, level: Level, args: fmt::Arguments) {
+    if !enabled(target, level) {
+        return;
+    }
+    if level == Level::Info {
+        eprintln!("[auto_parallelise] {}", args);
+    } else {
+        eprintln!("[auto_parallelise {} {}] {}", level, target, args);
+    }
+}
@@ -2,3 +2,23 @@
 macro_rules! stmtID {
     ($i:ident) => {{($i.span.lo().0, $i.span.hi().0)}}
 }
+
+// Logging to a stage target, see utils::logging
+macro_rules! log_at {
+    ($target:expr, $level:expr, $($arg:tt)+) => {{::utils::logging::log($target, $level, format_args!($($arg)+))}}
+}
+macro_rules! log_error {
+    ($target:expr, $($arg:tt)+) => {{log_at!($target, ::utils::logging::Level::Error, $($arg)+)}}
+}
+macro_rules! log_warn {
+    ($target:expr, $($arg:tt)+) => {{log_at!($target, ::utils::logging::Level::Warn, $($arg)+)}}
+}
+macro_rules! log_info {
+    ($target:expr, $($arg:tt)+) => {{log_at!($target, ::utils::logging::Level::Info, $($arg)+)}}
+}
+macro_rules! log_debug {
+    ($target:expr, $($arg:tt)+) => {{log_at!($target, ::utils::logging::Level::Debug, $($arg)+)}}
+}
+macro_rules! log_trace {
+    ($target:expr, $($arg:tt)+) => {{log_at!($target, ::utils::logging::Level::Trace, $($arg)+)}}
+}
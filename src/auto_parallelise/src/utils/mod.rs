@@ -1,8 +1,14 @@
+use syntax::codemap::CodeMap;
+use syntax_pos::Span;
+use std::collections::hash_map::DefaultHasher;
+use std::env;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub mod macros;
+pub mod logging;
 //pub mod noqueue_threadpool;
 
 pub fn write_file(path: &Path, contents: &String) {
@@ -36,3 +42,30 @@ pub fn read_file(filename: &str) -> Option<String> {
     }
     return Some(s);
 }
+
+// Number of logical cores on the compiling machine, used when the config leaves it unset
+pub fn num_cpus() -> usize {
+    match read_file("/proc/cpuinfo") {
+        Some(cpuinfo) => {
+            let num = cpuinfo.lines().filter(|line| line.starts_with("processor")).count();
+            if num > 0 { num } else { 1 }
+        },
+        None => 1,
+    }
+}
+
+// Hash of the source text under a span, used to notice code that has changed since it was analysed
+pub fn source_hash(codemap: &CodeMap, span: Span) -> u64 {
+    let source = codemap.span_to_snippet(span).unwrap_or("".to_owned());
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Cargo runs the compiler from the workspace root, so files of the crate are found from its manifest directory
+pub fn manifest_dir() -> PathBuf {
+    match env::var("CARGO_MANIFEST_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => env::current_dir().unwrap_or(PathBuf::from(".")),
+    }
+}
@@ -0,0 +1,33 @@
+#![feature(plugin)]
+#![plugin(auto_parallelise())]
+
+// The nested function is declared before any thread is spawned, and total is assigned on the thread that declared it
+#[autoparallelise]
+fn sum_of_doubles(a: i32, b: i32) -> i32 {
+    fn double(x: i32) -> i32 {
+        x * 2
+    }
+    let total;
+    let a = double(double(double(a)));
+    let b = double(double(double(b)));
+    total = a + b;
+    total
+}
+
+// The destructuring let cannot be analysed, so only this function is left sequential
+#[autoparallelise]
+fn sum_of_pair(pair: (i32, i32)) -> i32 {
+    let (a, b) = pair;
+    a + b
+}
+
+#[autoparallelise]
+fn main() {
+    let mut a = 4;
+    let mut b = 3;
+    a += 1;
+    b += 1;
+    println!("{}, {}", a, b);
+    println!("{}", sum_of_doubles(a, b));
+    println!("{}", sum_of_pair((a, b)));
+}
@@ -0,0 +1,97 @@
+#![feature(plugin)]
+#![plugin(auto_parallelise())]
+
+// Enough work for each iteration that the loops below are split between threads
+fn collatz_steps(start: i64) -> i64 {
+    let mut n = start;
+    let mut steps = 0;
+    while n != 1 {
+        n = if n % 2 == 0 { n / 2 } else { 3 * n + 1 };
+        steps += 1;
+    }
+    steps
+}
+
+// The iterations only read offset, so each chunk gets its own copy of it
+#[autoparallelise]
+fn print_offset_steps() {
+    let offset = 1000;
+    for i in 1..9 {
+        let start = i + offset;
+        let steps = collatz_steps(start);
+        println!("steps({}) = {}", start, steps);
+    }
+}
+
+// total and last are carried between iterations, so each chunk waits for the one before to hand them over
+#[autoparallelise]
+fn print_running_total() {
+    let mut total = 0;
+    let mut last = 0;
+    for i in 1..9 {
+        let steps = collatz_steps(i);
+        total = total * 3 + steps - last;
+        last = steps;
+    }
+    println!("total = {}, last = {}", total, last);
+}
+
+// Each iteration writes its own element of steps, and only reads starts, so the chunks share starts
+#[autoparallelise]
+fn print_indexed_steps() {
+    let starts: Vec<i64> = (1..9).map(|i| i * 7).collect();
+    let mut steps = vec![0; 8];
+    for i in 0..8 {
+        steps[i] = collatz_steps(starts[i].clone());
+    }
+    println!("steps = {:?}", steps);
+}
+
+// Slices are split by index, and iterators which own their items are collected then split
+#[autoparallelise]
+fn print_slice_steps() {
+    let starts: Vec<i64> = (20..28).collect();
+    for start in &starts[2..] {
+        println!("slice steps({}) = {}", start, collatz_steps(*start));
+    }
+    for start in starts.clone() {
+        println!("owned steps({}) = {}", start, collatz_steps(start));
+    }
+}
+
+// Integers are summed and multiplied, and vectors pushed to, by each chunk separately then combined
+#[autoparallelise]
+fn print_reductions() {
+    let mut total = 0;
+    let mut parity: i8 = 1;
+    let mut all_steps = vec![];
+    for i in 1..6 {
+        let steps = collatz_steps(i);
+        total += steps;
+        parity *= (steps % 2) as i8 + 1;
+        all_steps.push(steps);
+    }
+    println!("total = {}, parity = {}, all = {:?}", total, parity, all_steps);
+}
+
+// Adding floats and strings depends on the order, so mean and names are carried between chunks instead
+#[autoparallelise]
+fn print_ordered_sums() {
+    let mut mean = 0.0;
+    let mut names = String::new();
+    for i in 1..9 {
+        let steps = collatz_steps(i) as f64;
+        mean += steps / 8.0;
+        names += &format!("{} ", i);
+    }
+    println!("mean = {}, names = {}", mean, names);
+}
+
+fn main() {
+    print_offset_steps();
+    print_running_total();
+    print_indexed_steps();
+    print_slice_steps();
+    print_reductions();
+    print_ordered_sums();
+}
@@ -0,0 +1,5 @@
+#![feature(plugin)]
+#![plugin(auto_parallelise())]
+
+pub mod shapes;
+pub mod numbers;
@@ -0,0 +1,10 @@
+extern crate module_example;
+
+use module_example::{shapes, numbers};
+
+fn main() {
+    shapes::print_totals(3, 4);
+    numbers::print_totals(5, 6);
+    shapes::Rectangle { width: 3, height: 4 }.print_totals();
+    shapes::Square { side: 5 }.print_totals();
+}
@@ -0,0 +1,8 @@
+#[autoparallelise]
+pub fn print_totals(a: u32, b: u32) {
+    let mut sum = a + b;
+    let mut product = a * b;
+    sum += 1;
+    product += 1;
+    println!("{}, {}", sum, product);
+}
@@ -0,0 +1,44 @@
+// Both modules have a function called print_totals, which are told apart by their module
+#[autoparallelise]
+pub fn print_totals(width: u32, height: u32) {
+    let mut area = width * height;
+    let mut perimeter = 2 * (width + height);
+    area += 1;
+    perimeter += 1;
+    println!("{}, {}", area, perimeter);
+}
+
+#[derive(Clone, Copy)]
+pub struct Rectangle {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Copy)]
+pub struct Square {
+    pub side: u32,
+}
+
+// Methods of different types with the same name are told apart by their impl. They take self by value, as
+// borrowed variables cannot be moved into the spawned threads.
+impl Rectangle {
+    #[autoparallelise]
+    pub fn print_totals(self) {
+        let mut area = self.width * self.height;
+        let mut perimeter = 2 * (self.width + self.height);
+        area += 1;
+        perimeter += 1;
+        println!("rectangle {}, {}", area, perimeter);
+    }
+}
+
+impl Square {
+    #[autoparallelise]
+    pub fn print_totals(self) {
+        let mut area = self.side * self.side;
+        let mut perimeter = 4 * self.side;
+        area += 1;
+        perimeter += 1;
+        println!("square {}, {}", area, perimeter);
+    }
+}
@@ -1,7 +1,7 @@
 #!/bin/bash
 set -x
 tempfile=$(mktemp)
-sourcefile="$(pwd)/src/main.rs"
+treefile=$(mktemp)
 seq_no_o=$(mktemp)
 seq_o=$(mktemp)
 seq_log=$(mktemp)
@@ -9,13 +9,21 @@ par_source=$(mktemp)
 par_log=$(mktemp)
 par_no_o=$(mktemp)
 par_o=$(mktemp)
-stdin_params="1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20"
 repetitions=1000
 error=0
 results_file=most_complex_results.csv
+source ./parallelise.sh
+
+# Shrinks the program to a minimal reproducer for bug reports
+reduce() {
+    reduced_file="$(pwd)/reduced-$(date +%s).rs"
+    echo "Reducing the program, see interesting.sh"
+    sequential-program-generator --reduce ${treefile} ./interesting.sh > ${reduced_file}
+    echo "Minimal reproducer saved to ${reduced_file}"
+}
 
 # Generate a sequential program
-sequential-program-generator $1 > ${tempfile}
+sequential-program-generator $1 --tree ${treefile} > ${tempfile}
 
 ######################
 # Sequential compile #
@@ -31,9 +39,7 @@ if [ $? -ne 0 ]; then
     error=1
 else
     start=`date +%s.%N`
-    for ((n=0;n<${repetitions};n++)); do
-        target/debug/playground ${stdin_params} >> ${seq_no_o}
-    done
+    run_program target/debug/playground ${repetitions} ${seq_no_o}
     end=`date +%s.%N`
     seq_no_o_runtime=$( echo "$end - $start" | bc -l )
 fi
@@ -47,17 +53,13 @@ if [ $? -ne 0 ]; then
     error=1
 else
     start=`date +%s.%N`
-    for ((n=0;n<${repetitions};n++)); do
-        target/release/playground ${stdin_params} >> ${seq_o}
-    done
+    run_program target/release/playground ${repetitions} ${seq_o}
     end=`date +%s.%N`
     seq_o_runtime=$( echo "$end - $start" | bc -l )
 fi
 
 # Check that optimisations have not changed the program
-sort ${seq_no_o} -o ${seq_no_o}
-sort ${seq_o} -o ${seq_o}
-diff ${seq_no_o} ${seq_o}
+same_output ${seq_no_o} ${seq_o}
 if [ $? -ne 0 ]; then
     echo "There was a difference between seq_no_o and seq_o"
     exit 1
@@ -66,33 +68,18 @@ fi
 ####################
 # Parallel compile #
 ####################
-echo "#![feature(plugin)]
-#![plugin(auto_parallelise)]
-#[autoparallelise]" > ${sourcefile}
-cat ${tempfile} >> ${sourcefile}
-
-# Running Stage 1
-rm .autoparallelise
-echo "+ cargo build" >> ${par_log}
-RUST_BACKTRACE=full cargo build 2>> ${par_log}
-
-# Running Stage 2
-echo "+ cargo build" >> ${par_log}
-RUST_BACKTRACE=full cargo build >> ${par_source} 2>> ${par_log}
+parallelise ${tempfile} ${par_source} ${par_log}
 if [ ! -s ${par_source} ]; then
     echo "Failed to generate parallel source code"
     echo "View log at ${par_log}"
+    reduce
     exit 1
 fi
 
 # Copy into playground
 cat ${par_source} > ${sourcefile}
 
-# Quick and Dirty fixes
-sed -i 's/stdin_receive) =/stdin_receive): (::std::sync::mpsc::Sender<(Vec<i32>,)>, ::std::sync::mpsc::Receiver<(Vec<i32>,)>) =/g' ${sourcefile}
-sed -i 's/stdin_receive_0) =/stdin_receive_0): (::std::sync::mpsc::Sender<(Vec<i32>,)>, ::std::sync::mpsc::Receiver<(Vec<i32>,)>) =/g' ${sourcefile}
-sed -i 's/stdin_receive_i) =/stdin_receive_i): (::std::sync::mpsc::Sender<(Vec<i32>,)>, ::std::sync::mpsc::Receiver<(Vec<i32>,)>) =/g' ${sourcefile}
-sed -i 's/stdin_receive_new) =/stdin_receive_new): (::std::sync::mpsc::Sender<(Vec<i32>,)>, ::std::sync::mpsc::Receiver<(Vec<i32>,)>) =/g' ${sourcefile}
+fix_parallel_source
 
 # Static Analysis of Parallelisation
 num_threads=$((1 + $(grep -o "std::thread::spawn" ${sourcefile} | wc -l)))
@@ -108,9 +95,7 @@ if [ $? -ne 0 ]; then
     error=1
 else
     start=`date +%s.%N`
-    for ((n=0;n<${repetitions};n++)); do
-        target/debug/playground ${stdin_params} >> ${par_no_o}
-    done
+    run_program target/debug/playground ${repetitions} ${par_no_o}
     end=`date +%s.%N`
     par_no_o_runtime=$( echo "$end - $start" | bc -l )
 fi
@@ -125,17 +110,13 @@ if [ $? -ne 0 ]; then
     error=1
 else
     start=`date +%s.%N`
-    for ((n=0;n<${repetitions};n++)); do
-        target/release/playground ${stdin_params} >> ${par_o}
-    done
+    run_program target/release/playground ${repetitions} ${par_o}
     end=`date +%s.%N`
     par_o_runtime=$( echo "$end - $start" | bc -l )
 fi
 
 # Check that optimisations have not changed the program
-sort ${par_no_o} -o ${par_no_o}
-sort ${par_o} -o ${par_o}
-diff ${par_no_o} ${par_o}
+same_output ${par_no_o} ${par_o}
 if [ $? -ne 0 ]; then
     echo "There was a difference between par_no_o and par_o"
     tail -n 100 ${par_log}
@@ -143,11 +124,12 @@ if [ $? -ne 0 ]; then
 fi
 
 # Check that parallelisation has not changed the program
-if [ -z ${error} ]; then
-    diff ${seq_o} ${par_o}
+if [ ${error} -eq 0 ]; then
+    same_output ${seq_o} ${par_o}
     if [ $? -ne 0 ]; then
         echo "There was a difference between seq_o and par_o"
         tail -n 100 ${par_log}
+        reduce
         exit 1
     fi
 fi
@@ -155,6 +137,6 @@ fi
 echo "${1},${seq_no_o_runtime},${seq_o_runtime},${par_no_o_runtime},${par_o_runtime},${num_threads},${num_channels},${num_fors}" >> ${results_file}
 
 # Remove tempfiles
-rm ${tempfile} ${seq_no_o} ${seq_o} ${seq_log} ${par_source} ${par_no_o} ${par_o} ${par_log}
+rm ${tempfile} ${treefile} ${seq_no_o} ${seq_o} ${seq_log} ${par_source} ${par_no_o} ${par_o} ${par_log}
 
 exit ${error}
@@ -0,0 +1,34 @@
+#!/bin/bash
+# Test for sequential-program-generator --reduce, run from the playground directory.
+# Exits with 0 while the program in $1 still shows a bug: it works sequentially, but the plugin
+# panics, the parallel program does not compile, or its output differs from the sequential output.
+# The program is built and compared the same way as generated-test.sh, see parallelise.sh
+source ./parallelise.sh
+seq_o=$(mktemp)
+par_source=$(mktemp)
+par_log=$(mktemp)
+par_o=$(mktemp)
+repetitions=20
+
+finish() {
+    rm ${seq_o} ${par_source} ${par_log} ${par_o}
+    exit $1
+}
+
+# Sequential compile, a program which does not work sequentially is not interesting
+cat $1 > ${sourcefile}
+cargo build --release 2> /dev/null || finish 1
+run_program target/release/playground ${repetitions} ${seq_o} || finish 1
+
+# Parallel compile
+parallelise $1 ${par_source} ${par_log}
+grep -q "panicked at" ${par_log} && finish 0
+[ -s ${par_source} ] || finish 1
+cat ${par_source} > ${sourcefile}
+fix_parallel_source
+
+cargo build --release 2>> ${par_log} || finish 0
+run_program target/release/playground ${repetitions} ${par_o}
+
+same_output ${seq_o} ${par_o} && finish 1
+finish 0
@@ -0,0 +1,41 @@
+#!/bin/bash
+# Shared by generated-test.sh and interesting.sh, source it from the playground directory.
+sourcefile="$(pwd)/src/main.rs"
+stdin_params="1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20"
+
+# Runs both stages of the plugin on the program in $1, writing the parallel source to $2 and the log to $3
+parallelise() {
+    echo "#![feature(plugin)]
+#![plugin(auto_parallelise)]
+#[autoparallelise]" > ${sourcefile}
+    cat $1 >> ${sourcefile}
+
+    # Running Stage 1
+    rm -f .autoparallelise/*/state.json
+    echo "+ cargo build" >> $3
+    RUST_BACKTRACE=full cargo build 2>> $3
+
+    # Running Stage 2
+    echo "+ cargo build" >> $3
+    RUST_BACKTRACE=full cargo build >> $2 2>> $3
+}
+
+# Quick and Dirty fixes, the plugin cannot infer the types of the stdin channels
+fix_parallel_source() {
+    sed -i 's/stdin_receive) =/stdin_receive): (::std::sync::mpsc::Sender<(Vec<i32>,)>, ::std::sync::mpsc::Receiver<(Vec<i32>,)>) =/g' ${sourcefile}
+    sed -i 's/stdin_receive_0) =/stdin_receive_0): (::std::sync::mpsc::Sender<(Vec<i32>,)>, ::std::sync::mpsc::Receiver<(Vec<i32>,)>) =/g' ${sourcefile}
+    sed -i 's/stdin_receive_i) =/stdin_receive_i): (::std::sync::mpsc::Sender<(Vec<i32>,)>, ::std::sync::mpsc::Receiver<(Vec<i32>,)>) =/g' ${sourcefile}
+    sed -i 's/stdin_receive_new) =/stdin_receive_new): (::std::sync::mpsc::Sender<(Vec<i32>,)>, ::std::sync::mpsc::Receiver<(Vec<i32>,)>) =/g' ${sourcefile}
+}
+
+# Runs the binary $1 $2 times, appending its output to $3. Fails if any run fails.
+run_program() {
+    for ((n=0;n<$2;n++)); do
+        $1 ${stdin_params} >> $3 || return 1
+    done
+}
+
+# The parallel program must print the same lines in the same order as the sequential program
+same_output() {
+    diff -q $1 $2 > /dev/null
+}
@@ -1,3 +1,356 @@
+#![feature(dyn_trait)]
+
+extern crate rand;
+#[macro_use] extern crate lazy_static;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+
+use rand::{Rng, thread_rng};
+
+pub type Block = Vec<StmtKind>;
+fn block_to_string(block: &Block, printid: &mut u32) -> String {
+    let mut output = "{\n".to_owned();
+    for stmt in block {
+        output.push_str(&stmt.to_string(printid));
+        output.push_str("\n");
+    }
+    output.push_str("}");
+    output
+}
+
+lazy_static! {
+    static ref ALPHABET: Vec<&'static str> = "a b c d e f g h i j k l m n o p q r s t u v w x y z".split_whitespace().collect();
+
+    static ref STMT_KIND_LIST: Vec<&'static str> = vec!["NewVar", "Assignment", "Push", "Pop", "Print", "ForRange", "ForList", "IfLt"]; // "ForRange", "ForList", "IfLt"
+
+    static ref EXPR_KIND_LIST: Vec<&'static str> = vec!["Value", "StdinValue", "Var", "Add", "Mul", "Sub"];
+}
+
+static EXPR_HEIGHT: u32 = 3;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Variable {
+    Int(String),
+    List(String),
+}
+
+impl Variable {
+    fn to_string(&self) -> String {
+        match self {
+            &Variable::Int(ref var) |
+            &Variable::List(ref var) => var.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ExprKind{
+    Value(i32),
+    StdinValue(usize),
+    Var(Variable),
+    Add(Box<ExprKind>, Box<ExprKind>),
+    Mul(Box<ExprKind>, Box<ExprKind>),
+    Sub(Box<ExprKind>, Box<ExprKind>),
+}
+
+impl ExprKind {
+    fn generate<'a>(height: u32, env: &Vec<Variable>) -> Box<Self> {
+        let mut rng = thread_rng();
+        if height == 0 {
+            return Box::new(ExprKind::Value(rng.gen_range(-10, 10)))
+        }
+        loop {
+            let exprid = rng.choose(&EXPR_KIND_LIST).unwrap();
+            match exprid {
+                &"Value" => return Box::new(ExprKind::Value(rng.gen_range(-10, 10))),
+                &"StdinValue" => return Box::new(ExprKind::StdinValue(rng.gen_range(0, 20))),
+                &"Add" => {
+                    let a = ExprKind::generate(height-1, env);
+                    let b = ExprKind::generate(height-1, env);
+                    return Box::new(ExprKind::Add(a, b));
+                }
+                &"Mul" => {
+                    let a = ExprKind::generate(height-1, env);
+                    let b = ExprKind::generate(height-1, env);
+                    return Box::new(ExprKind::Mul(a, b));
+                }
+                &"Sub" => {
+                    let a = ExprKind::generate(height-1, env);
+                    let b = ExprKind::generate(height-1, env);
+                    return Box::new(ExprKind::Sub(a, b));
+                }
+                &"Var" => {
+                    if let Some(var) = rng.choose(&env) {
+                        match var {
+                            &Variable::Int(_) => return Box::new(ExprKind::Var(var.clone())),
+                            &Variable::List(_) => {},
+                        }
+                    }
+                }
+                &&_ => panic!("Not found in EXPR_KIND_LIST: {}", exprid),
+            }
+        }
+    }
+
+    fn to_string(&self) -> String {
+        match self {
+            &ExprKind::Value(val) => format!("{}", val),
+            &ExprKind::StdinValue(id) => format!("stdin[{}]", id),
+            &ExprKind::Var(ref var) => match var {
+                &Variable::Int(ref varname) => varname.clone(),
+                &Variable::List(ref varname) => format!("{}.pop().unwrap_or(0)", varname),
+            },
+            &ExprKind::Add(ref a, ref b) => format!("({}) + ({})", a.to_string(), b.to_string()),
+            &ExprKind::Mul(ref a, ref b) => format!("({}) * ({})", a.to_string(), b.to_string()),
+            &ExprKind::Sub(ref a, ref b) => format!("({}) - ({})", a.to_string(), b.to_string()),
+        }
+    }
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum StmtKind {
+    NewVar(Variable),
+    Assignment(Variable, Box<ExprKind>),
+    ForRange(Variable, Box<ExprKind>, Box<ExprKind>, Block),
+    ForList(Variable, Variable, Block),
+    IfLt(Box<ExprKind>, Box<ExprKind>, Block),
+
+    Push(Variable, Box<ExprKind>),
+    Pop(Variable),
+
+    Print(Box<ExprKind>),
+}
+
+impl StmtKind {
+    pub fn to_string(&self, printid: &mut u32) -> String {
+        match self {
+            &StmtKind::NewVar(ref var) => match var {
+                &Variable::Int(ref varname) => format!("let mut {}: i32 = 0;", varname),
+                &Variable::List(ref varname) => format!("let mut {}: Vec<i32> = vec![0];", varname),
+            },
+
+            &StmtKind::Assignment(ref var, ref value) =>
+            format!("{} = {};", var.to_string(), value.to_string()),
+
+            &StmtKind::ForRange(ref var, ref from, ref to, ref inner_block) =>
+            format!("for mut {} in 0.max({})..100.min({}) {{\n{}\n}}", var.to_string(), from.to_string(), to.to_string(), block_to_string(inner_block, printid)),
+
+            &StmtKind::ForList(ref var, ref list, ref inner_block) =>
+            format!("for mut {} in {}.clone() {{\n{}\n}}", var.to_string(), list.to_string(), block_to_string(inner_block, printid)),
+
+            &StmtKind::IfLt(ref a, ref b, ref inner_block) =>
+            format!("if ({}) < ({}) {}", a.to_string(), b.to_string(), block_to_string(inner_block, printid)),
+
+            &StmtKind::Push(ref var, ref value) =>
+            format!("{}.push({});", var.to_string(), value.to_string()),
+
+            &StmtKind::Pop(ref var) =>
+            format!("{}.pop();", var.to_string()),
+
+            &StmtKind::Print(ref val) => {
+                let val_str = val.to_string();
+                *printid += 1;
+                format!("println!(\"{:08}: {} = {{:?}}\", {});", *printid - 1, val_str, val_str)
+            },
+        }
+    }
+}
+
+fn gen_var(avoid_env: &Vec<String>) -> String {
+    let mut rng = thread_rng();
+    loop {
+        let varname = rng.choose(&ALPHABET).unwrap().to_string();
+        if !avoid_env.contains(&varname) {
+            return varname;
+        }
+    }
+}
+
+pub fn generate_block(num_statements: usize, external_env: &Vec<Variable>, avoid_env: &Vec<String>) -> Block {
+    let mut env: Vec<Variable> = external_env.clone();
+    let mut program: Block = vec![];
+    let mut rng = thread_rng();
+    while program.len() < num_statements {
+        match rng.choose(&STMT_KIND_LIST).unwrap() {
+            &"NewVar" => {
+                let varname = gen_var(avoid_env);
+                let var = if true { //rng.gen() {
+                    Variable::Int(varname.clone())
+                } else {
+                    Variable::List(varname.clone())
+                };
+                env.retain(|v| v.to_string() != varname);
+                env.push(var.clone());
+                program.push(StmtKind::NewVar(var));
+            },
+            &"Assignment" => {
+                let var = rng.choose(&env);
+                if let Some(&Variable::Int(_)) = var {
+                    program.push(StmtKind::Assignment(var.unwrap().clone(), ExprKind::generate(EXPR_HEIGHT, &env)));
+                }
+            },
+            &"ForRange" => {
+                let varname = gen_var(avoid_env);
+                let iter_var = Variable::Int(varname.clone());
+                let mut avoid_env = avoid_env.clone();
+                avoid_env.push(varname.clone());
+                let from = ExprKind::generate(EXPR_HEIGHT, &env);
+                let to = ExprKind::generate(EXPR_HEIGHT, &env);
+                let inner_block = generate_block(num_statements / 2, &env, &avoid_env);
+                program.push(StmtKind::ForRange(iter_var, from, to, inner_block));
+            },
+            &"ForList" => {
+                let varname = gen_var(avoid_env);
+                let mut avoid_env = avoid_env.clone();
+                avoid_env.push(varname.clone());
+                let iter_var = Variable::Int(varname.clone());
+                let list_var = rng.choose(&env);
+                if let Some(&Variable::List(_)) = list_var {
+                    let inner_block = generate_block(num_statements / 2, &env, &avoid_env);
+                    program.push(StmtKind::ForList(iter_var, list_var.unwrap().clone(), inner_block));
+                }
+            },
+            &"IfLt" => {
+                let a = ExprKind::generate(EXPR_HEIGHT, &env);
+                let b = ExprKind::generate(EXPR_HEIGHT, &env);
+                let inner_block = generate_block(num_statements / 2, &env, avoid_env);
+                program.push(StmtKind::IfLt(a, b, inner_block));
+            },
+            &"Push" => {
+                let list_var = rng.choose(&env);
+                if let Some(&Variable::List(_)) = list_var {
+                    let val = ExprKind::generate(EXPR_HEIGHT, &env);
+                    program.push(StmtKind::Push(list_var.unwrap().clone(), val));
+                }
+            },
+            &"Pop" => {
+                let list_var = rng.choose(&env);
+                if let Some(&Variable::List(_)) = list_var {
+                    program.push(StmtKind::Pop(list_var.unwrap().clone()));
+                }
+            },
+            &"Print" => {
+                let val = ExprKind::generate(EXPR_HEIGHT, &env);
+                program.push(StmtKind::Print(val));
+            },
+            &&_ => panic!("Not found in STMT_KIND_LIST"),
+        }
+    }
+    for var in env {
+        program.push(StmtKind::Print(Box::new(ExprKind::Var(var))));
+    }
+    program
+}
+
+pub fn program_to_string(program: &Block) -> String {
+    let mut printid = 0;
+    let inner_block = block_to_string(program, &mut printid);
+    format!("
+fn main() {{
+let stdin: Vec<i32> = ::std::env::args().skip(1).map(|i| i.parse::<i32>().unwrap()).collect();
+{}
+}}", inner_block)
+}
+
+// Inner blocks of a statement, in the order they are printed
+impl StmtKind {
+    fn blocks(&self) -> Vec<&Block> {
+        match self {
+            &StmtKind::ForRange(_, _, _, ref inner_block) |
+            &StmtKind::ForList(_, _, ref inner_block) |
+            &StmtKind::IfLt(_, _, ref inner_block) => vec![inner_block],
+            _ => vec![],
+        }
+    }
+
+    fn blocks_mut(&mut self) -> Vec<&mut Block> {
+        match self {
+            &mut StmtKind::ForRange(_, _, _, ref mut inner_block) |
+            &mut StmtKind::ForList(_, _, ref mut inner_block) |
+            &mut StmtKind::IfLt(_, _, ref mut inner_block) => vec![inner_block],
+            _ => vec![],
+        }
+    }
+}
+
+// A block is found by the index of each enclosing statement and which of its blocks it is
+type BlockPath = Vec<(usize, usize)>;
+
+fn block_at<'a>(block: &'a Block, path: &[(usize, usize)]) -> &'a Block {
+    match path.split_first() {
+        Some((&(stmtid, blockid), rest)) => block_at(block[stmtid].blocks()[blockid], rest),
+        None => block,
+    }
+}
+
+fn block_at_mut<'a>(block: &'a mut Block, path: &[(usize, usize)]) -> &'a mut Block {
+    match path.split_first() {
+        Some((&(stmtid, blockid), rest)) => {
+            let inner_block = block[stmtid].blocks_mut().remove(blockid);
+            block_at_mut(inner_block, rest)
+        },
+        None => block,
+    }
+}
+
+// Hierarchical delta debugging: the statements of the outermost block are reduced first, then the
+// blocks of the statements that are left. Repeated until nothing more can be removed.
+pub fn reduce(program: Block, test: &mut dyn FnMut(&Block) -> bool) -> Block {
+    let mut program = program;
+    loop {
+        let before = program_to_string(&program);
+        let mut paths: Vec<BlockPath> = vec![vec![]];
+        while let Some(path) = paths.pop() {
+            program = ddmin(program, &path, test);
+            for (stmtid, stmt) in block_at(&program, &path).iter().enumerate() {
+                for blockid in 0..stmt.blocks().len() {
+                    let mut inner_path = path.clone();
+                    inner_path.push((stmtid, blockid));
+                    paths.push(inner_path);
+                }
+            }
+        }
+        if program_to_string(&program) == before {
+            return program;
+        }
+    }
+}
+
+// Removes chunks of statements from the block at path while the test still fails, halving the
+// chunk size whenever no chunk can be removed (Zeller and Hildebrandt's ddmin, using complements)
+fn ddmin(program: Block, path: &[(usize, usize)], test: &mut dyn FnMut(&Block) -> bool) -> Block {
+    let mut program = program;
+    let mut granularity = 2;
+    loop {
+        let len = block_at(&program, path).len();
+        if len == 0 {
+            return program;
+        }
+        let granularity_used = granularity.min(len);
+        let chunk_size = (len + granularity_used - 1) / granularity_used;
+        let mut reduced = false;
+        let mut start = 0;
+        while start < len {
+            let mut candidate = program.clone();
+            block_at_mut(&mut candidate, path).drain(start..(start + chunk_size).min(len));
+            if test(&candidate) {
+                program = candidate;
+                granularity = (granularity_used - 1).max(2);
+                reduced = true;
+                break;
+            }
+            start += chunk_size;
+        }
+        if !reduced {
+            if chunk_size == 1 {
+                return program;
+            }
+            granularity = (granularity_used * 2).min(len);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
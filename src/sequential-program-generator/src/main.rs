@@ -1,256 +1,86 @@
-extern crate rand;
-#[macro_use] extern crate lazy_static;
-
-use rand::{Rng, thread_rng};
-
-type Block = Vec<StmtKind>;
-fn block_to_string(block: &Block, printid: &mut u32) -> String {
-    let mut output = "{\n".to_owned();
-    for stmt in block {
-        output.push_str(&stmt.to_string(printid));
-        output.push_str("\n");
+extern crate sequential_program_generator;
+extern crate serde_json;
+
+use sequential_program_generator::{Block, generate_block, program_to_string, reduce};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::process::Command;
+
+fn read_tree(path: &str) -> Block {
+    let mut json = String::new();
+    match File::open(path) {
+        Ok(mut file) => file.read_to_string(&mut json).expect(&format!("Unable to read {}", path)),
+        Err(why) => panic!("Unable to open {}: {}", path, why),
+    };
+    match serde_json::from_str(&json) {
+        Ok(program) => program,
+        Err(why) => panic!("Unable to parse {}: {}", path, why),
     }
-    output.push_str("}");
-    output
-}
-
-lazy_static! {
-    static ref ALPHABET: Vec<&'static str> = "a b c d e f g h i j k l m n o p q r s t u v w x y z".split_whitespace().collect();
-
-    static ref STMT_KIND_LIST: Vec<&'static str> = vec!["NewVar", "Assignment", "Push", "Pop", "Print", "ForRange", "ForList", "IfLtElse"]; // "ForRange", "ForList", "IfLtElse"
-
-    static ref EXPR_KIND_LIST: Vec<&'static str> = vec!["Value", "StdinValue", "Var", "Add", "Mul", "Sub"];
 }
 
-static EXPR_HEIGHT: u32 = 3;
-
-#[derive(Clone)]
-enum Variable {
-    Int(String),
-    List(String),
-}
-
-impl Variable {
-    fn to_string(&self) -> String {
-        match self {
-            &Variable::Int(ref var) |
-            &Variable::List(ref var) => var.clone(),
-        }
+fn write_file(path: &str, contents: &str) {
+    match File::create(path) {
+        Ok(mut file) => file.write_all(contents.as_bytes()).expect(&format!("Unable to write {}", path)),
+        Err(why) => panic!("Unable to create {}: {}", path, why),
     }
 }
 
-enum ExprKind{
-    Value(i32),
-    StdinValue(usize),
-    Var(Variable),
-    Add(Box<ExprKind>, Box<ExprKind>),
-    Mul(Box<ExprKind>, Box<ExprKind>),
-    Sub(Box<ExprKind>, Box<ExprKind>),
-}
-
-impl ExprKind {
-    fn generate<'a>(height: u32, env: &Vec<Variable>) -> Box<Self> {
-        let mut rng = thread_rng();
-        if height == 0 {
-            return Box::new(ExprKind::Value(rng.gen_range(-10, 10)))
-        }
-        loop {
-            let exprid = rng.choose(&EXPR_KIND_LIST).unwrap();
-            match exprid {
-                &"Value" => return Box::new(ExprKind::Value(rng.gen_range(-10, 10))),
-                &"StdinValue" => return Box::new(ExprKind::StdinValue(rng.gen_range(0, 20))),
-                &"Add" => {
-                    let a = ExprKind::generate(height-1, env);
-                    let b = ExprKind::generate(height-1, env);
-                    return Box::new(ExprKind::Add(a, b));
-                }
-                &"Mul" => {
-                    let a = ExprKind::generate(height-1, env);
-                    let b = ExprKind::generate(height-1, env);
-                    return Box::new(ExprKind::Mul(a, b));
-                }
-                &"Sub" => {
-                    let a = ExprKind::generate(height-1, env);
-                    let b = ExprKind::generate(height-1, env);
-                    return Box::new(ExprKind::Sub(a, b));
-                }
-                &"Var" => {
-                    if let Some(var) = rng.choose(&env) {
-                        match var {
-                            &Variable::Int(_) => return Box::new(ExprKind::Var(var.clone())),
-                            &Variable::List(_) => {},
-                        }
-                    }
-                }
-                &&_ => panic!("Not found in EXPR_KIND_LIST: {}", exprid),
-            }
-        }
+// sequential-program-generator --reduce TREE_FILE TEST_COMMAND [ARGS...]
+// The test command is given a file containing the program, and exits with 0 while the program
+// still shows the bug. The smallest program found is printed.
+fn reduce_main(args: &[String]) {
+    if args.len() < 2 {
+        panic!("Usage: sequential-program-generator --reduce TREE_FILE TEST_COMMAND [ARGS...]");
     }
-
-    fn to_string(&self) -> String {
-        match self {
-            &ExprKind::Value(val) => format!("{}", val),
-            &ExprKind::StdinValue(id) => format!("stdin[{}]", id),
-            &ExprKind::Var(ref var) => match var {
-                &Variable::Int(ref varname) => varname.clone(),
-                &Variable::List(ref varname) => format!("{}.pop().unwrap_or(0)", varname),
-            },
-            &ExprKind::Add(ref a, ref b) => format!("({}) + ({})", a.to_string(), b.to_string()),
-            &ExprKind::Mul(ref a, ref b) => format!("({}) * ({})", a.to_string(), b.to_string()),
-            &ExprKind::Sub(ref a, ref b) => format!("({}) - ({})", a.to_string(), b.to_string()),
-            _ => unimplemented!(),
+    let program = read_tree(&args[0]);
+    let program_path = format!("{}.reduce.rs", args[0]);
+    let mut results: HashMap<String, bool> = HashMap::new();
+    let mut test = |candidate: &Block| {
+        let source_code = program_to_string(candidate);
+        if let Some(&result) = results.get(&source_code) {
+            return result;
         }
+        write_file(&program_path, &source_code);
+        let result = match Command::new(&args[1]).args(&args[2..]).arg(&program_path).status() {
+            Ok(status) => status.success(),
+            Err(why) => panic!("Unable to run {}: {}", args[1], why),
+        };
+        eprintln!("Tried a program of {} lines: {}", source_code.lines().count(), if result { "still fails" } else { "passes" });
+        results.insert(source_code, result);
+        result
+    };
+    if !test(&program) {
+        panic!("The program in {} does not fail the test", args[0]);
     }
+    let reduced = reduce(program, &mut test);
+    let _ = ::std::fs::remove_file(&program_path);
+    println!("{}", program_to_string(&reduced));
 }
 
-
-
-enum StmtKind {
-    NewVar(Variable),
-    Assignment(Variable, Box<ExprKind>),
-    ForRange(Variable, Box<ExprKind>, Box<ExprKind>, Block),
-    ForList(Variable, Variable, Block),
-    IfLtElse(Box<ExprKind>, Box<ExprKind>, Block, Block),
-
-    Push(Variable, Box<ExprKind>),
-    Pop(Variable),
-
-    Print(Box<ExprKind>),
-}
-
-impl StmtKind {
-    pub fn to_string(&self, printid: &mut u32) -> String {
-        match self {
-            &StmtKind::NewVar(ref var) => match var {
-                &Variable::Int(ref varname) => format!("let mut {}: i32 = 0;", varname),
-                &Variable::List(ref varname) => format!("let mut {}: Vec<i32> = vec![0];", varname),
-            },
-
-            &StmtKind::Assignment(ref var, ref value) =>
-            format!("{} = {};", var.to_string(), value.to_string()),
-
-            &StmtKind::ForRange(ref var, ref from, ref to, ref inner_block) =>
-            format!("for mut {} in 0.max({})..100.min({}) {{\n{}\n}}", var.to_string(), from.to_string(), to.to_string(), block_to_string(inner_block, printid)),
-
-            &StmtKind::ForList(ref var, ref list, ref inner_block) =>
-            format!("for mut {} in {}.clone() {{\n{}\n}}", var.to_string(), list.to_string(), block_to_string(inner_block, printid)),
-
-            &StmtKind::IfLtElse(ref a, ref b, ref true_block, ref false_block) =>
-            //format!("if ({}) < ({}) {} else {}", a.to_string(), b.to_string(),
-            format!("if ({}) < ({}) {}", a.to_string(), b.to_string(), block_to_string(true_block, printid)),
-            //block_to_string(false_block)),
-
-            &StmtKind::Push(ref var, ref value) =>
-            format!("{}.push({});", var.to_string(), value.to_string()),
-
-            &StmtKind::Pop(ref var) =>
-            format!("{}.pop();", var.to_string()),
-
-            &StmtKind::Print(ref val) => {
-                let val_str = val.to_string();
-                *printid += 1;
-                format!("println!(\"{:08}: {} = {{:?}}\", {});", *printid - 1, val_str, val_str)
-            },
+// sequential-program-generator NUM_STATEMENTS [--tree TREE_FILE]
+// The tree file is used by --reduce to make a smaller version of the program.
+fn generate_main(args: &[String]) {
+    let num_statements = match args.get(0).map(|arg| arg.parse()) {
+        Some(Ok(num_statements)) => num_statements,
+        _ => panic!("Usage: sequential-program-generator NUM_STATEMENTS [--tree TREE_FILE]"),
+    };
+    let program = generate_block(num_statements, &vec![], &vec![]);
+    if args.get(1).map(|arg| &**arg) == Some("--tree") {
+        let tree_path = args.get(2).expect("Missing TREE_FILE after --tree");
+        match serde_json::to_string(&program) {
+            Ok(json) => write_file(tree_path, &json),
+            Err(why) => panic!("Unable to convert program to JSON: {}", why),
         }
     }
-}
-
-fn gen_var(avoid_env: &Vec<String>) -> String {
-    let mut rng = thread_rng();
-    loop {
-        let varname = rng.choose(&ALPHABET).unwrap().to_string();
-        if !avoid_env.contains(&varname) {
-            return varname;
-        }
-    }
-}
-
-fn generate_block(num_statements: usize, external_env: &Vec<Variable>, avoid_env: &Vec<String>) -> Block {
-    let mut env: Vec<Variable> = external_env.clone();
-    let mut program: Block = vec![];
-    let mut rng = thread_rng();
-    while program.len() < num_statements {
-        match rng.choose(&STMT_KIND_LIST).unwrap() {
-            &"NewVar" => {
-                let varname = gen_var(avoid_env);
-                let var = if true { //rng.gen() {
-                    Variable::Int(varname.clone())
-                } else {
-                    Variable::List(varname.clone())
-                };
-                env.retain(|v| v.to_string() != varname);
-                env.push(var.clone());
-                program.push(StmtKind::NewVar(var));
-            },
-            &"Assignment" => {
-                let var = rng.choose(&env);
-                if let Some(&Variable::Int(_)) = var {
-                    program.push(StmtKind::Assignment(var.unwrap().clone(), ExprKind::generate(EXPR_HEIGHT, &env)));
-                }
-            },
-            &"ForRange" => {
-                let varname = gen_var(avoid_env);
-                let iter_var = Variable::Int(varname.clone());
-                let mut avoid_env = avoid_env.clone();
-                avoid_env.push(varname.clone());
-                let from = ExprKind::generate(EXPR_HEIGHT, &env);
-                let to = ExprKind::generate(EXPR_HEIGHT, &env);
-                let inner_block = generate_block(num_statements / 2, &env, &avoid_env);
-                program.push(StmtKind::ForRange(iter_var, from, to, inner_block));
-            },
-            &"ForList" => {
-                let varname = gen_var(avoid_env);
-                let mut avoid_env = avoid_env.clone();
-                avoid_env.push(varname.clone());
-                let iter_var = Variable::Int(varname.clone());
-                let list_var = rng.choose(&env);
-                if let Some(&Variable::List(_)) = list_var {
-                    let inner_block = generate_block(num_statements / 2, &env, &avoid_env);
-                    program.push(StmtKind::ForList(iter_var, list_var.unwrap().clone(), inner_block));
-                }
-            },
-            &"IfLtElse" => {
-                let a = ExprKind::generate(EXPR_HEIGHT, &env);
-                let b = ExprKind::generate(EXPR_HEIGHT, &env);
-                let block_a = generate_block(num_statements / 2, &env, avoid_env);
-                let block_b = generate_block(num_statements / 2, &env, avoid_env);
-                program.push(StmtKind::IfLtElse(a, b, block_a, block_b));
-            },
-            &"Push" => {
-                let list_var = rng.choose(&env);
-                if let Some(&Variable::List(_)) = list_var {
-                    let val = ExprKind::generate(EXPR_HEIGHT, &env);
-                    program.push(StmtKind::Push(list_var.unwrap().clone(), val));
-                }
-            },
-            &"Pop" => {
-                let list_var = rng.choose(&env);
-                if let Some(&Variable::List(_)) = list_var {
-                    program.push(StmtKind::Pop(list_var.unwrap().clone()));
-                }
-            },
-            &"Print" => {
-                let val = ExprKind::generate(EXPR_HEIGHT, &env);
-                program.push(StmtKind::Print(val));
-            },
-            &&_ => panic!("Not found in STMT_KIND_LIST"),
-        }
-    }
-    for var in env {
-        program.push(StmtKind::Print(Box::new(ExprKind::Var(var))));
-    }
-    program
+    println!("{}", program_to_string(&program));
 }
 
 fn main() {
-    let num_statements = ::std::env::args().last().unwrap().parse().unwrap();
-    let mut printid = 0;
-    let inner_block = block_to_string(&generate_block(num_statements, &vec![], &vec![]), &mut printid);
-    let source_code = format!("
-fn main() {{
-let stdin: Vec<i32> = ::std::env::args().skip(1).map(|i| i.parse::<i32>().unwrap()).collect();
-{}
-}}", inner_block);
-
-    println!("{}", source_code);
+    let args: Vec<String> = ::std::env::args().skip(1).collect();
+    if args.get(0).map(|arg| &**arg) == Some("--reduce") {
+        reduce_main(&args[1..]);
+    } else {
+        generate_main(&args);
+    }
 }